@@ -3,6 +3,7 @@
 layout(location = 0) in vec3 inPos;
 
 layout(location = 1) in vec3 inOffset;
+layout(location = 2) in vec4 inColor;
 
 layout(set = 0, binding = 0) uniform LightUBO {
     vec4 pos;
@@ -10,4 +11,11 @@ layout(set = 0, binding = 0) uniform LightUBO {
     mat4 projection;
 } light;
 
-void main() { gl_Position = light.projection * vec4(inPos + inOffset, 1.0); }
+void main() {
+    // Unused slots of the instance buffer collapse to a point which isn't rasterized
+    if (inColor.a == 0.0) {
+        gl_Position = vec4(0.0);
+        return;
+    }
+    gl_Position = light.projection * vec4(inPos + inOffset, 1.0);
+}
@@ -6,6 +6,7 @@ layout(location = 2) in vec3 fragViewVec;
 layout(location = 3) in vec3 fragLightVec;
 layout(location = 4) in vec4 vertPos;
 layout(location = 5) in mat4 fragLightProj;
+layout(location = 9) in vec4 fragMaterial;
 
 
 layout(location = 0) out vec4 outColor;
@@ -65,17 +66,27 @@ float ShadowCalculationPcf(vec4 fragPosLightSpace)
 }
 
 void main() {
+  float emissive = fragMaterial.x;
+  float metallic = fragMaterial.y;
+  float roughness = fragMaterial.z;
+  float transparency = fragMaterial.w;
+
   vec3 N = normalize(fragNormal);
   vec3 L = normalize(fragLightVec);
-  vec3 V = normalize(fragViewVec.xyz);
-  vec3 R = reflect(L, N);
+  vec3 V = normalize(fragViewVec);
+  vec3 H = normalize(L + V);
 
-  vec3 ambient = fragColor.xyz * 0.2;
-  vec3 diffuse = fragColor.xyz * max(dot(N, L), 0.0);
-  //vec3 specular = pow(max(dot(R, V), 0.0), 16.0) * vec3(1.35);
+  vec3 ambient = fragColor.rgb * 0.2;
+  // Metals have no diffuse light, and their highlights have their own color
+  vec3 diffuse = fragColor.rgb * (1.0 - metallic) * max(dot(N, L), 0.0);
+  float shininess = mix(256.0, 2.0, roughness);
+  vec3 specularColor = mix(vec3(0.04), fragColor.rgb, metallic);
+  vec3 specular = specularColor * (1.0 - roughness) * pow(max(dot(N, H), 0.0), shininess);
   vec4 FragPosLightSpace = fragLightProj * vertPos;
   float shadow = ShadowCalculationPcf(FragPosLightSpace);
-  vec3 lighting = (ambient + (1.0 - shadow) * (diffuse /*+ specular*/));
+  // The emitted light doesn't depend on the light source, so it is not shadowed
+  vec3 emission = fragColor.rgb * emissive;
+  vec3 lighting = ambient + (1.0 - shadow) * (diffuse + specular) + emission;
 
-  outColor = vec4(lighting, fragColor.z);
+  outColor = vec4(lighting, fragColor.a * (1.0 - transparency));
 }
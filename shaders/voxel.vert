@@ -5,6 +5,8 @@ layout(location = 1) in vec3 inNormal;
 
 layout(location = 2) in vec3 inOffset;
 layout(location = 3) in vec4 inColor;
+// Emissive, metallic, roughness and transparency
+layout(location = 4) in vec4 inMaterial;
 
 layout(location = 0) out vec4 fragColor;
 layout(location = 1) out vec3 fragNormal;
@@ -12,6 +14,7 @@ layout(location = 2) out vec3 fragViewVec;
 layout(location = 3) out vec3 fragLightVec;
 layout(location = 4) out vec4 vertPos;
 layout(location = 5) out mat4 fragLightProj;
+layout(location = 9) out vec4 fragMaterial;
 
 layout(set = 0, binding = 0) uniform UBO {
     mat4 model;
@@ -26,14 +29,22 @@ layout(set = 0, binding = 1) uniform LightUBO {
 } light;
 
 void main() {
+    // Unused slots of the instance buffer collapse to a point which isn't rasterized
+    if (inColor.a == 0.0) {
+        gl_Position = vec4(0.0);
+        return;
+    }
     vec4 pos = vec4(inPos + inOffset, 1.0);
     gl_Position = ubo.projection * ubo.view * ubo.model * pos;
     vec4 worldPos = ubo.model * pos;
 
     fragColor = inColor;
     fragNormal = mat3(ubo.model) * inNormal;
-    fragViewVec = (ubo.view * worldPos).xyz;
+    // The view matrix only rotates and translates, so the camera is at its inverse translation
+    vec3 cameraPos = -transpose(mat3(ubo.view)) * ubo.view[3].xyz;
+    fragViewVec = cameraPos - worldPos.xyz;
     fragLightVec = light.direction.xyz;
     vertPos = pos;
     fragLightProj = light.projection;
+    fragMaterial = inMaterial;
 }
@@ -2,6 +2,16 @@ use camera_controllers::{CameraPerspective, Keys, OrbitZoomCamera, OrbitZoomCame
 use cgmath::Transform;
 use winit::event;
 
+/// The part of the camera setting which is stored in project files
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraState {
+    pub target: [f32; 3],
+    pub rotation: (f32, [f32; 3]),
+    pub distance: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
 pub struct CameraWrapper {
     /// Orbiting camera implementation
     camera: OrbitZoomCamera<f32>,
@@ -23,18 +33,19 @@ pub struct CameraWrapper {
 }
 
 impl CameraWrapper {
-    pub fn new(aspect_ratio: f32, meshes: f32) -> Self {
+    pub fn new(aspect_ratio: f32, dimensions: [f32; 3]) -> Self {
+        let size = Self::size(dimensions);
         let mut camera = OrbitZoomCamera::new(
-            [0.5 * meshes, 0.5 * meshes, 0.5 * meshes],
-            OrbitZoomCameraSettings::default().zoom_speed(0.4 * meshes),
+            [0.0; 3],
+            OrbitZoomCameraSettings::default().zoom_speed(0.4 * size),
         );
-        camera.distance = 2.0 * meshes;
+        Self::center(&mut camera, dimensions);
         CameraWrapper {
             camera,
             cam_persp: CameraPerspective {
                 fov: 45.0f32,
                 near_clip: 0.1,
-                far_clip: 10.0 * meshes,
+                far_clip: 10.0 * size,
                 aspect_ratio,
             },
             orbit_button: event::MouseButton::Right,
@@ -44,6 +55,28 @@ impl CameraWrapper {
         }
     }
 
+    /// The longest side of the canvas, which has to fit into the view
+    fn size(dimensions: [f32; 3]) -> f32 {
+        dimensions[0].max(dimensions[1]).max(dimensions[2])
+    }
+
+    fn center(camera: &mut OrbitZoomCamera<f32>, dimensions: [f32; 3]) {
+        camera.target = [
+            0.5 * dimensions[0],
+            0.5 * dimensions[1],
+            0.5 * dimensions[2],
+        ];
+        camera.distance = 2.0 * Self::size(dimensions);
+    }
+
+    /// Points the camera at the center of a canvas with the given dimensions
+    pub fn frame(&mut self, dimensions: [f32; 3]) {
+        let size = Self::size(dimensions);
+        Self::center(&mut self.camera, dimensions);
+        self.camera.settings = OrbitZoomCameraSettings::default().zoom_speed(0.4 * size);
+        self.cam_persp.far_clip = 10.0 * size;
+    }
+
     /// Generates the MVP matrix for the current camera setting
     pub fn mvp_matrices(&mut self, aspect_ratio: f32) -> [[[f32; 4]; 4]; 3] {
         self.cam_persp.aspect_ratio = aspect_ratio;
@@ -63,6 +96,24 @@ impl CameraWrapper {
         self.cam_persp.projection().into()
     }
 
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            target: self.camera.target,
+            rotation: self.camera.rotation,
+            distance: self.camera.distance,
+            pitch: self.camera.pitch,
+            yaw: self.camera.yaw,
+        }
+    }
+
+    pub fn set_state(&mut self, state: CameraState) {
+        self.camera.target = state.target;
+        self.camera.rotation = state.rotation;
+        self.camera.distance = state.distance;
+        self.camera.pitch = state.pitch;
+        self.camera.yaw = state.yaw;
+    }
+
     /// Respond to scroll and key press/release events
     pub fn update(&mut self, window_event: &winit::event::WindowEvent) -> bool {
         let mut viewport_changed = false;
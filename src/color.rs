@@ -1,5 +1,46 @@
 pub const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
 pub const HALF_ALPHA_RED: [f32; 4] = [1.0, 0.0, 0.0, 0.2];
+pub const HALF_ALPHA_YELLOW: [f32; 4] = [1.0, 1.0, 0.0, 0.3];
 pub const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
 pub const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
 pub const TRANSPARENT: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
+
+/// Palette of new documents
+pub const DEFAULT_PALETTE: [[f32; 4]; 18] = [
+    // red
+    [1.0, 0.0, 0.0, 1.0],
+    // orange
+    [1.0, 165.0 / 255.0, 0.0, 1.0],
+    // orange red
+    [1.0, 69.0 / 255.0, 0.0, 1.0],
+    // yellow
+    [1.0, 1.0, 0.0, 1.0],
+    // brown
+    [139.0 / 255.0, 69.0 / 255.0, 19.0 / 255.0, 1.0],
+    // purple
+    [0.5, 0.0, 0.5, 1.0],
+    // magenta
+    [1.0, 0.0, 1.0, 1.0],
+    // violet
+    [148.0 / 255.0, 0.0, 211.0 / 255.0, 1.0],
+    // blue
+    [0.0, 0.0, 1.0, 1.0],
+    // deep sky blue
+    [0.0, 191.0 / 255.0, 1.0, 1.0],
+    // aqua marine
+    [127.0 / 252.0, 1.0, 212.0 / 252.0, 1.0],
+    // cyan
+    [0.0, 1.0, 1.0, 1.0],
+    // green
+    [0.0, 1.0, 0.0, 1.0],
+    // chartreuse
+    [0.5, 1.0, 0.0, 1.0],
+    // white
+    [1.0, 1.0, 1.0, 1.0],
+    // silver
+    [192.0 / 255.0, 192.0 / 255.0, 192.0 / 255.0, 1.0],
+    // gray
+    [0.5, 0.5, 0.5, 1.0],
+    // black
+    [0.02, 0.02, 0.02, 1.0],
+];
@@ -1,26 +1,84 @@
+use crate::fill::Connectivity;
+use crate::material::Material;
+use crate::obj::{ColorMode, OBJ_EXTENSION};
+use crate::project::PROJECT_EXTENSION;
+use crate::renderer::DEFAULT_MESH_COUNT;
+use crate::shape::{Shape, ShapeOptions};
+use crate::symmetry::Symmetry;
+use crate::terrain::{TerrainMode, TerrainParams, MAX_OCTAVES};
+use crate::vox::VOX_EXTENSION;
+use crate::voxel_manager::{
+    Anchor, BooleanOp, GridBounds, OverlapColor, VoxelFilter, VoxelTransform,
+};
 use iced_wgpu::{
     canvas,
     container::{Style, StyleSheet},
     Renderer,
 };
 use iced_winit::{
-    button, mouse, Background, Button, Color, Column, Command, Container, Element, Length, Point,
-    Program, Radio, Rectangle, Size, Text,
+    button, mouse, slider, text_input, Background, Button, Checkbox, Color, Column, Command,
+    Container, Element, Length, Point, Program, Radio, Rectangle, Row, Size, Slider, Text,
+    TextInput,
 };
 
 use std::cell::Cell;
 
 pub const COLOR_SIZE: f32 = 20.0;
+/// Largest canvas size on any axis, .vox files can't store more
+pub const MAX_CANVAS_SIZE: usize = 256;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EditOp {
     Draw,
     Erase,
     Refill,
+    /// Selects the voxels of a box, which can be moved, copied and deleted
+    Select,
+    /// Recolors the voxels connected to the clicked one which have its color
+    Fill,
+    /// Fills the enclosed empty space in front of the clicked face
+    Bucket,
+    /// Draws a line from the start to the end of the drag
+    Line,
+    /// Draws a sphere fitted inside the largest cube which fits in the dragged box
+    Sphere,
+    /// Draws the shape fitted inside the dragged box
+    Ellipsoid,
+    Cylinder,
+    Cone,
 }
 
 impl EditOp {
-    pub const ALL: [EditOp; 3] = [EditOp::Draw, EditOp::Erase, EditOp::Refill];
+    pub const ALL: [EditOp; 11] = [
+        EditOp::Draw,
+        EditOp::Erase,
+        EditOp::Refill,
+        EditOp::Select,
+        EditOp::Fill,
+        EditOp::Bucket,
+        EditOp::Line,
+        EditOp::Sphere,
+        EditOp::Ellipsoid,
+        EditOp::Cylinder,
+        EditOp::Cone,
+    ];
+
+    /// Whether the op starts from the clicked voxel instead of a dragged box
+    pub fn is_fill(self) -> bool {
+        self == EditOp::Fill || self == EditOp::Bucket
+    }
+
+    /// The shape the op draws, if it's a shape tool
+    pub fn shape(self) -> Option<Shape> {
+        match self {
+            EditOp::Line => Some(Shape::Line),
+            EditOp::Sphere => Some(Shape::Sphere),
+            EditOp::Ellipsoid => Some(Shape::Ellipsoid),
+            EditOp::Cylinder => Some(Shape::Cylinder),
+            EditOp::Cone => Some(Shape::Cone),
+            _ => None,
+        }
+    }
 }
 
 impl Default for EditOp {
@@ -29,11 +87,137 @@ impl Default for EditOp {
     }
 }
 
+/// A canvas resize requested from the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeRequest {
+    pub extent: [usize; 3],
+    pub anchor: [Anchor; 3],
+    /// The user has been warned that the resize deletes voxels
+    pub confirmed: bool,
+}
+
+/// What the layer list shows of a document layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerEntry {
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+}
+
+/// A change of the document layers requested from the UI
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerRequest {
+    Select(usize),
+    SetVisible(usize, bool),
+    SetLocked(usize, bool),
+    Rename(usize, String),
+    Add,
+    Remove(usize),
+    Move { from: usize, to: usize },
+}
+
+/// A change of the scene objects requested from the UI
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectRequest {
+    Select(usize),
+    /// Adds an empty object with the given extent
+    Add([usize; 3]),
+    Remove(usize),
+    /// Moves the active object to the given position
+    Move([i32; 3]),
+    /// Turns the active object a quarter around the axis
+    Rotate(usize),
+}
+
+/// An edit of the model's shell requested from the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShellRequest {
+    /// Keeps the voxels less than the thickness below the surface
+    Hollow(usize),
+    /// Grows the shell inward by the thickness
+    Thicken(usize),
+}
+
+/// A Boolean operation on the active layer requested from the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CombineRequest {
+    pub op: BooleanOp,
+    /// The layer combined with the active one, the selection if there's none
+    pub source: Option<usize>,
+    pub overlap: OverlapColor,
+}
+
+/// Terrain generation requested from the UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerrainRequest {
+    /// Shows the terrain from above in the generator dialog
+    Preview(TerrainParams),
+    /// Replaces the active layer with the terrain
+    Generate(TerrainParams),
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     EditChanged(EditOp),
     ExportPressed,
-    ColorPicked(Color),
+    VertexColorsToggled(bool),
+    GreedyMeshingToggled(bool),
+    FillDiagonalsToggled(bool),
+    ShapeAxisStepped,
+    HollowToggled(bool),
+    NewSizeChanged(usize, String),
+    NewPressed,
+    AnchorStepped(usize),
+    ResizePressed,
+    CropWarning(ResizeRequest, usize),
+    OpenPressed,
+    SavePressed,
+    SaveAsPressed,
+    ImportPressed,
+    VoxExportPressed,
+    ColorPicked(usize),
+    ChannelChanged(usize, f32),
+    MaterialChanged(usize, f32),
+    AddColorPressed,
+    PaletteChanged(Vec<Color>, Vec<Material>),
+    LayerSelected(usize),
+    LayerVisibilityToggled(usize, bool),
+    LayerLockToggled(usize, bool),
+    LayerNameChanged(String),
+    AddLayerPressed,
+    RemoveLayerPressed,
+    LayerUpPressed,
+    LayerDownPressed,
+    LayersChanged(Vec<LayerEntry>, usize),
+    ObjectSelected(usize),
+    AddObjectPressed,
+    RemoveObjectPressed,
+    ObjectPositionChanged(usize, String),
+    ObjectRotatePressed(usize),
+    SeparateObjectsToggled(bool),
+    ObjectsChanged(Vec<String>, usize, [i32; 3]),
+    TurnsStepped,
+    RotatePressed(usize),
+    FlipPressed(usize),
+    TranslateOffsetChanged(usize, String),
+    TranslatePressed,
+    WrapToggled(bool),
+    GrowToggled(bool),
+    MirrorToggled(usize, bool),
+    MirrorPlaneChanged(usize, String),
+    WallThicknessChanged(String),
+    ShellThicknessChanged(String),
+    HollowModelPressed,
+    ThickenModelPressed,
+    FilterPressed(VoxelFilter),
+    CombineSourceStepped,
+    SourceColorToggled(bool),
+    CombinePressed(BooleanOp),
+    TerrainPressed,
+    TerrainModeStepped,
+    TerrainValueChanged(usize, String),
+    GeneratePressed,
+    TerrainPreviewChanged(Vec<Option<Color>>, usize),
 }
 
 #[derive(Default)]
@@ -44,49 +228,10 @@ struct ColorPicker {
 
 impl ColorPicker {
     pub const COLORS_PER_LINE: usize = 6;
-    fn new() -> Self {
-        let colors = vec![
-            // red
-            Color::new(1.0, 0.0, 0.0, 1.0),
-            // orange
-            Color::new(1.0, 165.0 / 255.0, 0.0, 1.0),
-            // orange red
-            Color::new(1.0, 69.0 / 255.0, 0.0, 1.0),
-            // yellow
-            Color::new(1.0, 1.0, 0.0, 1.0),
-            // brown
-            Color::new(139.0 / 255.0, 69.0 / 255.0, 19.0 / 255.0, 1.0),
-            // purple
-            Color::new(0.5, 0.0, 0.5, 1.0),
-            // magenta
-            Color::new(1.0, 0.0, 1.0, 1.0),
-            // violet
-            Color::new(148.0 / 255.0, 0.0, 211.0 / 255.0, 1.0),
-            // blue
-            Color::new(0.0, 0.0, 1.0, 1.0),
-            // deep sky blue
-            Color::new(0.0, 191.0 / 255.0, 1.0, 1.0),
-            // aqua marine
-            Color::new(127.0 / 252.0, 1.0, 212.0 / 252.0, 1.0),
-            // cyan
-            Color::new(0.0, 1.0, 1.0, 1.0),
-            // green
-            Color::new(0.0, 1.0, 0.0, 1.0),
-            // chartreuse
-            Color::new(0.5, 1.0, 0.0, 1.0),
-            // white
-            Color::new(1.0, 1.0, 1.0, 1.0),
-            // silver
-            Color::new(192.0 / 255.0, 192.0 / 255.0, 192.0 / 255.0, 1.0),
-            // gray
-            Color::new(0.5, 0.5, 0.5, 1.0),
-            // black
-            Color::new(0.02, 0.02, 0.02, 1.0),
-        ];
-        ColorPicker {
-            canvas_cache: canvas::Cache::default(),
-            colors,
-        }
+
+    fn set_colors(&mut self, colors: Vec<Color>) {
+        self.colors = colors;
+        self.canvas_cache.clear();
     }
 
     fn draw(&self, frame: &mut canvas::Frame) {
@@ -153,12 +298,174 @@ impl PickedColor {
     }
 }
 
+/// The terrain the generator parameters give seen from above, a square for every sampled
+/// column
+#[derive(Default)]
+struct TerrainPreview {
+    canvas_cache: canvas::Cache,
+    columns: Vec<Option<Color>>,
+    width: usize,
+}
+
+impl TerrainPreview {
+    pub const SIZE: f32 = 128.0;
+
+    fn set_columns(&mut self, columns: Vec<Option<Color>>, width: usize) {
+        self.columns = columns;
+        self.width = width;
+        self.canvas_cache.clear();
+    }
+
+    fn draw(&self, frame: &mut canvas::Frame) {
+        if self.width == 0 {
+            return;
+        }
+        let rows = (self.columns.len() + self.width - 1) / self.width;
+        let cell = Self::SIZE / self.width.max(rows) as f32;
+        let box_size = Size {
+            width: cell,
+            height: cell,
+        };
+
+        for (i, color) in self.columns.iter().enumerate() {
+            if let Some(color) = color {
+                let anchor = Point {
+                    x: (i % self.width) as f32 * cell,
+                    y: (i / self.width) as f32 * cell,
+                };
+                frame.fill_rectangle(anchor, box_size, *color);
+            }
+        }
+    }
+
+    pub fn view(&mut self) -> Element<Message, Renderer> {
+        canvas::Canvas::new(self)
+            .width(Length::Units(Self::SIZE as u16))
+            .height(Length::Units(Self::SIZE as u16))
+            .into()
+    }
+}
+
+/// A row of X, Y and Z buttons which send the message with their axis
+fn axis_buttons(
+    states: &mut [button::State; 3],
+    message: fn(usize) -> Message,
+) -> Row<Message, Renderer> {
+    states
+        .iter_mut()
+        .zip(["X", "Y", "Z"].iter())
+        .enumerate()
+        .fold(Row::new().spacing(5), |row, (axis, (state, name))| {
+            row.push(
+                Button::new(state, Text::new(*name).size(14))
+                    .width(Length::Units(28))
+                    .padding(2)
+                    .on_press(message(axis)),
+            )
+        })
+}
+
 pub struct Controls {
     edit_op: Cell<EditOp>,
     export_button: button::State,
+    obj_color_mode: ColorMode,
+    greedy_meshing: bool,
+    fill_diagonals: bool,
+    /// The axis of the cylinders and cones
+    shape_axis: usize,
+    shape_axis_button: button::State,
+    hollow: bool,
+    new_size_inputs: [text_input::State; 3],
+    new_size: [String; 3],
+    new_button: button::State,
+    anchor_buttons: [button::State; 3],
+    anchor: [Anchor; 3],
+    resize_button: button::State,
+    crop_warning: Option<(ResizeRequest, usize)>,
+    open_button: button::State,
+    save_button: button::State,
+    save_as_button: button::State,
+    import_button: button::State,
+    vox_export_button: button::State,
     color_picker: ColorPicker,
+    picked_index: usize,
     picked_color: PickedColor,
+    channel_sliders: [slider::State; 3],
+    /// The material of every palette entry
+    materials: Vec<Material>,
+    material_sliders: [slider::State; 4],
+    add_color_button: button::State,
+    layers: Vec<LayerEntry>,
+    active_layer: usize,
+    layer_name_input: text_input::State,
+    add_layer_button: button::State,
+    remove_layer_button: button::State,
+    layer_up_button: button::State,
+    layer_down_button: button::State,
+    /// Every layer change since the editor last asked, in order
+    layer_requests: Cell<Vec<LayerRequest>>,
+    objects: Vec<String>,
+    active_object: usize,
+    object_position_inputs: [text_input::State; 3],
+    object_position: [String; 3],
+    add_object_button: button::State,
+    remove_object_button: button::State,
+    rotate_object_buttons: [button::State; 3],
+    /// Export every object as a separate mesh or model instead of merging them
+    separate_objects: bool,
+    object_requests: Cell<Vec<ObjectRequest>>,
+    /// Quarter turns of the rotate buttons
+    turns: u8,
+    turns_button: button::State,
+    rotate_buttons: [button::State; 3],
+    flip_buttons: [button::State; 3],
+    translate_inputs: [text_input::State; 3],
+    translate_offset: [String; 3],
+    translate_button: button::State,
+    wrap: bool,
+    /// Grow the canvas to fit transformed voxels instead of deleting the ones leaving it
+    grow: bool,
+    transform_request: Cell<Option<(VoxelTransform, GridBounds)>>,
+    /// Thickness of the walls of the drawn boxes, solid boxes are drawn if it's 0
+    wall_thickness_input: text_input::State,
+    wall_thickness: String,
+    shell_thickness_input: text_input::State,
+    shell_thickness: String,
+    hollow_model_button: button::State,
+    thicken_model_button: button::State,
+    shell_request: Cell<Option<ShellRequest>>,
+    filter_buttons: [button::State; 5],
+    filter_request: Cell<Option<VoxelFilter>>,
+    /// The layer combined with the active one, the selection if there's none
+    combine_source: Option<usize>,
+    combine_source_button: button::State,
+    source_color_wins: bool,
+    combine_buttons: [button::State; 4],
+    combine_request: Cell<Option<CombineRequest>>,
+    /// Shows the parameters of the terrain generator
+    terrain_open: bool,
+    terrain_button: button::State,
+    terrain_mode: TerrainMode,
+    terrain_mode_button: button::State,
+    terrain_inputs: [text_input::State; 6],
+    /// Seed, scale, octaves, lacunarity, gain and ground level of the terrain
+    terrain_values: [String; 6],
+    generate_button: button::State,
+    terrain_preview: TerrainPreview,
+    terrain_request: Cell<Option<TerrainRequest>>,
+    symmetry: Symmetry,
+    mirror_plane_inputs: [text_input::State; 3],
+    mirror_planes: [String; 3],
     save_file: Cell<Option<String>>,
+    new_document: Cell<Option<[usize; 3]>>,
+    resize_canvas: Cell<Option<ResizeRequest>>,
+    palette_edit: Cell<Option<(usize, [f32; 4])>>,
+    material_edit: Cell<Option<(usize, Material)>>,
+    project_path: Option<String>,
+    project_save_file: Cell<Option<String>>,
+    project_open_file: Cell<Option<String>>,
+    import_file: Cell<Option<String>>,
+    vox_export_file: Cell<Option<String>>,
 }
 
 impl Controls {
@@ -166,9 +473,105 @@ impl Controls {
         Controls {
             edit_op: Cell::new(EditOp::default()),
             export_button: button::State::default(),
-            color_picker: ColorPicker::new(),
+            obj_color_mode: ColorMode::Material,
+            greedy_meshing: false,
+            fill_diagonals: false,
+            shape_axis: 2,
+            shape_axis_button: button::State::default(),
+            hollow: false,
+            new_size_inputs: Default::default(),
+            new_size: [
+                DEFAULT_MESH_COUNT.to_string(),
+                DEFAULT_MESH_COUNT.to_string(),
+                DEFAULT_MESH_COUNT.to_string(),
+            ],
+            new_button: button::State::default(),
+            anchor_buttons: Default::default(),
+            anchor: [Anchor::Start; 3],
+            resize_button: button::State::default(),
+            crop_warning: None,
+            open_button: button::State::default(),
+            save_button: button::State::default(),
+            save_as_button: button::State::default(),
+            import_button: button::State::default(),
+            vox_export_button: button::State::default(),
+            color_picker: ColorPicker::default(),
+            picked_index: 0,
             picked_color: PickedColor::new(Color::new(0.02, 0.02, 0.02, 1.0)),
+            channel_sliders: Default::default(),
+            materials: Vec::new(),
+            material_sliders: Default::default(),
+            add_color_button: button::State::default(),
+            layers: Vec::new(),
+            active_layer: 0,
+            layer_name_input: text_input::State::default(),
+            add_layer_button: button::State::default(),
+            remove_layer_button: button::State::default(),
+            layer_up_button: button::State::default(),
+            layer_down_button: button::State::default(),
+            layer_requests: Cell::new(Vec::new()),
+            objects: Vec::new(),
+            active_object: 0,
+            object_position_inputs: Default::default(),
+            object_position: Default::default(),
+            add_object_button: button::State::default(),
+            remove_object_button: button::State::default(),
+            rotate_object_buttons: Default::default(),
+            separate_objects: false,
+            object_requests: Cell::new(Vec::new()),
+            turns: 1,
+            turns_button: button::State::default(),
+            rotate_buttons: Default::default(),
+            flip_buttons: Default::default(),
+            translate_inputs: Default::default(),
+            translate_offset: Default::default(),
+            translate_button: button::State::default(),
+            wrap: false,
+            grow: false,
+            transform_request: Cell::new(None),
+            wall_thickness_input: text_input::State::default(),
+            wall_thickness: String::new(),
+            shell_thickness_input: text_input::State::default(),
+            shell_thickness: String::from("1"),
+            hollow_model_button: button::State::default(),
+            thicken_model_button: button::State::default(),
+            shell_request: Cell::new(None),
+            filter_buttons: Default::default(),
+            filter_request: Cell::new(None),
+            combine_source: None,
+            combine_source_button: button::State::default(),
+            source_color_wins: false,
+            combine_buttons: Default::default(),
+            combine_request: Cell::new(None),
+            terrain_open: false,
+            terrain_button: button::State::default(),
+            terrain_mode: TerrainMode::Heightfield,
+            terrain_mode_button: button::State::default(),
+            terrain_inputs: Default::default(),
+            terrain_values: [
+                String::from("0"),
+                String::from("32"),
+                String::from("4"),
+                String::from("2"),
+                String::from("0.5"),
+                String::from("0.5"),
+            ],
+            generate_button: button::State::default(),
+            terrain_preview: TerrainPreview::default(),
+            terrain_request: Cell::new(None),
+            symmetry: Symmetry::default(),
+            mirror_plane_inputs: Default::default(),
+            mirror_planes: Default::default(),
             save_file: Cell::new(None),
+            new_document: Cell::new(None),
+            resize_canvas: Cell::new(None),
+            palette_edit: Cell::new(None),
+            material_edit: Cell::new(None),
+            project_path: None,
+            project_save_file: Cell::new(None),
+            project_open_file: Cell::new(None),
+            import_file: Cell::new(None),
+            vox_export_file: Cell::new(None),
         }
     }
 
@@ -176,7 +579,15 @@ impl Controls {
         match self.edit_op.get() {
             EditOp::Draw => self.edit_op.set(EditOp::Erase),
             EditOp::Erase => self.edit_op.set(EditOp::Refill),
-            EditOp::Refill => self.edit_op.set(EditOp::Draw),
+            EditOp::Refill => self.edit_op.set(EditOp::Select),
+            EditOp::Select => self.edit_op.set(EditOp::Fill),
+            EditOp::Fill => self.edit_op.set(EditOp::Bucket),
+            EditOp::Bucket => self.edit_op.set(EditOp::Line),
+            EditOp::Line => self.edit_op.set(EditOp::Sphere),
+            EditOp::Sphere => self.edit_op.set(EditOp::Ellipsoid),
+            EditOp::Ellipsoid => self.edit_op.set(EditOp::Cylinder),
+            EditOp::Cylinder => self.edit_op.set(EditOp::Cone),
+            EditOp::Cone => self.edit_op.set(EditOp::Draw),
         }
     }
 
@@ -191,6 +602,264 @@ impl Controls {
     pub fn save_path(&self) -> Option<String> {
         self.save_file.take()
     }
+
+    pub fn obj_color_mode(&self) -> ColorMode {
+        self.obj_color_mode
+    }
+
+    pub fn greedy_meshing(&self) -> bool {
+        self.greedy_meshing
+    }
+
+    /// Which voxels the flood fill treats as connected
+    pub fn fill_connectivity(&self) -> Connectivity {
+        if self.fill_diagonals {
+            Connectivity::Full
+        } else {
+            Connectivity::Faces
+        }
+    }
+
+    pub fn shape_options(&self) -> ShapeOptions {
+        ShapeOptions {
+            axis: self.shape_axis,
+            hollow: self.hollow,
+        }
+    }
+
+    pub fn separate_objects(&self) -> bool {
+        self.separate_objects
+    }
+
+    pub fn symmetry(&self) -> Symmetry {
+        self.symmetry
+    }
+
+    /// The dimensions of the new document, if one was requested
+    pub fn new_document(&self) -> Option<[usize; 3]> {
+        self.new_document.take()
+    }
+
+    pub fn resize_canvas(&self) -> Option<ResizeRequest> {
+        self.resize_canvas.take()
+    }
+
+    pub fn project_save_path(&self) -> Option<String> {
+        self.project_save_file.take()
+    }
+
+    pub fn project_open_path(&self) -> Option<String> {
+        self.project_open_file.take()
+    }
+
+    pub fn import_path(&self) -> Option<String> {
+        self.import_file.take()
+    }
+
+    pub fn vox_export_path(&self) -> Option<String> {
+        self.vox_export_file.take()
+    }
+
+    /// The palette index and new color of an edited palette entry. The index is one past
+    /// the end of the palette for added colors.
+    pub fn palette_edit(&self) -> Option<(usize, [f32; 4])> {
+        self.palette_edit.take()
+    }
+
+    /// The palette index of the draw color
+    pub fn draw_index(&self) -> usize {
+        self.picked_index
+    }
+
+    /// The palette index and new material of an edited palette entry
+    pub fn material_edit(&self) -> Option<(usize, Material)> {
+        self.material_edit.take()
+    }
+
+    pub fn layer_requests(&self) -> Vec<LayerRequest> {
+        self.layer_requests.take()
+    }
+
+    fn request_layer_change(&self, request: LayerRequest) {
+        let mut requests = self.layer_requests.take();
+        requests.push(request);
+        self.layer_requests.set(requests);
+    }
+
+    pub fn object_requests(&self) -> Vec<ObjectRequest> {
+        self.object_requests.take()
+    }
+
+    fn request_object_change(&self, request: ObjectRequest) {
+        let mut requests = self.object_requests.take();
+        requests.push(request);
+        self.object_requests.set(requests);
+    }
+
+    /// A transform of the selection, or of the model if nothing is selected
+    pub fn transform_request(&self) -> Option<(VoxelTransform, GridBounds)> {
+        self.transform_request.take()
+    }
+
+    fn request_transform(&self, transform: VoxelTransform) {
+        let bounds = if self.grow {
+            GridBounds::Grow
+        } else {
+            GridBounds::Clip
+        };
+        self.transform_request.set(Some((transform, bounds)));
+    }
+
+    /// Thickness of the walls of the drawn boxes, 0 for solid boxes
+    pub fn wall_thickness(&self) -> usize {
+        self.wall_thickness.trim().parse().unwrap_or(0)
+    }
+
+    pub fn shell_request(&self) -> Option<ShellRequest> {
+        self.shell_request.take()
+    }
+
+    /// A filter of the selection, or of the model if nothing is selected
+    pub fn filter_request(&self) -> Option<VoxelFilter> {
+        self.filter_request.take()
+    }
+
+    /// A Boolean operation between the active layer and another layer or the selection
+    pub fn combine_request(&self) -> Option<CombineRequest> {
+        self.combine_request.take()
+    }
+
+    /// A preview or the generation of terrain
+    pub fn terrain_request(&self) -> Option<TerrainRequest> {
+        self.terrain_request.take()
+    }
+
+    /// The terrain parameters if every field has a valid value
+    fn terrain_params(&self) -> Option<TerrainParams> {
+        let [seed, scale, octaves, lacunarity, gain, level] = &self.terrain_values;
+        let params = TerrainParams {
+            seed: seed.trim().parse().ok()?,
+            mode: self.terrain_mode,
+            scale: scale.trim().parse().ok()?,
+            octaves: octaves.trim().parse().ok()?,
+            lacunarity: lacunarity.trim().parse().ok()?,
+            gain: gain.trim().parse().ok()?,
+            level: level.trim().parse().ok()?,
+        };
+        let valid = params.scale > 0.0
+            && params.octaves > 0
+            && params.octaves <= MAX_OCTAVES
+            && params.lacunarity >= 1.0
+            && params.gain >= 0.0;
+        if valid {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    /// Asks for a new preview while the generator dialog is open
+    fn request_terrain_preview(&self) {
+        if self.terrain_open {
+            if let Some(params) = self.terrain_params() {
+                self.terrain_request
+                    .set(Some(TerrainRequest::Preview(params)));
+            }
+        }
+    }
+
+    /// The next layer to combine with the active one, skipping it, or the selection after
+    /// the last layer
+    fn next_combine_source(&self) -> Option<usize> {
+        let start = self.combine_source.map_or(0, |idx| idx + 1);
+        (start..self.layers.len()).find(|idx| *idx != self.active_layer)
+    }
+
+    /// The shell thickness if it's a positive number
+    fn parsed_shell_thickness(&self) -> Option<usize> {
+        match self.shell_thickness.trim().parse() {
+            Ok(thickness) if thickness > 0 => Some(thickness),
+            _ => None,
+        }
+    }
+
+    /// Moves the active layer one place up or down the stack, if it isn't at the end
+    fn move_active_layer(&mut self, up: bool) {
+        let from = self.active_layer;
+        let to = if up { from + 1 } else { from.wrapping_sub(1) };
+        if to < self.layers.len() {
+            self.layers.swap(from, to);
+            self.active_layer = to;
+            self.request_layer_change(LayerRequest::Move { from, to });
+        }
+    }
+
+    fn picked_material(&self) -> Material {
+        self.materials
+            .get(self.picked_index)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn pick_color(&mut self, idx: usize) {
+        self.picked_index = idx;
+        if let Some(color) = self.color_picker.colors.get(idx) {
+            self.picked_color = PickedColor::new(*color);
+        }
+    }
+
+    /// Shows the edited color in the picker and passes the edit on to the editor
+    fn edit_picked_color(&mut self, color: Color) {
+        let idx = self.picked_index;
+        let mut colors = self.color_picker.colors.clone();
+        if idx < colors.len() {
+            colors[idx] = color;
+        } else {
+            colors.push(color);
+        }
+        self.color_picker.set_colors(colors);
+        self.picked_color = PickedColor::new(color);
+        self.palette_edit
+            .set(Some((idx, [color.r, color.g, color.b, color.a])));
+    }
+
+    fn edit_picked_material(&mut self, material: Material) {
+        let idx = self.picked_index;
+        if idx < self.materials.len() {
+            self.materials[idx] = material;
+        } else {
+            self.materials.push(material);
+        }
+        self.material_edit.set(Some((idx, material)));
+    }
+
+    /// Parses the canvas size fields
+    fn canvas_size(&self) -> Option<[usize; 3]> {
+        let mut dimensions = [0; 3];
+        for (d, value) in dimensions.iter_mut().zip(self.new_size.iter()) {
+            *d = value.trim().parse().unwrap_or(0);
+        }
+        if dimensions.iter().all(|d| *d > 0 && *d <= MAX_CANVAS_SIZE) {
+            Some(dimensions)
+        } else {
+            println!(
+                "Invalid canvas size {:?}, every side has to be between 1 and {}",
+                self.new_size, MAX_CANVAS_SIZE
+            );
+            None
+        }
+    }
+
+    fn pick_project_save_path(&mut self) {
+        let result = nfd::open_save_dialog(Some(PROJECT_EXTENSION), None).unwrap_or_else(|e| {
+            panic!(e);
+        });
+
+        if let nfd::Response::Okay(file_path) = result {
+            self.project_path = Some(file_path.clone());
+            self.project_save_file.set(Some(file_path));
+        }
+    }
 }
 
 impl Program for Controls {
@@ -201,7 +870,7 @@ impl Program for Controls {
         match message {
             Message::EditChanged(op) => self.edit_op.set(op),
             Message::ExportPressed => {
-                let result = nfd::open_save_dialog(Some("obj"), None).unwrap_or_else(|e| {
+                let result = nfd::open_save_dialog(Some(OBJ_EXTENSION), None).unwrap_or_else(|e| {
                     panic!(e);
                 });
 
@@ -210,13 +879,588 @@ impl Program for Controls {
                     _ => {}
                 }
             }
-            Message::ColorPicked(color) => self.picked_color = PickedColor::new(color),
+            Message::VertexColorsToggled(enabled) => {
+                self.obj_color_mode = if enabled {
+                    ColorMode::VertexColor
+                } else {
+                    ColorMode::Material
+                }
+            }
+            Message::GreedyMeshingToggled(enabled) => self.greedy_meshing = enabled,
+            Message::FillDiagonalsToggled(enabled) => self.fill_diagonals = enabled,
+            Message::ShapeAxisStepped => self.shape_axis = (self.shape_axis + 1) % 3,
+            Message::HollowToggled(hollow) => self.hollow = hollow,
+            Message::NewSizeChanged(axis, value) => {
+                self.new_size[axis] = value;
+                self.crop_warning = None;
+            }
+            Message::NewPressed => {
+                if let Some(dimensions) = self.canvas_size() {
+                    self.project_path = None;
+                    self.crop_warning = None;
+                    self.new_document.set(Some(dimensions));
+                }
+            }
+            Message::AnchorStepped(axis) => {
+                self.anchor[axis] = self.anchor[axis].next();
+                self.crop_warning = None;
+            }
+            Message::ResizePressed => {
+                if let Some(extent) = self.canvas_size() {
+                    let mut request = ResizeRequest {
+                        extent,
+                        anchor: self.anchor,
+                        confirmed: false,
+                    };
+                    // Pressing resize again after the warning crops the canvas
+                    if let Some((warned, _)) = self.crop_warning.take() {
+                        request.confirmed = warned == request;
+                    }
+                    self.resize_canvas.set(Some(request));
+                }
+            }
+            Message::CropWarning(request, count) => self.crop_warning = Some((request, count)),
+            Message::OpenPressed => {
+                let result =
+                    nfd::open_file_dialog(Some(PROJECT_EXTENSION), None).unwrap_or_else(|e| {
+                        panic!(e);
+                    });
+
+                if let nfd::Response::Okay(file_path) = result {
+                    self.project_path = Some(file_path.clone());
+                    self.project_open_file.set(Some(file_path));
+                }
+            }
+            Message::SavePressed => match self.project_path {
+                Some(ref file_path) => self.project_save_file.set(Some(file_path.clone())),
+                None => self.pick_project_save_path(),
+            },
+            Message::SaveAsPressed => self.pick_project_save_path(),
+            Message::ImportPressed => {
+                let result = nfd::open_file_dialog(Some(VOX_EXTENSION), None).unwrap_or_else(|e| {
+                    panic!(e);
+                });
+
+                if let nfd::Response::Okay(file_path) = result {
+                    self.import_file.set(Some(file_path));
+                }
+            }
+            Message::VoxExportPressed => {
+                let result = nfd::open_save_dialog(Some(VOX_EXTENSION), None).unwrap_or_else(|e| {
+                    panic!(e);
+                });
+
+                if let nfd::Response::Okay(file_path) = result {
+                    self.vox_export_file.set(Some(file_path));
+                }
+            }
+            Message::ColorPicked(idx) => self.pick_color(idx),
+            Message::ChannelChanged(channel, value) => {
+                let mut color = self.picked_color.color;
+                match channel {
+                    0 => color.r = value,
+                    1 => color.g = value,
+                    _ => color.b = value,
+                }
+                self.edit_picked_color(color);
+            }
+            Message::MaterialChanged(property, value) => {
+                let mut properties = self.picked_material().to_array();
+                properties[property] = value;
+                self.edit_picked_material(Material::from_array(properties));
+            }
+            Message::AddColorPressed => {
+                // The new entry starts as a copy of the picked color and material
+                let color = self.picked_color.color;
+                let material = self.picked_material();
+                self.picked_index = self.color_picker.colors.len();
+                self.edit_picked_color(color);
+                if material == Material::default() {
+                    self.materials.push(material);
+                } else {
+                    self.edit_picked_material(material);
+                }
+            }
+            Message::PaletteChanged(colors, materials) => {
+                let last = colors.len().saturating_sub(1);
+                self.color_picker.set_colors(colors);
+                self.materials = materials;
+                self.pick_color(self.picked_index.min(last));
+                // The preview shows the height bands in the palette colors
+                self.request_terrain_preview();
+            }
+            Message::LayerSelected(idx) => {
+                self.active_layer = idx;
+                self.request_layer_change(LayerRequest::Select(idx));
+            }
+            Message::LayerVisibilityToggled(idx, visible) => {
+                self.layers[idx].visible = visible;
+                self.request_layer_change(LayerRequest::SetVisible(idx, visible));
+            }
+            Message::LayerLockToggled(idx, locked) => {
+                self.layers[idx].locked = locked;
+                self.request_layer_change(LayerRequest::SetLocked(idx, locked));
+            }
+            Message::LayerNameChanged(name) => {
+                if let Some(layer) = self.layers.get_mut(self.active_layer) {
+                    layer.name = name.clone();
+                    self.request_layer_change(LayerRequest::Rename(self.active_layer, name));
+                }
+            }
+            Message::AddLayerPressed => self.request_layer_change(LayerRequest::Add),
+            Message::RemoveLayerPressed => {
+                if self.layers.len() > 1 {
+                    self.request_layer_change(LayerRequest::Remove(self.active_layer));
+                }
+            }
+            Message::LayerUpPressed => self.move_active_layer(true),
+            Message::LayerDownPressed => self.move_active_layer(false),
+            Message::LayersChanged(layers, active_layer) => {
+                self.layers = layers;
+                self.active_layer = active_layer;
+                if let Some(source) = self.combine_source {
+                    if source >= self.layers.len() || source == active_layer {
+                        self.combine_source = None;
+                    }
+                }
+            }
+            Message::ObjectSelected(idx) => {
+                self.active_object = idx;
+                self.request_object_change(ObjectRequest::Select(idx));
+            }
+            Message::AddObjectPressed => {
+                if let Some(extent) = self.canvas_size() {
+                    self.request_object_change(ObjectRequest::Add(extent));
+                }
+            }
+            Message::RemoveObjectPressed => {
+                if self.objects.len() > 1 {
+                    self.request_object_change(ObjectRequest::Remove(self.active_object));
+                }
+            }
+            Message::ObjectPositionChanged(axis, value) => {
+                self.object_position[axis] = value;
+                let mut translation = [0; 3];
+                let parsed = translation
+                    .iter_mut()
+                    .zip(self.object_position.iter())
+                    .all(|(t, value)| value.trim().parse().map(|v| *t = v).is_ok());
+                if parsed {
+                    self.request_object_change(ObjectRequest::Move(translation));
+                }
+            }
+            Message::ObjectRotatePressed(axis) => {
+                self.request_object_change(ObjectRequest::Rotate(axis))
+            }
+            Message::SeparateObjectsToggled(enabled) => self.separate_objects = enabled,
+            Message::ObjectsChanged(objects, active_object, translation) => {
+                self.objects = objects;
+                self.active_object = active_object;
+                for (value, t) in self.object_position.iter_mut().zip(translation.iter()) {
+                    *value = t.to_string();
+                }
+            }
+            Message::TurnsStepped => self.turns = self.turns % 3 + 1,
+            Message::RotatePressed(axis) => self.request_transform(VoxelTransform::Rotate {
+                axis,
+                turns: self.turns,
+            }),
+            Message::FlipPressed(axis) => self.request_transform(VoxelTransform::Flip(axis)),
+            Message::TranslateOffsetChanged(axis, value) => self.translate_offset[axis] = value,
+            Message::TranslatePressed => {
+                let mut offset = [0; 3];
+                for (o, value) in offset.iter_mut().zip(self.translate_offset.iter()) {
+                    *o = value.trim().parse().unwrap_or(0);
+                }
+                if offset != [0; 3] {
+                    self.request_transform(VoxelTransform::Translate {
+                        offset,
+                        wrap: self.wrap,
+                    });
+                }
+            }
+            Message::WrapToggled(wrap) => self.wrap = wrap,
+            Message::GrowToggled(grow) => self.grow = grow,
+            Message::MirrorToggled(axis, enabled) => self.symmetry.enabled[axis] = enabled,
+            Message::MirrorPlaneChanged(axis, value) => {
+                // The plane snaps to half cells, an empty or invalid field puts it in the center
+                self.symmetry.planes[axis] = value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .map(|plane| (plane * 2.0).round() / 2.0);
+                self.mirror_planes[axis] = value;
+            }
+            Message::WallThicknessChanged(value) => self.wall_thickness = value,
+            Message::ShellThicknessChanged(value) => self.shell_thickness = value,
+            Message::HollowModelPressed => {
+                if let Some(thickness) = self.parsed_shell_thickness() {
+                    self.shell_request
+                        .set(Some(ShellRequest::Hollow(thickness)));
+                }
+            }
+            Message::ThickenModelPressed => {
+                if let Some(thickness) = self.parsed_shell_thickness() {
+                    self.shell_request
+                        .set(Some(ShellRequest::Thicken(thickness)));
+                }
+            }
+            Message::FilterPressed(filter) => self.filter_request.set(Some(filter)),
+            Message::CombineSourceStepped => self.combine_source = self.next_combine_source(),
+            Message::SourceColorToggled(enabled) => self.source_color_wins = enabled,
+            Message::CombinePressed(op) => {
+                let overlap = if self.source_color_wins {
+                    OverlapColor::Source
+                } else {
+                    OverlapColor::Target
+                };
+                self.combine_request.set(Some(CombineRequest {
+                    op,
+                    source: self.combine_source,
+                    overlap,
+                }));
+            }
+            Message::TerrainPressed => {
+                self.terrain_open = !self.terrain_open;
+                self.request_terrain_preview();
+            }
+            Message::TerrainModeStepped => {
+                self.terrain_mode = match self.terrain_mode {
+                    TerrainMode::Heightfield => TerrainMode::Density,
+                    TerrainMode::Density => TerrainMode::Heightfield,
+                };
+                self.request_terrain_preview();
+            }
+            Message::TerrainValueChanged(idx, value) => {
+                self.terrain_values[idx] = value;
+                self.request_terrain_preview();
+            }
+            Message::GeneratePressed => {
+                if let Some(params) = self.terrain_params() {
+                    self.terrain_request
+                        .set(Some(TerrainRequest::Generate(params)));
+                }
+            }
+            Message::TerrainPreviewChanged(columns, width) => {
+                self.terrain_preview.set_columns(columns, width)
+            }
         };
 
         Command::none()
     }
 
     fn view(&mut self) -> Element<Message, Renderer> {
+        let new_size_row = self
+            .new_size_inputs
+            .iter_mut()
+            .zip(self.new_size.iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (state, value))| {
+                row.push(
+                    TextInput::new(state, "", value, move |value| {
+                        Message::NewSizeChanged(axis, value)
+                    })
+                    .width(Length::Units(38))
+                    .padding(2),
+                )
+            });
+        let anchor_row = self
+            .anchor_buttons
+            .iter_mut()
+            .zip(self.anchor.iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (state, anchor))| {
+                row.push(
+                    Button::new(state, Text::new(format!("{:?}", anchor)).size(12))
+                        .width(Length::Units(38))
+                        .padding(2)
+                        .on_press(Message::AnchorStepped(axis)),
+                )
+            });
+        let picked = self.picked_color.color;
+        let channel_sliders = self
+            .channel_sliders
+            .iter_mut()
+            .zip([picked.r, picked.g, picked.b].iter())
+            .enumerate()
+            .fold(
+                Column::new().spacing(5),
+                |column, (channel, (state, value))| {
+                    column.push(Slider::new(state, 0.0..=1.0, *value, move |value| {
+                        Message::ChannelChanged(channel, value)
+                    }))
+                },
+            );
+        let material = self.picked_material().to_array();
+        let material_sliders = self
+            .material_sliders
+            .iter_mut()
+            .zip(material.iter())
+            .zip(Material::PROPERTY_NAMES.iter())
+            .enumerate()
+            .fold(
+                Column::new().spacing(5),
+                |column, (property, ((state, value), name))| {
+                    column.push(Text::new(*name).size(14)).push(Slider::new(
+                        state,
+                        0.0..=1.0,
+                        *value,
+                        move |value| Message::MaterialChanged(property, value),
+                    ))
+                },
+            );
+        // The topmost layer is listed first
+        let active_layer = self.active_layer;
+        let layer_list = self.layers.iter().enumerate().rev().fold(
+            Column::new()
+                .spacing(5)
+                .push(Text::new("Shown, locked, name").size(14)),
+            |column, (idx, layer)| {
+                column.push(
+                    Row::new()
+                        .spacing(5)
+                        .push(Checkbox::new(layer.visible, "", move |visible| {
+                            Message::LayerVisibilityToggled(idx, visible)
+                        }))
+                        .push(Checkbox::new(layer.locked, "", move |locked| {
+                            Message::LayerLockToggled(idx, locked)
+                        }))
+                        .push(Radio::new(
+                            idx,
+                            &layer.name,
+                            Some(active_layer),
+                            Message::LayerSelected,
+                        )),
+                )
+            },
+        );
+        let active_name = self
+            .layers
+            .get(active_layer)
+            .map(|layer| layer.name.clone())
+            .unwrap_or_default();
+        let layer_buttons = Column::new()
+            .spacing(5)
+            .push(
+                TextInput::new(
+                    &mut self.layer_name_input,
+                    "Layer name",
+                    &active_name,
+                    Message::LayerNameChanged,
+                )
+                .padding(2),
+            )
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Button::new(&mut self.add_layer_button, Text::new("Add").size(14))
+                            .on_press(Message::AddLayerPressed),
+                    )
+                    .push(
+                        Button::new(&mut self.remove_layer_button, Text::new("Remove").size(14))
+                            .on_press(Message::RemoveLayerPressed),
+                    ),
+            )
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Button::new(&mut self.layer_up_button, Text::new("Up").size(14))
+                            .on_press(Message::LayerUpPressed),
+                    )
+                    .push(
+                        Button::new(&mut self.layer_down_button, Text::new("Down").size(14))
+                            .on_press(Message::LayerDownPressed),
+                    ),
+            );
+        let active_object = self.active_object;
+        let object_list = self.objects.iter().enumerate().fold(
+            Column::new().spacing(5),
+            |column, (idx, name)| {
+                column.push(Radio::new(
+                    idx,
+                    name,
+                    Some(active_object),
+                    Message::ObjectSelected,
+                ))
+            },
+        );
+        let object_position_row = self
+            .object_position_inputs
+            .iter_mut()
+            .zip(self.object_position.iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (state, value))| {
+                row.push(
+                    TextInput::new(state, "", value, move |value| {
+                        Message::ObjectPositionChanged(axis, value)
+                    })
+                    .width(Length::Units(38))
+                    .padding(2),
+                )
+            });
+        let rotate_object_row = self
+            .rotate_object_buttons
+            .iter_mut()
+            .zip(["X", "Y", "Z"].iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (state, name))| {
+                row.push(
+                    Button::new(state, Text::new(*name).size(14))
+                        .width(Length::Units(38))
+                        .padding(2)
+                        .on_press(Message::ObjectRotatePressed(axis)),
+                )
+            });
+        let rotate_row = axis_buttons(&mut self.rotate_buttons, Message::RotatePressed).push(
+            Button::new(
+                &mut self.turns_button,
+                Text::new(format!("{}°", 90 * self.turns as u16)).size(14),
+            )
+            .padding(2)
+            .on_press(Message::TurnsStepped),
+        );
+        let flip_row = axis_buttons(&mut self.flip_buttons, Message::FlipPressed);
+        let translate_row = self
+            .translate_inputs
+            .iter_mut()
+            .zip(self.translate_offset.iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (state, value))| {
+                row.push(
+                    TextInput::new(state, "0", value, move |value| {
+                        Message::TranslateOffsetChanged(axis, value)
+                    })
+                    .width(Length::Units(38))
+                    .padding(2),
+                )
+            });
+        let filter_row = self
+            .filter_buttons
+            .iter_mut()
+            .zip(
+                [
+                    VoxelFilter::Dilate,
+                    VoxelFilter::Erode,
+                    VoxelFilter::Open,
+                    VoxelFilter::Close,
+                    VoxelFilter::Smooth,
+                ]
+                .iter(),
+            )
+            .fold(Row::new().spacing(5), |row, (state, filter)| {
+                row.push(
+                    Button::new(state, Text::new(format!("{:?}", filter)).size(14))
+                        .padding(2)
+                        .on_press(Message::FilterPressed(*filter)),
+                )
+            });
+        let combine_row = self
+            .combine_buttons
+            .iter_mut()
+            .zip(
+                [
+                    BooleanOp::Union,
+                    BooleanOp::Subtract,
+                    BooleanOp::Intersect,
+                    BooleanOp::Xor,
+                ]
+                .iter(),
+            )
+            .fold(Row::new().spacing(5), |row, (state, op)| {
+                row.push(
+                    Button::new(state, Text::new(format!("{:?}", op)).size(14))
+                        .padding(2)
+                        .on_press(Message::CombinePressed(*op)),
+                )
+            });
+        let combine_source = match self.combine_source.and_then(|idx| self.layers.get(idx)) {
+            Some(layer) => format!("With {}", layer.name),
+            None => String::from("With selection"),
+        };
+        let terrain_dialog = if self.terrain_open {
+            self.terrain_inputs
+                .iter_mut()
+                .zip(self.terrain_values.iter())
+                .zip(["Seed", "Scale", "Octaves", "Lacunarity", "Gain", "Level"].iter())
+                .enumerate()
+                .fold(
+                    Column::new().spacing(5).push(
+                        Button::new(
+                            &mut self.terrain_mode_button,
+                            Text::new(format!("{:?}", self.terrain_mode)).size(14),
+                        )
+                        .padding(2)
+                        .on_press(Message::TerrainModeStepped),
+                    ),
+                    |column, (idx, ((state, value), name))| {
+                        column.push(
+                            Row::new()
+                                .spacing(5)
+                                .push(Text::new(*name).size(14).width(Length::Units(70)))
+                                .push(
+                                    TextInput::new(state, "", value, move |value| {
+                                        Message::TerrainValueChanged(idx, value)
+                                    })
+                                    .width(Length::Units(50))
+                                    .padding(2),
+                                ),
+                        )
+                    },
+                )
+                .push(self.terrain_preview.view())
+                .push(
+                    Button::new(&mut self.generate_button, Text::new("Generate").size(14))
+                        .on_press(Message::GeneratePressed),
+                )
+        } else {
+            Column::new()
+        };
+        let mirror_row = self
+            .symmetry
+            .enabled
+            .iter()
+            .zip(["X", "Y", "Z"].iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (enabled, name))| {
+                row.push(Checkbox::new(*enabled, *name, move |enabled| {
+                    Message::MirrorToggled(axis, enabled)
+                }))
+            });
+        let mirror_plane_row = self
+            .mirror_plane_inputs
+            .iter_mut()
+            .zip(self.mirror_planes.iter())
+            .enumerate()
+            .fold(Row::new().spacing(5), |row, (axis, (state, value))| {
+                row.push(
+                    TextInput::new(state, "mid", value, move |value| {
+                        Message::MirrorPlaneChanged(axis, value)
+                    })
+                    .width(Length::Units(38))
+                    .padding(2),
+                )
+            });
+        let object_buttons = Row::new()
+            .spacing(5)
+            .push(
+                Button::new(&mut self.add_object_button, Text::new("Add").size(14))
+                    .on_press(Message::AddObjectPressed),
+            )
+            .push(
+                Button::new(&mut self.remove_object_button, Text::new("Remove").size(14))
+                    .on_press(Message::RemoveObjectPressed),
+            );
+        let crop_warning = match self.crop_warning {
+            Some((_, count)) => Text::new(format!(
+                "Resizing deletes {} voxels, press Resize canvas again to crop",
+                count
+            ))
+            .size(14)
+            .color(Color::new(1.0, 0.0, 0.0, 1.0)),
+            None => Text::new(""),
+        };
         let edit_bar = EditOp::ALL
             .iter()
             .fold(
@@ -233,14 +1477,187 @@ impl Program for Controls {
                     ))
                 },
             )
+            .push(Checkbox::new(
+                self.fill_diagonals,
+                "Fill diagonals",
+                Message::FillDiagonalsToggled,
+            ))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Button::new(
+                            &mut self.shape_axis_button,
+                            Text::new(format!("Axis {}", ["X", "Y", "Z"][self.shape_axis]))
+                                .size(14),
+                        )
+                        .padding(2)
+                        .on_press(Message::ShapeAxisStepped),
+                    )
+                    .push(Checkbox::new(self.hollow, "Hollow", Message::HollowToggled)),
+            )
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(Text::new("Box walls").size(14))
+                    .push(
+                        TextInput::new(
+                            &mut self.wall_thickness_input,
+                            "0",
+                            &self.wall_thickness,
+                            Message::WallThicknessChanged,
+                        )
+                        .width(Length::Units(38))
+                        .padding(2),
+                    ),
+            )
+            .push(Text::new("Symmetry"))
+            .push(mirror_row)
+            .push(Text::new("Mirror planes (X, Y, Z)").size(14))
+            .push(mirror_plane_row)
             .push(Text::new("Pick a color"))
             .push(self.color_picker.view())
             .push(Text::new("Draw color"))
             .push(self.picked_color.view())
+            .push(channel_sliders)
+            .push(
+                Button::new(&mut self.add_color_button, Text::new("Add color"))
+                    .on_press(Message::AddColorPressed),
+            )
+            .push(Text::new("Material"))
+            .push(material_sliders)
+            .push(Text::new("Objects"))
+            .push(object_list)
+            .push(object_buttons)
+            .push(Text::new("Position (X, Y, Z)").size(14))
+            .push(object_position_row)
+            .push(Text::new("Rotate around").size(14))
+            .push(rotate_object_row)
+            .push(Text::new("Transform selection or model"))
+            .push(Text::new("Rotate around").size(14))
+            .push(rotate_row)
+            .push(Text::new("Flip along").size(14))
+            .push(flip_row)
+            .push(Text::new("Move by (X, Y, Z)").size(14))
+            .push(translate_row)
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Button::new(&mut self.translate_button, Text::new("Move").size(14))
+                            .on_press(Message::TranslatePressed),
+                    )
+                    .push(Checkbox::new(self.wrap, "Wrap", Message::WrapToggled)),
+            )
+            .push(Checkbox::new(
+                self.grow,
+                "Grow canvas",
+                Message::GrowToggled,
+            ))
+            .push(Text::new("Shell thickness"))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        TextInput::new(
+                            &mut self.shell_thickness_input,
+                            "1",
+                            &self.shell_thickness,
+                            Message::ShellThicknessChanged,
+                        )
+                        .width(Length::Units(38))
+                        .padding(2),
+                    )
+                    .push(
+                        Button::new(&mut self.hollow_model_button, Text::new("Hollow").size(14))
+                            .on_press(Message::HollowModelPressed),
+                    )
+                    .push(
+                        Button::new(
+                            &mut self.thicken_model_button,
+                            Text::new("Thicken").size(14),
+                        )
+                        .on_press(Message::ThickenModelPressed),
+                    ),
+            )
+            .push(Text::new("Filter selection or model"))
+            .push(filter_row)
+            .push(Text::new("Combine with the active layer"))
+            .push(
+                Row::new()
+                    .spacing(5)
+                    .push(
+                        Button::new(
+                            &mut self.combine_source_button,
+                            Text::new(combine_source).size(14),
+                        )
+                        .padding(2)
+                        .on_press(Message::CombineSourceStepped),
+                    )
+                    .push(Checkbox::new(
+                        self.source_color_wins,
+                        "Source color wins",
+                        Message::SourceColorToggled,
+                    )),
+            )
+            .push(combine_row)
+            .push(
+                Button::new(&mut self.terrain_button, Text::new("Terrain generator"))
+                    .on_press(Message::TerrainPressed),
+            )
+            .push(terrain_dialog)
+            .push(Text::new("Layers"))
+            .push(layer_list)
+            .push(layer_buttons)
+            .push(Checkbox::new(
+                self.greedy_meshing,
+                "Greedy meshing",
+                Message::GreedyMeshingToggled,
+            ))
+            .push(Text::new("Canvas size (X, Y, Z)"))
+            .push(new_size_row)
+            .push(Button::new(&mut self.new_button, Text::new("New")).on_press(Message::NewPressed))
+            .push(Text::new("Resize anchors"))
+            .push(anchor_row)
+            .push(
+                Button::new(&mut self.resize_button, Text::new("Resize canvas"))
+                    .on_press(Message::ResizePressed),
+            )
+            .push(crop_warning)
+            .push(
+                Button::new(&mut self.open_button, Text::new("Open"))
+                    .on_press(Message::OpenPressed),
+            )
+            .push(
+                Button::new(&mut self.save_button, Text::new("Save"))
+                    .on_press(Message::SavePressed),
+            )
+            .push(
+                Button::new(&mut self.save_as_button, Text::new("Save As"))
+                    .on_press(Message::SaveAsPressed),
+            )
+            .push(
+                Button::new(&mut self.import_button, Text::new("Import .vox"))
+                    .on_press(Message::ImportPressed),
+            )
             .push(
                 Button::new(&mut self.export_button, Text::new("Export as .obj"))
                     .on_press(Message::ExportPressed),
-            );
+            )
+            .push(Checkbox::new(
+                self.obj_color_mode == ColorMode::VertexColor,
+                "Vertex colors",
+                Message::VertexColorsToggled,
+            ))
+            .push(
+                Button::new(&mut self.vox_export_button, Text::new("Export as .vox"))
+                    .on_press(Message::VoxExportPressed),
+            )
+            .push(Checkbox::new(
+                self.separate_objects,
+                "Separate objects",
+                Message::SeparateObjectsToggled,
+            ));
 
         Container::new(edit_bar)
             .width(Length::Units(150))
@@ -279,7 +1696,7 @@ impl canvas::Program<Message> for ColorPicker {
                         let y_pos = y_dist as usize / COLOR_SIZE as usize;
                         let idx = y_pos * Self::COLORS_PER_LINE + x_pos;
                         if idx < self.colors.len() {
-                            return Some(Message::ColorPicked(self.colors[idx]));
+                            return Some(Message::ColorPicked(idx));
                         }
                     }
                 }
@@ -300,6 +1717,16 @@ impl canvas::Program<Message> for PickedColor {
     }
 }
 
+impl canvas::Program<Message> for TerrainPreview {
+    fn draw(&self, bounds: Rectangle, _cursor: canvas::Cursor) -> Vec<canvas::Geometry> {
+        let theme = self.canvas_cache.draw(bounds.size(), |frame| {
+            self.draw(frame);
+        });
+
+        vec![theme]
+    }
+}
+
 struct UiStyle {}
 impl StyleSheet for UiStyle {
     fn style(&self) -> Style {
@@ -1,17 +1,35 @@
 use crate::camera::CameraWrapper;
-use crate::controls::EditOp;
+use crate::color::{DEFAULT_PALETTE, HALF_ALPHA_YELLOW};
+use crate::controls::{
+    CombineRequest, EditOp, LayerEntry, LayerRequest, Message, ObjectRequest, ResizeRequest,
+    ShellRequest, TerrainRequest, MAX_CANVAS_SIZE,
+};
+use crate::fill::{self, MAX_FILL_CELLS};
 use crate::fps::FpsCounter;
 use crate::geometry::*;
+use crate::history::{Command, History, DEFAULT_HISTORY_MEMORY};
+use crate::material::Material;
+use crate::obj::{self, ColorMode, MTL_EXTENSION};
+use crate::project;
 use crate::renderer::{Renderer, DEFAULT_MESH_COUNT};
+use crate::scene::Scene;
+use crate::selection::{CellEdits, Clipboard, Selection};
+use crate::shape::{self, Shape};
+use crate::symmetry::Symmetry;
+use crate::terrain::{self, TerrainParams, PREVIEW_COLUMNS};
 use crate::ui::Ui;
-use crate::vertex::VoxelVertex;
-use crate::voxel_manager::VoxelManager;
+use crate::vertex::MeshVertex;
+use crate::vox;
+use crate::voxel_manager::{GridBounds, VoxelFilter, VoxelManager, VoxelTransform};
 use cgmath::Vector3;
 use futures::executor::block_on;
 use iced_wgpu::wgpu;
+use iced_winit::Color;
 use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufWriter;
+use std::io::{BufReader, BufWriter};
+use std::iter;
+use std::mem;
+use std::path::Path;
 use std::time;
 
 use winit::{
@@ -29,21 +47,45 @@ enum EditorState {
 pub struct Editor {
     window: winit::window::Window,
     camera: CameraWrapper,
-    voxel_manager: VoxelManager,
+    scene: Scene,
+    /// The edit history of every object of the scene
+    histories: Vec<History>,
     renderer: Renderer,
     ui: Ui,
     state: EditorState,
     cursor_ray: Ray,
+    modifiers: event::ModifiersState,
+    /// The selected voxels of the active layer
+    selection: Option<Selection>,
+    clipboard: Option<Clipboard>,
+    /// The drag started on the selection, so it moves the selection instead of selecting
+    drag_selection: bool,
+}
+
+/// The cells of the walls of a drawn box with the given thickness
+fn box_walls(bbox: &BoundingBox, wall: usize) -> Vec<[i32; 3]> {
+    let (start, end) = shape::drag_ends(bbox);
+    shape::box_walls(start, end, wall as i32)
 }
 
 impl Editor {
     fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         self.renderer.resize(size, &mut self.camera);
-        self.ui = Ui::new(&self.window, self.renderer.device_mut())
+        self.ui = Ui::new(&self.window, self.renderer.device_mut());
+        self.update_palette();
+        self.update_layers();
+        self.update_objects();
     }
 
-    pub fn get_model_data(&self) -> (Vec<VoxelVertex>, Vec<u32>) {
-        self.voxel_manager.vertices()
+    /// The mesh of the scene, with the name and triangle count of every object
+    pub fn get_model_data(&self) -> (Vec<MeshVertex>, Vec<u32>, Vec<(String, usize)>) {
+        self.scene.scene_mesh()
+    }
+
+    /// Applies the command to the active object and records it in the object's history
+    fn apply(&mut self, command: Command) {
+        let active = self.scene.active();
+        self.histories[active].apply(command, self.scene.voxel_manager_mut());
     }
 
     fn update(&mut self, event: winit::event::WindowEvent) {
@@ -57,6 +99,8 @@ impl Editor {
             match state {
                 event::ElementState::Pressed => {
                     self.state = EditorState::Edit;
+                    self.drag_selection = self.ui.controls().edit_op() == EditOp::Select
+                        && self.points_at_selection();
                 }
                 event::ElementState::Released => {
                     self.state = EditorState::EditFinished;
@@ -75,6 +119,63 @@ impl Editor {
         {
             self.ui.controls().step_edit_op();
         };
+        if let event::WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = modifiers;
+        }
+        if let event::WindowEvent::KeyboardInput {
+            input:
+                event::KeyboardInput {
+                    virtual_keycode: Some(key),
+                    state: event::ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        {
+            if self.modifiers.ctrl() && self.state == EditorState::ChangeView {
+                match key {
+                    event::VirtualKeyCode::C => self.copy_selection(),
+                    event::VirtualKeyCode::V => self.paste(),
+                    _ => {}
+                }
+                let history = &mut self.histories[self.scene.active()];
+                let voxel_manager = self.scene.voxel_manager_mut();
+                let changed = match key {
+                    event::VirtualKeyCode::Z if self.modifiers.shift() => {
+                        history.redo(voxel_manager)
+                    }
+                    event::VirtualKeyCode::Z => history.undo(voxel_manager),
+                    event::VirtualKeyCode::Y => history.redo(voxel_manager),
+                    _ => false,
+                };
+                if changed {
+                    self.drop_selection();
+                    self.update_canvas();
+                    self.update_palette();
+                    self.update_layers();
+                }
+            } else if self.state == EditorState::ChangeView && self.selection.is_some() {
+                // The arrows move the selection on the ground, the page keys move it up and down
+                let offset = match key {
+                    event::VirtualKeyCode::Left => Some([-1, 0, 0]),
+                    event::VirtualKeyCode::Right => Some([1, 0, 0]),
+                    event::VirtualKeyCode::Up => Some([0, 0, -1]),
+                    event::VirtualKeyCode::Down => Some([0, 0, 1]),
+                    event::VirtualKeyCode::PageUp => Some([0, 1, 0]),
+                    event::VirtualKeyCode::PageDown => Some([0, -1, 0]),
+                    _ => None,
+                };
+                match key {
+                    event::VirtualKeyCode::Delete => self.delete_selection(),
+                    event::VirtualKeyCode::Return => self.drop_selection(),
+                    _ => {
+                        if let Some(offset) = offset {
+                            self.move_selection(offset);
+                        }
+                    }
+                }
+            }
+        };
 
         if let event::WindowEvent::CursorMoved { position, .. } = event {
             self.cursor_ray.from_cursor(
@@ -95,12 +196,23 @@ impl Editor {
         self.renderer
             .cursor_helper(Some(self.cursor_ray.origin), self.cursor_ray.end);
 
-        let (erase_box, draw_box) = self.voxel_manager.get_intersection_boxes(&self.cursor_ray);
+        let (erase_box, draw_box) = self
+            .scene
+            .voxel_manager()
+            .get_intersection_boxes(&self.cursor_ray);
+        #[cfg(feature = "debug_ray")]
+        self.renderer.debug_hit(erase_box.as_ref().map(|bbox| {
+            [
+                bbox.corner.x as usize,
+                bbox.corner.y as usize,
+                bbox.corner.z as usize,
+            ]
+        }));
         #[cfg(feature = "debug_ray")]
         let mut closest_plane_name = "None";
         let mut closest_plane = None;
         let mut intersection_point = Vector3::new(0.0, 0.0, 0.0);
-        let mesh_count = DEFAULT_MESH_COUNT as f32;
+        let [size_x, size_y, size_z] = self.scene.voxel_manager().extent();
         if erase_box.is_none() {
             for plane in [XY_PLANE, YZ_PLANE, XZ_PLANE].iter() {
                 if let Some(point) = self.cursor_ray.plane_intersection(plane) {
@@ -110,11 +222,11 @@ impl Editor {
                         plane.name,
                         point
                     );
-                    if point.x <= mesh_count
+                    if point.x <= size_x as f32
                         && point.x >= 0.0
-                        && point.y <= mesh_count
+                        && point.y <= size_y as f32
                         && point.y >= 0.0
-                        && point.z <= mesh_count
+                        && point.z <= size_z as f32
                         && point.z >= 0.0
                     {
                         intersection_point = point;
@@ -158,51 +270,520 @@ impl Editor {
             EditorState::ChangeView => {
                 if let Some(bbox) = erase_box {
                     match self.ui.controls().edit_op() {
-                        EditOp::Draw => self.renderer.update_cursor_pos(draw_box.unwrap()),
-                        EditOp::Erase => self.renderer.update_cursor_pos(bbox),
-                        EditOp::Refill => self.renderer.update_cursor_pos(bbox),
+                        EditOp::Draw
+                        | EditOp::Bucket
+                        | EditOp::Line
+                        | EditOp::Sphere
+                        | EditOp::Ellipsoid
+                        | EditOp::Cylinder
+                        | EditOp::Cone => match draw_box {
+                            Some(draw_box) => self.renderer.update_cursor_pos(draw_box),
+                            // The pointed face is on the border of the grid
+                            None => self.renderer.hide_cursor(),
+                        },
+                        EditOp::Erase | EditOp::Refill | EditOp::Select | EditOp::Fill => {
+                            self.renderer.update_cursor_pos(bbox)
+                        }
                     };
                 } else {
                     self.renderer
                         .update_cursor_pos_on_plane(intersection_point, closest_plane);
                 }
             }
+            // The fill tools start from the clicked voxel, there is no box to drag
+            EditorState::Edit if self.ui.controls().edit_op().is_fill() => {}
             EditorState::Edit => {
                 if let Some(bbox) = erase_box {
                     match self.ui.controls().edit_op() {
-                        EditOp::Draw => self.renderer.update_draw_rectangle(draw_box.unwrap()),
-                        EditOp::Erase => self.renderer.update_draw_rectangle(bbox),
-                        EditOp::Refill => self.renderer.update_draw_rectangle(bbox),
+                        EditOp::Draw
+                        | EditOp::Line
+                        | EditOp::Sphere
+                        | EditOp::Ellipsoid
+                        | EditOp::Cylinder
+                        | EditOp::Cone => {
+                            if let Some(draw_box) = draw_box {
+                                self.renderer.update_draw_rectangle(draw_box)
+                            }
+                        }
+                        EditOp::Erase | EditOp::Refill | EditOp::Select => {
+                            self.renderer.update_draw_rectangle(bbox)
+                        }
+                        EditOp::Fill | EditOp::Bucket => {}
                     };
                 } else {
                     self.renderer
                         .update_draw_rectangle_on_plane(intersection_point, closest_plane);
                 }
+                if self.drag_selection {
+                    self.preview_selection_drag();
+                }
+                let edit_op = self.ui.controls().edit_op();
+                let wall = self.ui.controls().wall_thickness();
+                if let Some(shape) = edit_op.shape() {
+                    let cells = self.shape_cells(shape);
+                    self.renderer.show_shape(&cells);
+                } else if edit_op == EditOp::Draw && wall > 0 {
+                    let cells = self
+                        .renderer
+                        .draw_rectangle()
+                        .map(|bbox| box_walls(&bbox, wall))
+                        .unwrap_or_default();
+                    self.renderer.show_shape(&cells);
+                }
+            }
+            EditorState::EditFinished if self.ui.controls().edit_op() == EditOp::Select => {
+                self.finish_select();
+                self.state = EditorState::ChangeView;
+            }
+            EditorState::EditFinished if self.ui.controls().edit_op().shape().is_some() => {
+                self.draw_shape();
+                self.state = EditorState::ChangeView;
+            }
+            EditorState::EditFinished if self.ui.controls().edit_op().is_fill() => {
+                // Drops a rectangle left over from switching the tool while dragging
+                self.renderer.take_draw_rectangle([0.0; 4]);
+                if let Some(bbox) = erase_box {
+                    self.fill(bbox, draw_box);
+                }
+                self.state = EditorState::ChangeView;
             }
             EditorState::EditFinished => {
-                match self.ui.controls().edit_op() {
-                    EditOp::Draw => {
-                        let c = self.ui.controls().draw_color();
-                        self.renderer
-                            .draw_rectangle([c.r, c.g, c.b, c.a], &mut self.voxel_manager)
+                let c = self.ui.controls().draw_color();
+                let color = [c.r, c.g, c.b, c.a];
+                let locked = self.active_layer_locked();
+                match self.renderer.take_draw_rectangle(color) {
+                    Some(_) if locked => {
+                        println!("Failed to edit reason: the active layer is locked")
                     }
-                    EditOp::Erase => self.renderer.erase_rectangle(&mut self.voxel_manager),
-                    EditOp::Refill => {
-                        let c = self.ui.controls().draw_color();
-                        self.renderer
-                            .fill_rectangle([c.r, c.g, c.b, c.a], &mut self.voxel_manager)
+                    Some(cube) => {
+                        let palette_len = self.scene.voxel_manager().palette().len();
+                        let idx = self.draw_palette_index(color);
+                        let edit_op = self.ui.controls().edit_op();
+                        let wall = self.ui.controls().wall_thickness();
+                        let extent = self.scene.voxel_manager().extent();
+                        let mirrored = self.renderer.symmetry().mirrored_boxes(&cube, extent);
+                        let mut commands: Vec<_> = iter::once(cube)
+                            .chain(mirrored)
+                            .map(|cube| match edit_op {
+                                EditOp::Draw if wall > 0 => Command::SetCells(
+                                    shape::grid_cells(&box_walls(&cube, wall), extent)
+                                        .into_iter()
+                                        .map(|cell| (cell, Some(idx)))
+                                        .collect(),
+                                ),
+                                EditOp::Draw => Command::AddBox(cube, idx),
+                                EditOp::Erase => Command::EraseBox(cube),
+                                EditOp::Refill => Command::Refill(cube, idx),
+                                _ => unreachable!("Only the box tools edit boxes"),
+                            })
+                            .collect();
+                        // The mirrored boxes are undone together with the box
+                        let command = match commands.len() {
+                            1 => commands.remove(0),
+                            _ => Command::Group(commands),
+                        };
+                        self.apply(command);
+                        self.drop_selection();
+                        self.renderer.update_instances(&mut self.scene);
+                        if self.scene.voxel_manager().palette().len() != palette_len {
+                            self.update_palette();
+                        }
                     }
-                };
+                    None => {}
+                }
                 self.state = EditorState::ChangeView;
             }
         }
     }
 
+    /// Palette index of the draw color, the color is added to the palette if it's new
+    fn draw_palette_index(&mut self, color: [f32; 4]) -> u16 {
+        let palette_len = self.scene.voxel_manager().palette().len();
+        match self.ui.controls().draw_index() {
+            idx if idx < palette_len => idx as u16,
+            _ => self.scene.voxel_manager_mut().color_index(color),
+        }
+    }
+
+    /// Palette index `draw_palette_index` would return, without adding the color yet
+    fn peek_draw_palette_index(&self, color: [f32; 4]) -> u16 {
+        let palette_len = self.scene.voxel_manager().palette().len();
+        match self.ui.controls().draw_index() {
+            idx if idx < palette_len => idx as u16,
+            _ => self.scene.voxel_manager().peek_color_index(color),
+        }
+    }
+
+    /// The cells of the shape fitted to the rectangle being drawn
+    fn shape_cells(&self, shape: Shape) -> Vec<[i32; 3]> {
+        match self.renderer.draw_rectangle() {
+            Some(bbox) => {
+                let (start, end) = shape::drag_ends(&bbox);
+                shape.rasterize(start, end, self.ui.controls().shape_options())
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Fills the cells of the shape drawn with the shape tool
+    fn draw_shape(&mut self) {
+        let shape = match self.ui.controls().edit_op().shape() {
+            Some(shape) => shape,
+            None => return,
+        };
+        let cells = self.shape_cells(shape);
+        self.renderer.take_draw_rectangle([0.0; 4]);
+        if cells.is_empty() {
+            return;
+        }
+        if self.active_layer_locked() {
+            println!("Failed to draw shape reason: the active layer is locked");
+            return;
+        }
+        let c = self.ui.controls().draw_color();
+        let color = [c.r, c.g, c.b, c.a];
+        let palette_len = self.scene.voxel_manager().palette().len();
+        let idx = self.draw_palette_index(color);
+        let extent = self.scene.voxel_manager().extent();
+        let edits: CellEdits = shape::grid_cells(&cells, extent)
+            .into_iter()
+            .map(|cell| (cell, Some(idx)))
+            .collect();
+        self.apply(Command::SetCells(edits));
+        self.drop_selection();
+        self.renderer.update_instances(&mut self.scene);
+        if self.scene.voxel_manager().palette().len() != palette_len {
+            self.update_palette();
+        }
+    }
+
+    /// Filters the selected voxels, or the active layer if nothing is selected. The selection
+    /// keeps its box.
+    fn filter(&mut self, filter: VoxelFilter) {
+        if self.active_layer_locked() {
+            println!("Failed to filter reason: the active layer is locked");
+            return;
+        }
+        let region = self
+            .selection
+            .as_ref()
+            .map(|selection| (selection.corner(), selection.extent()));
+        let edits = self.scene.voxel_manager().filter(filter, region);
+        if edits.is_empty() {
+            return;
+        }
+        self.apply(Command::SetCells(edits));
+        self.renderer.update_instances(&mut self.scene);
+        if let Some((corner, extent)) = region {
+            let selection = Selection::from_cells(self.scene.voxel_manager(), corner, extent);
+            self.set_selection(selection);
+        }
+    }
+
+    /// Hollows out the model or thickens its shell on the active layer
+    fn edit_shell(&mut self, request: ShellRequest) {
+        if self.active_layer_locked() {
+            println!("Failed to edit shell reason: the active layer is locked");
+            return;
+        }
+        let voxel_manager = self.scene.voxel_manager();
+        let edits = match request {
+            ShellRequest::Hollow(thickness) => voxel_manager.hollow(thickness),
+            ShellRequest::Thicken(thickness) => voxel_manager.thicken(thickness),
+        };
+        if edits.is_empty() {
+            return;
+        }
+        self.apply(Command::SetCells(edits));
+        self.drop_selection();
+        self.renderer.update_instances(&mut self.scene);
+    }
+
+    /// Replaces the active layer with its combination with another layer or the selection
+    fn combine(&mut self, request: CombineRequest) {
+        if self.active_layer_locked() {
+            println!("Failed to combine reason: the active layer is locked");
+            return;
+        }
+        let voxel_manager = self.scene.voxel_manager();
+        let (target, source) = match request.source {
+            Some(idx) => (
+                voxel_manager.layers()[voxel_manager.active_layer()].voxels(),
+                voxel_manager.layers()[idx].voxels(),
+            ),
+            None => match &self.selection {
+                Some(selection) => selection.split(voxel_manager),
+                None => {
+                    println!("Failed to combine reason: nothing is selected");
+                    return;
+                }
+            },
+        };
+        let combined = request.op.combine(&target, &source, request.overlap);
+        let edits = voxel_manager.layer_edits(&combined);
+        self.drop_selection();
+        if edits.is_empty() {
+            return;
+        }
+        self.apply(Command::SetCells(edits));
+        self.renderer.update_instances(&mut self.scene);
+    }
+
+    /// Shows the terrain seen from above in the generator dialog
+    fn preview_terrain(&mut self, params: TerrainParams) {
+        let voxel_manager = self.scene.voxel_manager();
+        let extent = voxel_manager.extent();
+        let palette = voxel_manager.palette();
+        let bands = match terrain::palette_bands(palette) {
+            Some(bands) => bands,
+            None => {
+                println!("Failed to preview terrain reason: the palette is empty");
+                self.ui
+                    .queue_message(Message::TerrainPreviewChanged(Vec::new(), 0));
+                return;
+            }
+        };
+        let step = (extent[0].max(extent[2]) + PREVIEW_COLUMNS - 1) / PREVIEW_COLUMNS;
+        let columns = terrain::top_view(&params, extent, bands, step)
+            .into_iter()
+            .map(|idx| {
+                idx.map(|idx| {
+                    let c = palette[idx as usize];
+                    Color::new(c[0], c[1], c[2], c[3])
+                })
+            })
+            .collect();
+        let width = (extent[0] + step - 1) / step;
+        self.ui
+            .queue_message(Message::TerrainPreviewChanged(columns, width));
+    }
+
+    /// Replaces the active layer with terrain colored by the palette from bottom to top
+    fn generate_terrain(&mut self, params: TerrainParams) {
+        if self.active_layer_locked() {
+            println!("Failed to generate terrain reason: the active layer is locked");
+            return;
+        }
+        let voxel_manager = self.scene.voxel_manager();
+        let bands = match terrain::palette_bands(voxel_manager.palette()) {
+            Some(bands) => bands,
+            None => {
+                println!("Failed to generate terrain reason: the palette is empty");
+                return;
+            }
+        };
+        let voxels = terrain::generate(&params, voxel_manager.extent(), bands);
+        let edits = voxel_manager.layer_edits(&voxels);
+        self.drop_selection();
+        if edits.is_empty() {
+            return;
+        }
+        self.apply(Command::SetCells(edits));
+        self.renderer.update_instances(&mut self.scene);
+    }
+
+    /// Flood fills from the pointed voxel, or fills the enclosed region in front of it with
+    /// the bucket
+    fn fill(&mut self, pointed: BoundingBox, front: Option<BoundingBox>) {
+        if self.active_layer_locked() {
+            println!("Failed to fill reason: the active layer is locked");
+            return;
+        }
+        let cell = |bbox: &BoundingBox| {
+            [
+                bbox.corner.x as usize,
+                bbox.corner.y as usize,
+                bbox.corner.z as usize,
+            ]
+        };
+        let c = self.ui.controls().draw_color();
+        let color = [c.r, c.g, c.b, c.a];
+        // The color is only added to the palette if the fill changes something
+        let idx = self.peek_draw_palette_index(color);
+        let voxel_manager = self.scene.voxel_manager();
+        let edits = match self.ui.controls().edit_op() {
+            EditOp::Bucket => match front {
+                Some(front) => {
+                    fill::fill_enclosed(voxel_manager, cell(&front), idx, MAX_FILL_CELLS)
+                }
+                // The pointed face is on the border of the grid
+                None => Ok(Vec::new()),
+            },
+            _ => fill::flood_fill(
+                voxel_manager,
+                cell(&pointed),
+                idx,
+                self.ui.controls().fill_connectivity(),
+                MAX_FILL_CELLS,
+            ),
+        };
+        match edits {
+            Err(e) => println!("Failed to fill reason: {}", e),
+            Ok(edits) if edits.is_empty() => {}
+            Ok(edits) => {
+                let palette_len = self.scene.voxel_manager().palette().len();
+                self.draw_palette_index(color);
+                self.apply(Command::SetCells(edits));
+                self.drop_selection();
+                self.renderer.update_instances(&mut self.scene);
+                if self.scene.voxel_manager().palette().len() != palette_len {
+                    self.update_palette();
+                }
+            }
+        }
+    }
+
+    fn active_layer_locked(&self) -> bool {
+        let voxel_manager = self.scene.voxel_manager();
+        voxel_manager.layers()[voxel_manager.active_layer()].locked
+    }
+
+    /// Whether the cursor points at a voxel inside the selection
+    fn points_at_selection(&self) -> bool {
+        let (pointed, _) = self
+            .scene
+            .voxel_manager()
+            .get_intersection_boxes(&self.cursor_ray);
+        match (&self.selection, pointed) {
+            (Some(selection), Some(bbox)) => selection.contains([
+                bbox.corner.x as usize,
+                bbox.corner.y as usize,
+                bbox.corner.z as usize,
+            ]),
+            _ => false,
+        }
+    }
+
+    /// Forgets the selection, its voxels stay where they are
+    fn drop_selection(&mut self) {
+        self.selection = None;
+        self.renderer.show_selection(None);
+    }
+
+    fn set_selection(&mut self, selection: Selection) {
+        self.renderer.show_selection(Some(selection.bounding_box()));
+        self.selection = Some(selection);
+    }
+
+    /// Shows where the selection would be moved if the drag ended here
+    fn preview_selection_drag(&mut self) {
+        if let (Some(selection), Some(offset)) =
+            (&self.selection, self.renderer.draw_rectangle_offset())
+        {
+            let mut bbox = selection.bounding_box();
+            bbox.corner += Vector3::new(offset[0] as f32, offset[1] as f32, offset[2] as f32);
+            self.renderer.hide_cursor();
+            self.renderer.show_selection(Some(bbox));
+        }
+    }
+
+    /// Selects the voxels of the dragged box, or moves the selection if it was dragged
+    fn finish_select(&mut self) {
+        let offset = self.renderer.draw_rectangle_offset();
+        let cube = self.renderer.take_draw_rectangle(HALF_ALPHA_YELLOW);
+        if mem::replace(&mut self.drag_selection, false) {
+            self.move_selection(offset.unwrap_or([0; 3]));
+        } else if let Some(cube) = cube {
+            let selection = Selection::from_box(self.scene.voxel_manager(), &cube);
+            self.set_selection(selection);
+        }
+    }
+
+    /// Moves the selected voxels, the move can be undone
+    fn move_selection(&mut self, offset: [i32; 3]) {
+        if offset != [0; 3] && self.active_layer_locked() {
+            println!("Failed to move selection reason: the active layer is locked");
+        } else if let Some(ref mut selection) = self.selection {
+            match selection.translate(offset, self.scene.voxel_manager()) {
+                Some(cells) => {
+                    self.apply(Command::SetCells(cells));
+                    self.renderer.update_instances(&mut self.scene);
+                }
+                None => println!("Failed to move selection reason: it would leave the canvas"),
+            }
+        }
+        let bbox = self
+            .selection
+            .as_ref()
+            .map(|selection| selection.bounding_box());
+        self.renderer.show_selection(bbox);
+    }
+
+    fn delete_selection(&mut self) {
+        if self.active_layer_locked() {
+            println!("Failed to delete selection reason: the active layer is locked");
+            return;
+        }
+        if let Some(selection) = self.selection.take() {
+            self.apply(Command::SetCells(selection.delete()));
+            self.renderer.update_instances(&mut self.scene);
+            self.renderer.show_selection(None);
+        }
+    }
+
+    fn copy_selection(&mut self) {
+        if let Some(ref selection) = self.selection {
+            self.clipboard = Some(selection.copy(self.scene.voxel_manager()));
+        }
+    }
+
+    /// Pastes the copied voxels over the selection, or where they were copied from. The
+    /// pasted voxels are selected, so they can be moved around.
+    fn paste(&mut self) {
+        if self.active_layer_locked() {
+            println!("Failed to paste reason: the active layer is locked");
+            return;
+        }
+        let corner = self.selection.as_ref().map(|selection| selection.corner());
+        let palette_len = self.scene.voxel_manager().palette().len();
+        let (selection, cells) = match self.clipboard {
+            Some(ref clipboard) => clipboard.paste(self.scene.voxel_manager_mut(), corner),
+            None => return,
+        };
+        self.apply(Command::SetCells(cells));
+        self.renderer.update_instances(&mut self.scene);
+        if self.scene.voxel_manager().palette().len() != palette_len {
+            self.update_palette();
+        }
+        self.set_selection(selection);
+    }
+
+    /// Rotates, flips or moves the selected voxels, or the whole model if nothing is
+    /// selected. The selection follows its voxels.
+    fn transform(&mut self, transform: VoxelTransform, bounds: GridBounds) {
+        let region = self
+            .selection
+            .as_ref()
+            .map(|selection| (selection.corner(), selection.extent()));
+        if region.is_some() && self.active_layer_locked() {
+            println!("Failed to transform selection reason: the active layer is locked");
+            return;
+        }
+        let target = self
+            .scene
+            .voxel_manager()
+            .transformed_box(transform, region, bounds);
+        self.apply(Command::Transform {
+            transform,
+            region,
+            bounds,
+        });
+        match (region, target) {
+            (Some(_), Some((corner, extent))) => {
+                let selection = Selection::from_cells(self.scene.voxel_manager(), corner, extent);
+                self.set_selection(selection);
+            }
+            (Some(_), None) => self.drop_selection(),
+            (None, _) => {}
+        }
+        self.update_canvas();
+    }
+
     fn redraw(&mut self) {
         let mouse_interaction = self.renderer.render(
             &mut self.ui,
             #[cfg(feature = "debug_ray")]
-            &mut self.voxel_manager,
+            &self.scene,
         );
         // Update the mouse cursor
         self.window
@@ -210,56 +791,304 @@ impl Editor {
     }
 
     fn save_vertices(&self, file_path: String) -> std::io::Result<()> {
-        let (vertex_data, indices) = self.get_model_data();
+        let (vertex_data, indices, mut objects) = self.get_model_data();
+        if !self.ui.controls().separate_objects() {
+            objects.clear();
+        }
+        let color_mode = self.ui.controls().obj_color_mode();
+        let mtl_path = Path::new(&file_path).with_extension(MTL_EXTENSION);
+        if color_mode == ColorMode::Material {
+            let (materials, _) = obj::materials(&vertex_data, &indices);
+            let mut buffer = BufWriter::new(File::create(&mtl_path)?);
+            obj::write_mtl(&mut buffer, &materials)?;
+        }
+
+        let mtl_file_name = mtl_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
         let mut buffer = BufWriter::new(File::create(&file_path)?);
+        obj::write_obj(
+            &mut buffer,
+            &vertex_data,
+            &indices,
+            color_mode,
+            mtl_file_name,
+            &objects,
+        )
+    }
+
+    fn save_project(&self, file_path: String) -> std::io::Result<()> {
+        let mut buffer = BufWriter::new(File::create(&file_path)?);
+        project::save(&mut buffer, &self.scene, &self.camera.state())
+    }
+
+    /// Uploads the voxels, and rebuilds the canvas if the size of the active object's grid
+    /// has changed
+    fn update_canvas(&mut self) {
+        let [x, y, z] = self.scene.voxel_manager().extent();
+        let dimensions = [x as u16, y as u16, z as u16];
+        if dimensions != self.renderer.dimensions() {
+            self.renderer.set_dimensions(dimensions);
+            self.camera.frame([x as f32, y as f32, z as f32]);
+            self.renderer.update_view(&mut self.camera);
+        }
+        self.renderer.update_instances(&mut self.scene);
+    }
+
+    /// Shows the palette of the document in the color picker and the material editor
+    fn update_palette(&mut self) {
+        let colors = self
+            .scene
+            .voxel_manager()
+            .palette()
+            .iter()
+            .map(|c| Color::new(c[0], c[1], c[2], c[3]))
+            .collect();
+        let materials = self.scene.voxel_manager().materials().to_vec();
+        self.ui
+            .queue_message(Message::PaletteChanged(colors, materials));
+    }
+
+    /// Changes or adds a palette entry, which recolors every voxel using it
+    fn edit_palette(&mut self, idx: usize, color: [f32; 4]) {
+        let palette_len = self.scene.voxel_manager().palette().len();
+        if idx < palette_len {
+            self.apply(Command::SetPaletteColor {
+                index: idx as u16,
+                color,
+            });
+            self.renderer.update_instances(&mut self.scene);
+        } else if palette_len < u16::MAX as usize {
+            self.apply(Command::AddPaletteColor {
+                index: palette_len as u16,
+                color,
+            });
+        } else {
+            println!("Failed to add color reason: the palette is full");
+        }
+    }
+
+    fn edit_material(&mut self, idx: usize, material: Material) {
+        if idx < self.scene.voxel_manager().palette().len() {
+            self.apply(Command::SetMaterial {
+                index: idx as u16,
+                material,
+            });
+            self.renderer.update_instances(&mut self.scene);
+        }
+    }
+
+    /// Shows the layers of the document in the layer list
+    fn update_layers(&mut self) {
+        let voxel_manager = self.scene.voxel_manager();
+        let layers = voxel_manager
+            .layers()
+            .iter()
+            .map(|layer| LayerEntry {
+                name: layer.name.clone(),
+                visible: layer.visible,
+                locked: layer.locked,
+            })
+            .collect();
+        self.ui
+            .queue_message(Message::LayersChanged(layers, voxel_manager.active_layer()));
+    }
+
+    /// Applies a change of the layer list, the changes of the stack can be undone
+    fn edit_layers(&mut self, request: LayerRequest) {
+        let count = self.scene.voxel_manager().layers().len();
+        let valid = match request {
+            LayerRequest::Select(idx)
+            | LayerRequest::SetVisible(idx, _)
+            | LayerRequest::SetLocked(idx, _)
+            | LayerRequest::Rename(idx, _) => idx < count,
+            LayerRequest::Remove(idx) => idx < count && count > 1,
+            LayerRequest::Move { from, to } => from < count && to < count,
+            LayerRequest::Add => true,
+        };
+        if !valid {
+            return;
+        }
+        // The selection belongs to the active layer
+        match request {
+            LayerRequest::Rename(..) | LayerRequest::SetLocked(..) => {}
+            _ => self.drop_selection(),
+        }
+        let voxel_manager = self.scene.voxel_manager_mut();
+        match request {
+            LayerRequest::Select(idx) => voxel_manager.set_active_layer(idx),
+            LayerRequest::SetVisible(idx, visible) => {
+                voxel_manager.set_layer_visible(idx, visible);
+                self.renderer.update_instances(&mut self.scene);
+            }
+            LayerRequest::SetLocked(idx, locked) => voxel_manager.set_layer_locked(idx, locked),
+            LayerRequest::Rename(idx, name) => voxel_manager.rename_layer(idx, name),
+            LayerRequest::Add => self.apply(Command::AddLayer),
+            LayerRequest::Remove(idx) => {
+                self.apply(Command::RemoveLayer(idx));
+                self.renderer.update_instances(&mut self.scene);
+            }
+            LayerRequest::Move { from, to } => {
+                self.apply(Command::MoveLayer { from, to });
+                self.renderer.update_instances(&mut self.scene);
+            }
+        }
+    }
+
+    /// Shows the objects of the scene in the object list
+    fn update_objects(&mut self) {
+        let names = self
+            .scene
+            .objects()
+            .iter()
+            .map(|object| object.name.clone())
+            .collect();
+        let active = self.scene.active();
+        let translation = self.scene.objects()[active].transform.translation;
+        self.ui
+            .queue_message(Message::ObjectsChanged(names, active, translation));
+    }
 
-        buffer.write_all(b"# List of geometric vertices, with (x, y, z [,w]) coordinates, w is optional and defaults to 1.0.\n")?;
-
-        for vd in vertex_data.iter() {
-            buffer.write_all(
-                format!(
-                    "v {:.3} {:.3} {:.3} 1.0\n",
-                    vd.pos[0] as f32, vd.pos[1] as f32, vd.pos[2] as f32
-                )
-                .as_ref(),
-            )?;
-        }
-
-        buffer.write_all(
-            b"# List of vertex normals in (x,y,z) form; normals might not be unit vectors.\n",
-        )?;
-
-        for vd in vertex_data.iter() {
-            buffer.write_all(
-                format!(
-                    "vn {:.3} {:.3} {:.3}\n",
-                    vd.normal[0], vd.normal[1], vd.normal[2]
-                )
-                .as_ref(),
-            )?;
-        }
-
-        buffer.write_all(b"# Polygonal face element\n")?;
-
-        for id in indices.chunks(3) {
-            buffer.write_all(
-                format!(
-                    "f {}//{} {}//{} {}//{}\n",
-                    id[0] + 1,
-                    id[0] + 1,
-                    id[1] + 1,
-                    id[1] + 1,
-                    id[2] + 1,
-                    id[2] + 1
-                )
-                .as_ref(),
-            )?;
-        }
-
-        buffer.flush()?;
+    /// Shows the active object on the canvas, the other objects are drawn around it
+    fn show_active_object(&mut self) {
+        self.drop_selection();
+        self.update_canvas();
+        let [x, y, z] = self.scene.voxel_manager().extent();
+        self.camera.frame([x as f32, y as f32, z as f32]);
+        self.renderer.update_view(&mut self.camera);
+        self.update_palette();
+        self.update_layers();
+    }
+
+    /// Applies a change of the object list. Only the edits inside an object are recorded in
+    /// its history, adding, removing and placing objects can't be undone.
+    fn edit_objects(&mut self, request: ObjectRequest) {
+        let count = self.scene.objects().len();
+        match request {
+            ObjectRequest::Select(idx) if idx < count => {
+                self.scene.set_active(idx);
+                self.show_active_object();
+            }
+            ObjectRequest::Add(extent) => {
+                let mut voxel_manager = VoxelManager::new(extent);
+                voxel_manager.set_palette(DEFAULT_PALETTE.to_vec());
+                self.scene.add_object(voxel_manager);
+                self.histories.push(History::new(DEFAULT_HISTORY_MEMORY));
+                self.show_active_object();
+            }
+            ObjectRequest::Remove(idx) if idx < count && count > 1 => {
+                self.scene.remove_object(idx);
+                self.histories.remove(idx);
+                self.show_active_object();
+            }
+            ObjectRequest::Move(translation) => {
+                let active = self.scene.active();
+                let mut transform = self.scene.objects()[active].transform;
+                transform.translation = translation;
+                self.scene.set_transform(active, transform);
+                self.renderer.update_instances(&mut self.scene);
+            }
+            ObjectRequest::Rotate(axis) => {
+                let active = self.scene.active();
+                let mut transform = self.scene.objects()[active].transform;
+                transform.rotate(axis, self.scene.voxel_manager().extent());
+                self.scene.set_transform(active, transform);
+                self.renderer.update_instances(&mut self.scene);
+            }
+            _ => {}
+        }
+    }
+
+    /// Replaces the edited document and resizes the canvas to its active object
+    fn set_document(&mut self, scene: Scene) {
+        self.histories = scene
+            .objects()
+            .iter()
+            .map(|_| History::new(DEFAULT_HISTORY_MEMORY))
+            .collect();
+        self.scene = scene;
+        self.drop_selection();
+        self.update_canvas();
+        self.update_palette();
+        self.update_layers();
+        self.update_objects();
+    }
+
+    /// Resizes the canvas unless it would delete voxels the user hasn't been warned about
+    fn resize_canvas(&mut self, request: ResizeRequest) {
+        let extent = self.scene.voxel_manager().extent();
+        let mut offset = [0; 3];
+        for i in 0..3 {
+            offset[i] = request.anchor[i].offset(extent[i], request.extent[i]);
+        }
+        let cropped = self
+            .scene
+            .voxel_manager()
+            .cropped_voxels(request.extent, offset)
+            .len();
+        if cropped > 0 && !request.confirmed {
+            self.ui
+                .queue_message(Message::CropWarning(request, cropped));
+            return;
+        }
+        self.apply(Command::Resize {
+            extent: request.extent,
+            offset,
+        });
+        self.drop_selection();
+        self.update_canvas();
+    }
+
+    fn new_document(&mut self, dimensions: [usize; 3]) {
+        let mut voxel_manager = VoxelManager::new(dimensions);
+        voxel_manager.set_palette(DEFAULT_PALETTE.to_vec());
+        self.set_document(Scene::new(voxel_manager));
+        self.camera.frame([
+            dimensions[0] as f32,
+            dimensions[1] as f32,
+            dimensions[2] as f32,
+        ]);
+        self.renderer.update_view(&mut self.camera);
+    }
+
+    fn open_project(&mut self, file_path: String) -> std::io::Result<()> {
+        let mut buffer = BufReader::new(File::open(&file_path)?);
+        let project = project::load(&mut buffer)?;
+        for object in project.scene.objects().iter() {
+            let extent = object.voxel_manager.extent();
+            if extent.iter().any(|e| *e == 0 || *e > MAX_CANVAS_SIZE) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "Object size {}x{}x{} is not supported, the limit is {} on every axis",
+                        extent[0], extent[1], extent[2], MAX_CANVAS_SIZE
+                    ),
+                ));
+            }
+        }
+        self.set_document(project.scene);
+        self.camera.set_state(project.camera);
+        self.renderer.update_view(&mut self.camera);
         Ok(())
     }
 
+    fn import_vox(&mut self, file_path: String) -> std::io::Result<()> {
+        let mut buffer = BufReader::new(File::open(&file_path)?);
+        let voxel_manager = vox::import_file(&mut buffer, self.scene.voxel_manager().extent())?;
+        self.set_document(Scene::new(voxel_manager));
+        Ok(())
+    }
+
+    fn export_vox(&self, file_path: String) -> std::io::Result<()> {
+        let mut buffer = BufWriter::new(File::create(&file_path)?);
+        if self.ui.controls().separate_objects() {
+            vox::export_objects_file(&mut buffer, self.scene.objects())
+        } else {
+            vox::export_file(&mut buffer, &self.scene.merged())
+        }
+    }
+
     pub fn init(window: winit::window::Window) -> Self {
         log::info!("Initializing the surface...");
 
@@ -297,9 +1126,12 @@ impl Editor {
 
         let mut camera = CameraWrapper::new(
             sc_desc.width as f32 / sc_desc.height as f32,
-            DEFAULT_MESH_COUNT as f32,
+            [DEFAULT_MESH_COUNT as f32; 3],
         );
 
+        let mut voxel_manager = VoxelManager::new([DEFAULT_MESH_COUNT as usize; 3]);
+        voxel_manager.set_palette(DEFAULT_PALETTE.to_vec());
+
         log::info!("Initializing the Renderer...");
         let renderer = Renderer::init(
             surface,
@@ -307,18 +1139,27 @@ impl Editor {
             queue,
             sc_desc,
             swap_chain,
-            DEFAULT_MESH_COUNT,
+            [DEFAULT_MESH_COUNT; 3],
             &mut camera,
         );
-        Editor {
+        let mut editor = Editor {
             window,
             renderer,
             ui,
             state: EditorState::ChangeView,
             cursor_ray: Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0)),
             camera,
-            voxel_manager: VoxelManager::new(DEFAULT_MESH_COUNT as usize),
-        }
+            scene: Scene::new(voxel_manager),
+            histories: vec![History::new(DEFAULT_HISTORY_MEMORY)],
+            modifiers: event::ModifiersState::empty(),
+            selection: None,
+            clipboard: None,
+            drag_selection: false,
+        };
+        editor.update_palette();
+        editor.update_layers();
+        editor.update_objects();
+        editor
     }
 
     pub fn run(mut self, event_loop: winit::event_loop::EventLoop<()>) {
@@ -331,12 +1172,99 @@ impl Editor {
                 self.window
                     .set_title(&format!("Voxel-editor (FPS: {:?})", fps));
             }
+            let greedy_meshing = self.ui.controls().greedy_meshing();
+            if greedy_meshing != self.renderer.greedy_meshing() {
+                self.renderer
+                    .set_greedy_meshing(greedy_meshing, &self.scene);
+            }
+            // Only the boxes are mirrored, not the selection or the shapes
+            let edit_op = self.ui.controls().edit_op();
+            let symmetry = if edit_op == EditOp::Select || edit_op.shape().is_some() {
+                Symmetry::default()
+            } else {
+                self.ui.controls().symmetry()
+            };
+            if symmetry != self.renderer.symmetry() {
+                self.renderer.set_symmetry(symmetry);
+            }
             if let Some(file_path) = self.ui.controls().save_path() {
                 match self.save_vertices(file_path) {
                     Err(e) => println!("Failed to save file reason: {:?}", e),
                     Ok(_) => println!("File saved"),
                 };
             }
+            if let Some(dimensions) = self.ui.controls().new_document() {
+                self.new_document(dimensions);
+            }
+            if let Some(request) = self.ui.controls().resize_canvas() {
+                self.resize_canvas(request);
+            }
+            let palette_len = self.scene.voxel_manager().palette().len();
+            if let Some((idx, color)) = self.ui.controls().palette_edit() {
+                self.edit_palette(idx, color);
+            }
+            if let Some((idx, material)) = self.ui.controls().material_edit() {
+                self.edit_material(idx, material);
+            }
+            // An added entry is shown in the picker with its index and material
+            if self.scene.voxel_manager().palette().len() != palette_len {
+                self.update_palette();
+            }
+            let layer_requests = self.ui.controls().layer_requests();
+            if !layer_requests.is_empty() {
+                for request in layer_requests {
+                    self.edit_layers(request);
+                }
+                self.update_layers();
+            }
+            if let Some((transform, bounds)) = self.ui.controls().transform_request() {
+                self.transform(transform, bounds);
+            }
+            if let Some(filter) = self.ui.controls().filter_request() {
+                self.filter(filter);
+            }
+            if let Some(request) = self.ui.controls().shell_request() {
+                self.edit_shell(request);
+            }
+            if let Some(request) = self.ui.controls().combine_request() {
+                self.combine(request);
+            }
+            match self.ui.controls().terrain_request() {
+                Some(TerrainRequest::Preview(params)) => self.preview_terrain(params),
+                Some(TerrainRequest::Generate(params)) => self.generate_terrain(params),
+                None => {}
+            }
+            let object_requests = self.ui.controls().object_requests();
+            if !object_requests.is_empty() {
+                for request in object_requests {
+                    self.edit_objects(request);
+                }
+                self.update_objects();
+            }
+            if let Some(file_path) = self.ui.controls().project_save_path() {
+                match self.save_project(file_path) {
+                    Err(e) => println!("Failed to save project reason: {:?}", e),
+                    Ok(_) => println!("Project saved"),
+                };
+            }
+            if let Some(file_path) = self.ui.controls().project_open_path() {
+                match self.open_project(file_path) {
+                    Err(e) => println!("Failed to open project reason: {:?}", e),
+                    Ok(_) => println!("Project opened"),
+                };
+            }
+            if let Some(file_path) = self.ui.controls().import_path() {
+                match self.import_vox(file_path) {
+                    Err(e) => println!("Failed to import file reason: {:?}", e),
+                    Ok(_) => println!("File imported"),
+                };
+            }
+            if let Some(file_path) = self.ui.controls().vox_export_path() {
+                match self.export_vox(file_path) {
+                    Err(e) => println!("Failed to export file reason: {:?}", e),
+                    Ok(_) => println!("File exported"),
+                };
+            }
             match event {
                 event::Event::MainEventsCleared => {
                     if last_update_inst.elapsed() > time::Duration::from_millis(16) {
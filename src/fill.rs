@@ -0,0 +1,276 @@
+use crate::selection::CellEdits;
+use crate::voxel_manager::VoxelManager;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// Most cells a fill may change, so a click into a huge region doesn't stall the editor
+pub const MAX_FILL_CELLS: usize = 1 << 20;
+
+/// Which cells around a cell are connected to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Connectivity {
+    /// The 6 cells sharing a face
+    Faces,
+    /// The 26 cells sharing a face, an edge or a corner
+    Full,
+}
+
+impl Connectivity {
+    fn offsets(self) -> Vec<[i32; 3]> {
+        let mut offsets = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let distance = i32::abs(x) + i32::abs(y) + i32::abs(z);
+                    if distance == 1 || (distance > 1 && self == Connectivity::Full) {
+                        offsets.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        offsets
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FillError {
+    /// The fill would change more cells than the limit
+    TooLarge(usize),
+    /// The empty region isn't enclosed, it reaches the border of the grid
+    NotEnclosed,
+}
+
+impl fmt::Display for FillError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FillError::TooLarge(limit) => write!(f, "the fill would change over {} cells", limit),
+            FillError::NotEnclosed => write!(f, "the region isn't enclosed by voxels"),
+        }
+    }
+}
+
+/// Visits the cells connected to `start` for which `inside` holds, breadth first.
+/// Stepping out of the grid from a visited cell fails if `enclosed` is set.
+fn connected_cells(
+    extent: [usize; 3],
+    start: [usize; 3],
+    offsets: &[[i32; 3]],
+    enclosed: bool,
+    limit: usize,
+    inside: impl Fn([usize; 3]) -> bool,
+) -> Result<Vec<[usize; 3]>, FillError> {
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(start);
+    queue.push_back(start);
+    while let Some(cell) = queue.pop_front() {
+        for offset in offsets {
+            let mut next = [0; 3];
+            let mut in_grid = true;
+            for i in 0..3 {
+                let p = cell[i] as i32 + offset[i];
+                in_grid &= p >= 0 && p < extent[i] as i32;
+                next[i] = p as usize;
+            }
+            if !in_grid {
+                if enclosed {
+                    return Err(FillError::NotEnclosed);
+                }
+                continue;
+            }
+            if !visited.contains(&next) && inside(next) {
+                if visited.len() == limit {
+                    return Err(FillError::TooLarge(limit));
+                }
+                visited.insert(next);
+                queue.push_back(next);
+            }
+        }
+    }
+    let mut cells: Vec<_> = visited.into_iter().collect();
+    cells.sort();
+    Ok(cells)
+}
+
+/// Recolors the voxels of the active layer connected to the one at `start` which have its
+/// palette entry
+pub fn flood_fill(
+    voxel_manager: &VoxelManager,
+    start: [usize; 3],
+    idx: u16,
+    connectivity: Connectivity,
+    limit: usize,
+) -> Result<CellEdits, FillError> {
+    let layer = &voxel_manager.layers()[voxel_manager.active_layer()];
+    let [x, y, z] = start;
+    let target = match layer.get(x, y, z) {
+        Some(target) if target != idx => target,
+        _ => return Ok(Vec::new()),
+    };
+    let cells = connected_cells(
+        voxel_manager.extent(),
+        start,
+        &connectivity.offsets(),
+        false,
+        limit,
+        |[x, y, z]| layer.get(x, y, z) == Some(target),
+    )?;
+    Ok(cells.into_iter().map(|cell| (cell, Some(idx))).collect())
+}
+
+/// Fills the empty region around `start`, connected through the faces of the cells. The
+/// region has to be enclosed by visible voxels.
+pub fn fill_enclosed(
+    voxel_manager: &VoxelManager,
+    start: [usize; 3],
+    idx: u16,
+    limit: usize,
+) -> Result<CellEdits, FillError> {
+    let [x, y, z] = start;
+    if voxel_manager.get_index(x, y, z).is_some() {
+        return Ok(Vec::new());
+    }
+    let cells = connected_cells(
+        voxel_manager.extent(),
+        start,
+        &Connectivity::Faces.offsets(),
+        true,
+        limit,
+        |[x, y, z]| voxel_manager.get_index(x, y, z).is_none(),
+    )?;
+    Ok(cells.into_iter().map(|cell| (cell, Some(idx))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(extent: [usize; 3], voxels: &[([usize; 3], u16)]) -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new(extent);
+        voxel_manager.set_palette(vec![[1.0; 4], [0.5; 4], [0.2; 4]]);
+        for &([x, y, z], idx) in voxels {
+            voxel_manager.set_index(x, y, z, idx);
+        }
+        voxel_manager
+    }
+
+    fn cells(edits: CellEdits) -> Vec<[usize; 3]> {
+        edits.into_iter().map(|(cell, _)| cell).collect()
+    }
+
+    #[test]
+    fn connectivity_offsets() {
+        assert_eq!(Connectivity::Faces.offsets().len(), 6);
+        assert_eq!(Connectivity::Full.offsets().len(), 26);
+    }
+
+    #[test]
+    fn flood_fill_follows_connectivity() {
+        // A diagonal pair, a face neighbour of another color and a far voxel
+        let voxel_manager = grid(
+            [4; 3],
+            &[
+                ([0, 0, 0], 0),
+                ([1, 1, 0], 0),
+                ([0, 1, 0], 1),
+                ([1, 0, 1], 0),
+                ([3, 3, 3], 0),
+            ],
+        );
+        let faces = flood_fill(&voxel_manager, [0, 0, 0], 2, Connectivity::Faces, 100);
+        assert_eq!(faces, Ok(vec![([0, 0, 0], Some(2))]));
+        let full = flood_fill(&voxel_manager, [0, 0, 0], 2, Connectivity::Full, 100).unwrap();
+        assert_eq!(cells(full), vec![[0, 0, 0], [1, 0, 1], [1, 1, 0]]);
+
+        // Empty cells and fills with the same color change nothing
+        let empty = flood_fill(&voxel_manager, [2, 2, 2], 2, Connectivity::Full, 100);
+        assert_eq!(empty, Ok(Vec::new()));
+        let same = flood_fill(&voxel_manager, [0, 0, 0], 0, Connectivity::Full, 100);
+        assert_eq!(same, Ok(Vec::new()));
+    }
+
+    #[test]
+    fn flood_fill_stays_on_the_active_layer() {
+        let mut voxel_manager = grid([3, 1, 1], &[([0, 0, 0], 0), ([2, 0, 0], 0)]);
+        voxel_manager.add_layer();
+        voxel_manager.set_index(1, 0, 0, 0);
+        voxel_manager.set_index(2, 0, 0, 0);
+        let edits = flood_fill(&voxel_manager, [1, 0, 0], 1, Connectivity::Faces, 100);
+        assert_eq!(edits.map(cells), Ok(vec![[1, 0, 0], [2, 0, 0]]));
+    }
+
+    #[test]
+    fn fill_limit() {
+        let voxels: Vec<_> = (0..10).map(|x| ([x, 0, 0], 0)).collect();
+        let voxel_manager = grid([10, 1, 1], &voxels);
+        let edits = flood_fill(&voxel_manager, [4, 0, 0], 1, Connectivity::Faces, 10);
+        assert_eq!(edits.map(|edits| edits.len()), Ok(10));
+        let edits = flood_fill(&voxel_manager, [4, 0, 0], 1, Connectivity::Faces, 9);
+        assert_eq!(edits, Err(FillError::TooLarge(9)));
+    }
+
+    /// The walls of a hollow cube of 5 by 5 by 5 cells at the origin
+    fn hollow_cube() -> Vec<([usize; 3], u16)> {
+        let mut voxels = Vec::new();
+        for x in 0..5 {
+            for y in 0..5 {
+                for z in 0..5 {
+                    if [x, y, z].iter().any(|&i| i == 0 || i == 4) {
+                        voxels.push(([x, y, z], 0));
+                    }
+                }
+            }
+        }
+        voxels
+    }
+
+    #[test]
+    fn fill_enclosed_region() {
+        let voxel_manager = grid([6; 3], &hollow_cube());
+        let edits = fill_enclosed(&voxel_manager, [2, 1, 3], 1, 100).unwrap();
+        assert_eq!(edits.len(), 27);
+        assert!(edits
+            .iter()
+            .all(|(cell, idx)| cell.iter().all(|&i| (1..=3).contains(&i)) && *idx == Some(1)));
+        assert!(edits.contains(&([1, 1, 1], Some(1))));
+        assert!(edits.contains(&([3, 3, 3], Some(1))));
+
+        // Outside of the cube the region reaches the border
+        assert_eq!(
+            fill_enclosed(&voxel_manager, [5, 5, 5], 1, 100),
+            Err(FillError::NotEnclosed)
+        );
+        // Clicking a voxel fills nothing
+        assert_eq!(
+            fill_enclosed(&voxel_manager, [0, 0, 1], 1, 100),
+            Ok(Vec::new())
+        );
+        assert_eq!(
+            fill_enclosed(&voxel_manager, [2, 2, 2], 1, 5),
+            Err(FillError::TooLarge(5))
+        );
+    }
+
+    #[test]
+    fn gap_in_the_walls() {
+        let voxels: Vec<_> = hollow_cube()
+            .into_iter()
+            .filter(|(cell, _)| *cell != [4, 2, 2])
+            .collect();
+        let voxel_manager = grid([6; 3], &voxels);
+        assert_eq!(
+            fill_enclosed(&voxel_manager, [2, 2, 2], 1, 100),
+            Err(FillError::NotEnclosed)
+        );
+        // A square outline on a slice doesn't enclose anything
+        let outline: Vec<_> = hollow_cube()
+            .into_iter()
+            .filter(|(cell, _)| cell[2] == 2)
+            .collect();
+        let voxel_manager = grid([6; 3], &outline);
+        assert_eq!(
+            fill_enclosed(&voxel_manager, [2, 2, 2], 1, 100),
+            Err(FillError::NotEnclosed)
+        );
+    }
+}
@@ -0,0 +1,808 @@
+use crate::geometry::BoundingBox;
+use crate::material::Material;
+use crate::selection::CellEdits;
+use crate::voxel_manager::{GridBounds, Layer, VoxelManager, VoxelTransform};
+use std::collections::VecDeque;
+use std::mem;
+
+/// Memory the undo history may use before the oldest edits are dropped
+pub const DEFAULT_HISTORY_MEMORY: usize = 64 * 1024 * 1024;
+
+/// An edit of the voxel grid which can be recorded in the history.
+/// The voxel edits change the active layer.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Fills the box with the palette entry
+    AddBox(BoundingBox, u16),
+    EraseBox(BoundingBox),
+    /// Changes the filled voxels of the box to the palette entry
+    Refill(BoundingBox, u16),
+    /// Adds an empty layer on top and makes it the active one
+    AddLayer,
+    /// Deletes the layer with its voxels
+    RemoveLayer(usize),
+    /// Moves a layer to another place of the stack
+    MoveLayer {
+        from: usize,
+        to: usize,
+    },
+    /// Resizes the canvas and moves the voxels by the offset
+    Resize {
+        extent: [usize; 3],
+        offset: [i32; 3],
+    },
+    /// Changes a palette entry, which recolors every voxel using it
+    SetPaletteColor {
+        index: u16,
+        color: [f32; 4],
+    },
+    /// Adds an entry at the end of the palette, which has the index unless the entry is
+    /// still there when the command is redone
+    AddPaletteColor {
+        index: u16,
+        color: [f32; 4],
+    },
+    /// Changes the material of a palette entry
+    SetMaterial {
+        index: u16,
+        material: Material,
+    },
+    /// Writes the cells in order, like the voxels of a moved selection
+    SetCells(CellEdits),
+    /// Rotates, flips or moves the voxels of the active layer in the region, given by its
+    /// corner and extent, or of every unlocked layer
+    Transform {
+        transform: VoxelTransform,
+        region: Option<([usize; 3], [usize; 3])>,
+        bounds: GridBounds,
+    },
+    /// Commands applied in order and undone in one step, like the boxes of a symmetric edit
+    Group(Vec<Command>),
+}
+
+impl Command {
+    fn apply(&self, voxel_manager: &mut VoxelManager) {
+        match *self {
+            Command::AddBox(bbox, idx) => voxel_manager.add_box(bbox, idx),
+            Command::EraseBox(bbox) => voxel_manager.erase_box(bbox),
+            Command::Refill(bbox, idx) => voxel_manager.refill(bbox, idx),
+            Command::AddLayer => {
+                voxel_manager.add_layer();
+            }
+            Command::RemoveLayer(idx) => {
+                voxel_manager.remove_layer(idx);
+            }
+            Command::MoveLayer { from, to } => voxel_manager.move_layer(from, to),
+            Command::Resize { extent, offset } => voxel_manager.resize(extent, offset),
+            Command::SetPaletteColor { index, color } => {
+                voxel_manager.set_palette_color(index, color)
+            }
+            Command::AddPaletteColor { index, color } => {
+                if (index as usize) < voxel_manager.palette().len() {
+                    voxel_manager.set_palette_color(index, color);
+                } else {
+                    voxel_manager.add_palette_color(color);
+                }
+            }
+            Command::SetMaterial { index, material } => voxel_manager.set_material(index, material),
+            Command::SetCells(ref cells) => voxel_manager.set_cells(cells),
+            Command::Transform {
+                transform,
+                region,
+                bounds,
+            } => voxel_manager.transform(transform, region, bounds),
+            Command::Group(ref commands) => {
+                for command in commands {
+                    command.apply(voxel_manager);
+                }
+            }
+        }
+    }
+
+    /// Applies the command and returns what it overwrote
+    fn apply_recorded(&self, voxel_manager: &mut VoxelManager) -> Prior {
+        let layer = voxel_manager.active_layer();
+        let prior = match *self {
+            Command::AddBox(bbox, _) | Command::EraseBox(bbox) | Command::Refill(bbox, _) => {
+                let cells = &voxel_manager.layers()[layer];
+                Prior::Cells(
+                    Command::cells(&bbox)
+                        .iter()
+                        .map(|&[x, y, z]| cells.get(x, y, z))
+                        .collect(),
+                )
+            }
+            Command::SetCells(ref edits) => {
+                let cells = &voxel_manager.layers()[layer];
+                Prior::Cells(
+                    edits
+                        .iter()
+                        .map(|&([x, y, z], _)| cells.get(x, y, z))
+                        .collect(),
+                )
+            }
+            Command::RemoveLayer(idx) => Prior::Layer(voxel_manager.layers()[idx].clone()),
+            Command::AddLayer | Command::MoveLayer { .. } | Command::AddPaletteColor { .. } => {
+                Prior::Nothing
+            }
+            Command::Resize { extent, offset } => Prior::Canvas {
+                extent: voxel_manager.extent(),
+                cropped: voxel_manager.cropped_voxels(extent, offset),
+            },
+            Command::SetPaletteColor { index, .. } => {
+                Prior::PaletteColor(voxel_manager.palette()[index as usize])
+            }
+            Command::SetMaterial { index, .. } => {
+                Prior::Material(voxel_manager.materials()[index as usize])
+            }
+            Command::Transform { .. } => Prior::Layers {
+                extent: voxel_manager.extent(),
+                layers: voxel_manager.layers().to_vec(),
+            },
+            Command::Group(ref commands) => {
+                // Every command sees the edits of the ones before it
+                return Prior::Group(
+                    commands
+                        .iter()
+                        .map(|command| command.apply_recorded(voxel_manager))
+                        .collect(),
+                );
+            }
+        };
+        self.apply(voxel_manager);
+        prior
+    }
+
+    /// Restores what the command overwrote on the given layer
+    fn revert(&self, prior: &Prior, layer: usize, voxel_manager: &mut VoxelManager) {
+        match (self, prior) {
+            (Command::Resize { offset, .. }, Prior::Canvas { extent, cropped }) => {
+                voxel_manager.resize(*extent, [-offset[0], -offset[1], -offset[2]]);
+                for (layer, [x, y, z], idx) in cropped.iter() {
+                    voxel_manager.set_layer_cell(*layer, *x, *y, *z, Some(*idx));
+                }
+            }
+            (
+                Command::AddBox(bbox, _) | Command::EraseBox(bbox) | Command::Refill(bbox, _),
+                Prior::Cells(cells),
+            ) => {
+                for (&[x, y, z], prior) in Command::cells(bbox).iter().zip(cells.iter()) {
+                    voxel_manager.set_layer_cell(layer, x, y, z, *prior);
+                }
+            }
+            (Command::SetCells(edits), Prior::Cells(cells)) => {
+                // A cell may be written more than once, the first prior value is restored last
+                for (&([x, y, z], _), prior) in edits.iter().zip(cells.iter()).rev() {
+                    voxel_manager.set_layer_cell(layer, x, y, z, *prior);
+                }
+            }
+            (Command::AddLayer, Prior::Nothing) => {
+                voxel_manager.remove_layer(voxel_manager.layers().len() - 1);
+            }
+            (Command::RemoveLayer(idx), Prior::Layer(removed)) => {
+                voxel_manager.insert_layer(*idx, removed.clone());
+            }
+            (Command::MoveLayer { from, to }, Prior::Nothing) => {
+                voxel_manager.move_layer(*to, *from)
+            }
+            (Command::SetPaletteColor { index, .. }, Prior::PaletteColor(color)) => {
+                voxel_manager.set_palette_color(*index, *color);
+            }
+            (Command::AddPaletteColor { index, .. }, Prior::Nothing) => {
+                // The entries added by later edits stay, and with them the added one
+                if *index as usize + 1 == voxel_manager.palette().len() {
+                    voxel_manager.pop_palette_color();
+                }
+            }
+            (Command::SetMaterial { index, .. }, Prior::Material(material)) => {
+                voxel_manager.set_material(*index, *material);
+            }
+            (Command::Transform { .. }, Prior::Layers { extent, layers }) => {
+                voxel_manager.restore_layers(*extent, layers.clone());
+            }
+            (Command::Group(commands), Prior::Group(priors)) => {
+                for (command, prior) in commands.iter().zip(priors.iter()).rev() {
+                    command.revert(prior, layer, voxel_manager);
+                }
+            }
+            _ => unreachable!("The prior state doesn't match the command"),
+        }
+    }
+
+    fn memory_size(&self) -> usize {
+        match self {
+            Command::SetCells(cells) => cells.len() * mem::size_of::<([usize; 3], Option<u16>)>(),
+            Command::Group(commands) => commands
+                .iter()
+                .map(|command| mem::size_of::<Command>() + command.memory_size())
+                .sum(),
+            _ => 0,
+        }
+    }
+
+    /// Whether the command only replaces the result of the previous one, like the ticks
+    /// of a slider dragged over the same palette entry
+    fn overwrites(&self, previous: &Command) -> bool {
+        match (self, previous) {
+            (
+                Command::SetPaletteColor { index, .. },
+                Command::SetPaletteColor { index: last, .. },
+            )
+            | (Command::SetMaterial { index, .. }, Command::SetMaterial { index: last, .. }) => {
+                index == last
+            }
+            _ => false,
+        }
+    }
+
+    /// The grid cells the command touches
+    fn cells(bbox: &BoundingBox) -> Vec<[usize; 3]> {
+        let origin = [
+            bbox.corner.x as usize,
+            bbox.corner.y as usize,
+            bbox.corner.z as usize,
+        ];
+        let mut cells = Vec::new();
+        for x in origin[0]..origin[0] + bbox.extent.x as usize {
+            for y in origin[1]..origin[1] + bbox.extent.y as usize {
+                for z in origin[2]..origin[2] + bbox.extent.z as usize {
+                    cells.push([x, y, z]);
+                }
+            }
+        }
+        cells
+    }
+}
+
+/// What a command overwrote, so it can be restored
+enum Prior {
+    /// Palette indices of the edited cells
+    Cells(Vec<Option<u16>>),
+    /// The extent of the canvas before a resize and the voxels the resize deleted with
+    /// their layer
+    Canvas {
+        extent: [usize; 3],
+        cropped: Vec<(usize, [usize; 3], u16)>,
+    },
+    /// The removed layer
+    Layer(Layer),
+    /// The extent of the canvas and every layer before a transform
+    Layers {
+        extent: [usize; 3],
+        layers: Vec<Layer>,
+    },
+    /// Commands which can be reverted without saving anything
+    Nothing,
+    /// The color of the edited palette entry
+    PaletteColor([f32; 4]),
+    /// The material of the edited palette entry
+    Material(Material),
+    /// What every command of a group overwrote
+    Group(Vec<Prior>),
+}
+
+impl Prior {
+    fn memory_size(&self) -> usize {
+        match self {
+            Prior::Cells(cells) => cells.len() * mem::size_of::<Option<u16>>(),
+            Prior::Canvas { cropped, .. } => {
+                cropped.len() * mem::size_of::<(usize, [usize; 3], u16)>()
+            }
+            Prior::Layer(layer) => layer.memory_size(),
+            Prior::Layers { layers, .. } => layers.iter().map(Layer::memory_size).sum(),
+            Prior::Group(priors) => priors
+                .iter()
+                .map(|prior| mem::size_of::<Prior>() + prior.memory_size())
+                .sum(),
+            Prior::PaletteColor(_) | Prior::Material(_) | Prior::Nothing => 0,
+        }
+    }
+}
+
+/// A command together with the contents of the cells it touched before it was applied
+struct Record {
+    command: Command,
+    /// The active layer when the command was applied
+    layer: usize,
+    prior: Prior,
+}
+
+impl Record {
+    fn memory_size(&self) -> usize {
+        mem::size_of::<Self>() + self.command.memory_size() + self.prior.memory_size()
+    }
+}
+
+pub struct History {
+    undo_stack: VecDeque<Record>,
+    redo_stack: Vec<Record>,
+    memory_cap: usize,
+    memory_used: usize,
+}
+
+impl History {
+    pub fn new(memory_cap: usize) -> Self {
+        History {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            memory_cap,
+            memory_used: 0,
+        }
+    }
+
+    /// Applies the command to the voxel manager and records it, which discards the redo history.
+    /// The oldest records are dropped while the history uses more memory than its cap.
+    /// Consecutive edits of the same palette entry are recorded once, so dragging a
+    /// slider is undone in one step.
+    pub fn apply(&mut self, command: Command, voxel_manager: &mut VoxelManager) {
+        if self.redo_stack.is_empty() {
+            if let Some(last) = self.undo_stack.back_mut() {
+                if command.overwrites(&last.command) {
+                    command.apply(voxel_manager);
+                    last.command = command;
+                    return;
+                }
+            }
+        }
+
+        let layer = voxel_manager.active_layer();
+        let prior = command.apply_recorded(voxel_manager);
+
+        for record in self.redo_stack.drain(..) {
+            self.memory_used -= record.memory_size();
+        }
+        let record = Record {
+            command,
+            layer,
+            prior,
+        };
+        self.memory_used += record.memory_size();
+        self.undo_stack.push_back(record);
+        while self.memory_used > self.memory_cap {
+            match self.undo_stack.pop_front() {
+                Some(record) => self.memory_used -= record.memory_size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the cells touched by the last command. Returns false if there was nothing to undo.
+    /// The layer which was active when the command was applied becomes active again.
+    pub fn undo(&mut self, voxel_manager: &mut VoxelManager) -> bool {
+        match self.undo_stack.pop_back() {
+            Some(record) => {
+                record
+                    .command
+                    .revert(&record.prior, record.layer, voxel_manager);
+                voxel_manager.set_active_layer(record.layer);
+                self.redo_stack.push(record);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the last undone command again. Returns false if there was nothing to redo.
+    pub fn redo(&mut self, voxel_manager: &mut VoxelManager) -> bool {
+        match self.redo_stack.pop() {
+            Some(record) => {
+                voxel_manager.set_active_layer(record.layer);
+                record.command.apply(voxel_manager);
+                self.undo_stack.push_back(record);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    const EXTENT: usize = 12;
+    const PALETTE_SIZE: usize = 8;
+
+    /// Small linear congruential generator, so the tests are reproducible
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((self.0 >> 33) as usize) % bound
+        }
+    }
+
+    fn random_command(rng: &mut Lcg) -> Command {
+        let mut corner = [0.0; 3];
+        let mut extent = [0.0; 3];
+        for i in 0..3 {
+            let start = rng.next(EXTENT);
+            corner[i] = start as f32;
+            extent[i] = (1 + rng.next(EXTENT - start)) as f32;
+        }
+        let bbox = BoundingBox::new(
+            Vector3::new(corner[0], corner[1], corner[2]),
+            Vector3::new(extent[0], extent[1], extent[2]),
+            [1.0; 4],
+        );
+        let idx = rng.next(PALETTE_SIZE) as u16;
+        match rng.next(3) {
+            0 => Command::AddBox(bbox, idx),
+            1 => Command::EraseBox(bbox),
+            _ => Command::Refill(bbox, idx),
+        }
+    }
+
+    /// An empty grid with a palette of grays
+    fn canvas() -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new([EXTENT; 3]);
+        voxel_manager.set_palette(
+            (0..PALETTE_SIZE)
+                .map(|i| {
+                    let gray = i as f32 / PALETTE_SIZE as f32;
+                    [gray, gray, gray, 1.0]
+                })
+                .collect(),
+        );
+        voxel_manager
+    }
+
+    fn cube(corner: f32, extent: f32) -> BoundingBox {
+        BoundingBox::new(
+            Vector3::new(corner, corner, corner),
+            Vector3::new(extent, extent, extent),
+            [1.0, 0.0, 0.0, 1.0],
+        )
+    }
+
+    #[test]
+    fn undo_random_edits() {
+        for seed in 0..20 {
+            let mut rng = Lcg(seed);
+            let mut voxel_manager = canvas();
+            voxel_manager.add_box(cube(2.0, 5.0), 0);
+            let original = voxel_manager.clone();
+
+            let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+            let count = 1 + rng.next(30);
+            for _ in 0..count {
+                history.apply(random_command(&mut rng), &mut voxel_manager);
+            }
+            let edited = voxel_manager.clone();
+
+            for _ in 0..count {
+                assert!(history.undo(&mut voxel_manager));
+            }
+            assert!(!history.undo(&mut voxel_manager));
+            assert_eq!(voxel_manager, original, "seed {}", seed);
+
+            for _ in 0..count {
+                assert!(history.redo(&mut voxel_manager));
+            }
+            assert!(!history.redo(&mut voxel_manager));
+            assert_eq!(voxel_manager, edited, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn interleaved_undo_and_redo() {
+        let mut rng = Lcg(42);
+        let mut voxel_manager = canvas();
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        // Snapshots of the grid after every edit which is still in the history
+        let mut states = vec![voxel_manager.clone()];
+        for _ in 0..200 {
+            match rng.next(4) {
+                0 => {
+                    if history.undo(&mut voxel_manager) {
+                        states.pop();
+                    }
+                }
+                _ => {
+                    history.apply(random_command(&mut rng), &mut voxel_manager);
+                    states.push(voxel_manager.clone());
+                }
+            }
+            assert_eq!(&voxel_manager, states.last().unwrap());
+        }
+        while history.undo(&mut voxel_manager) {
+            states.pop();
+            assert_eq!(&voxel_manager, states.last().unwrap());
+        }
+        assert_eq!(voxel_manager, VoxelManager::new([EXTENT; 3]));
+    }
+
+    #[test]
+    fn new_edit_discards_redo() {
+        let mut voxel_manager = canvas();
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        history.apply(Command::AddBox(cube(0.0, 2.0), 0), &mut voxel_manager);
+        assert!(history.undo(&mut voxel_manager));
+        history.apply(Command::AddBox(cube(4.0, 2.0), 0), &mut voxel_manager);
+        assert!(!history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager.get(0, 0, 0), None);
+        assert!(voxel_manager.get(4, 4, 4).is_some());
+    }
+
+    #[test]
+    fn memory_cap_drops_oldest_edits() {
+        let mut voxel_manager = canvas();
+        let command = Command::AddBox(cube(0.0, 2.0), 0);
+        let record_size = Record {
+            command: command.clone(),
+            layer: 0,
+            prior: Prior::Cells(vec![None; 8]),
+        }
+        .memory_size();
+        let mut history = History::new(2 * record_size);
+        history.apply(command, &mut voxel_manager);
+        history.apply(Command::EraseBox(cube(0.0, 2.0)), &mut voxel_manager);
+        history.apply(Command::AddBox(cube(1.0, 2.0), 0), &mut voxel_manager);
+        assert!(history.memory_used <= 2 * record_size);
+
+        assert!(history.undo(&mut voxel_manager));
+        assert!(history.undo(&mut voxel_manager));
+        // The first edit has been forgotten, so its voxels stay
+        assert!(!history.undo(&mut voxel_manager));
+        assert!(voxel_manager.get(0, 0, 0).is_some());
+        assert!(voxel_manager.get(2, 2, 2).is_none());
+    }
+
+    #[test]
+    fn undo_cell_edits() {
+        let mut voxel_manager = canvas();
+        voxel_manager.add_box(cube(0.0, 2.0), 1);
+        let original = voxel_manager.clone();
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        // The first cell is written twice, like when a selection moves over itself
+        let cells = vec![
+            ([0, 0, 0], None),
+            ([5, 5, 5], Some(2)),
+            ([0, 0, 0], Some(3)),
+            ([1, 1, 1], None),
+        ];
+        history.apply(Command::SetCells(cells), &mut voxel_manager);
+        assert_eq!(voxel_manager.get_index(0, 0, 0), Some(3));
+        assert_eq!(voxel_manager.get_index(5, 5, 5), Some(2));
+        assert_eq!(voxel_manager.get_index(1, 1, 1), None);
+        let edited = voxel_manager.clone();
+
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager, original);
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager, edited);
+    }
+
+    #[test]
+    fn undo_resize() {
+        let mut rng = Lcg(7);
+        let mut voxel_manager = canvas();
+        for _ in 0..10 {
+            random_command(&mut rng).apply(&mut voxel_manager);
+        }
+        let original = voxel_manager.clone();
+
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        history.apply(
+            Command::Resize {
+                extent: [5, 20, 7],
+                offset: [-3, 4, -2],
+            },
+            &mut voxel_manager,
+        );
+        history.apply(Command::AddBox(cube(0.0, 2.0), 0), &mut voxel_manager);
+        assert_eq!(voxel_manager.extent(), [5, 20, 7]);
+        let resized = voxel_manager.clone();
+
+        assert!(history.undo(&mut voxel_manager));
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager, original);
+        assert!(history.redo(&mut voxel_manager));
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager, resized);
+    }
+
+    #[test]
+    fn undo_group_in_one_step() {
+        let mut voxel_manager = canvas();
+        voxel_manager.add_box(cube(2.0, 5.0), 0);
+        let original = voxel_manager.clone();
+
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        // The boxes overlap, so the erase has to be undone before the fill
+        let group = Command::Group(vec![
+            Command::AddBox(cube(0.0, 4.0), 3),
+            Command::EraseBox(cube(3.0, 3.0)),
+            Command::Refill(cube(1.0, 8.0), 5),
+        ]);
+        history.apply(group, &mut voxel_manager);
+        assert_eq!(voxel_manager.get_index(0, 0, 0), Some(3));
+        assert_eq!(voxel_manager.get_index(1, 1, 1), Some(5));
+        assert_eq!(voxel_manager.get_index(3, 3, 3), None);
+        let edited = voxel_manager.clone();
+
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager, original);
+        assert!(!history.undo(&mut voxel_manager));
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager, edited);
+    }
+
+    #[test]
+    fn undo_transform() {
+        let mut rng = Lcg(11);
+        let mut voxel_manager = canvas();
+        for _ in 0..10 {
+            random_command(&mut rng).apply(&mut voxel_manager);
+        }
+        let original = voxel_manager.clone();
+
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        history.apply(
+            Command::Transform {
+                transform: VoxelTransform::Rotate { axis: 1, turns: 1 },
+                region: Some(([1, 2, 3], [8, 4, 2])),
+                bounds: GridBounds::Grow,
+            },
+            &mut voxel_manager,
+        );
+        history.apply(
+            Command::Transform {
+                transform: VoxelTransform::Translate {
+                    offset: [3, 0, 0],
+                    wrap: false,
+                },
+                region: None,
+                bounds: GridBounds::Clip,
+            },
+            &mut voxel_manager,
+        );
+        let transformed = voxel_manager.clone();
+        assert_ne!(transformed, original);
+
+        assert!(history.undo(&mut voxel_manager));
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager, original);
+        assert!(history.redo(&mut voxel_manager));
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager, transformed);
+    }
+
+    #[test]
+    fn undo_palette_edits() {
+        let mut voxel_manager = canvas();
+        voxel_manager.add_box(cube(0.0, 2.0), 0);
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        for i in 1..=10 {
+            let color = [1.0, i as f32 / 10.0, 0.0, 1.0];
+            history.apply(
+                Command::SetPaletteColor { index: 0, color },
+                &mut voxel_manager,
+            );
+        }
+        assert_eq!(voxel_manager.get(1, 1, 1), Some([1.0, 1.0, 0.0, 1.0]));
+
+        // The slider ticks were merged into a single edit
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager.get(1, 1, 1), Some([0.0, 0.0, 0.0, 1.0]));
+        assert!(!history.undo(&mut voxel_manager));
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager.get(0, 0, 0), Some([1.0, 1.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn undo_palette_additions() {
+        let mut voxel_manager = canvas();
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let index = PALETTE_SIZE as u16;
+        history.apply(
+            Command::AddPaletteColor { index, color: red },
+            &mut voxel_manager,
+        );
+        history.apply(Command::AddBox(cube(0.0, 2.0), index), &mut voxel_manager);
+        assert_eq!(voxel_manager.get(1, 1, 1), Some(red));
+
+        assert!(history.undo(&mut voxel_manager));
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager.palette(), canvas().palette());
+        assert_eq!(voxel_manager.materials().len(), PALETTE_SIZE);
+        assert!(history.redo(&mut voxel_manager));
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager.get(1, 1, 1), Some(red));
+
+        // An entry added after the undone one keeps it in the palette, and the redo reuses it
+        assert!(history.undo(&mut voxel_manager));
+        assert!(history.undo(&mut voxel_manager));
+        voxel_manager.color_index([1.0; 4]);
+        voxel_manager.color_index(red);
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager.palette().len(), PALETTE_SIZE + 2);
+        assert!(history.redo(&mut voxel_manager));
+        assert_eq!(voxel_manager.get(1, 1, 1), Some(red));
+    }
+
+    #[test]
+    fn undo_material_edits() {
+        let mut voxel_manager = canvas();
+        voxel_manager.add_box(cube(0.0, 2.0), 3);
+        let original = voxel_manager.clone();
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        let glass = Material {
+            transparency: 0.8,
+            roughness: 0.1,
+            ..Material::default()
+        };
+        history.apply(
+            Command::SetMaterial {
+                index: 3,
+                material: glass,
+            },
+            &mut voxel_manager,
+        );
+        let metal = Material {
+            metallic: 1.0,
+            ..Material::default()
+        };
+        history.apply(
+            Command::SetMaterial {
+                index: 2,
+                material: metal,
+            },
+            &mut voxel_manager,
+        );
+        assert_eq!(voxel_manager.materials()[3], glass);
+        assert_ne!(voxel_manager, original);
+
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager.materials()[2], Material::default());
+        assert!(history.undo(&mut voxel_manager));
+        assert_eq!(voxel_manager, original);
+    }
+
+    #[test]
+    fn undo_layer_edits() {
+        let mut rng = Lcg(3);
+        let mut voxel_manager = canvas();
+        let mut history = History::new(DEFAULT_HISTORY_MEMORY);
+        let mut states = vec![voxel_manager.clone()];
+        // The active layer before every edit
+        let mut active_layers = Vec::new();
+        for _ in 0..100 {
+            let layers = voxel_manager.layers().len();
+            if rng.next(3) == 0 {
+                voxel_manager.set_active_layer(rng.next(layers));
+            }
+            let command = match rng.next(6) {
+                0 => Command::AddLayer,
+                1 if layers > 1 => Command::RemoveLayer(rng.next(layers)),
+                2 => Command::MoveLayer {
+                    from: rng.next(layers),
+                    to: rng.next(layers),
+                },
+                _ => random_command(&mut rng),
+            };
+            active_layers.push(voxel_manager.active_layer());
+            history.apply(command, &mut voxel_manager);
+            states.push(voxel_manager.clone());
+        }
+        assert!(voxel_manager.layers().len() > 1);
+
+        for i in (0..100).rev() {
+            assert!(history.undo(&mut voxel_manager));
+            assert_eq!(voxel_manager, states[i]);
+            assert_eq!(voxel_manager.active_layer(), active_layers[i]);
+            assert_eq!(voxel_manager.voxels(), states[i].voxels());
+        }
+        for state in states.iter().skip(1) {
+            assert!(history.redo(&mut voxel_manager));
+            assert_eq!(&voxel_manager, state);
+            assert_eq!(voxel_manager.voxels(), state.voxels());
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::vertex::{hidden_instance, VoxelInstance};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Identifies a voxel by the index of its object and its position in the object grid
+pub type VoxelKey = (usize, [usize; 3]);
+
+/// Gives every voxel a slot which stays the same until the voxel is released.
+/// The released slots are reused before the allocator grows.
+#[derive(Debug, Default)]
+pub struct SlotAllocator {
+    slots: HashMap<VoxelKey, u32>,
+    free: Vec<u32>,
+    /// One past the highest slot ever allocated
+    end: u32,
+}
+
+impl SlotAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &VoxelKey) -> Option<u32> {
+        self.slots.get(key).copied()
+    }
+
+    /// Returns the slot of the voxel, allocating one if it doesn't have a slot yet
+    pub fn allocate(&mut self, key: VoxelKey) -> u32 {
+        if let Some(slot) = self.get(&key) {
+            return slot;
+        }
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.end += 1;
+                self.end - 1
+            }
+        };
+        self.slots.insert(key, slot);
+        slot
+    }
+
+    /// Frees the slot of the voxel and returns it
+    pub fn release(&mut self, key: &VoxelKey) -> Option<u32> {
+        let slot = self.slots.remove(key)?;
+        self.free.push(slot);
+        Some(slot)
+    }
+
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Returns true if allocating a slot for the voxel would raise the end
+    pub fn grows(&self, key: &VoxelKey) -> bool {
+        !self.slots.contains_key(key) && self.free.is_empty()
+    }
+}
+
+/// Slots of the voxels in the instance buffer. The opaque voxels fill the buffer from
+/// the front and the transparent ones from the back, so the opaque ones are drawn first.
+#[derive(Debug)]
+pub struct InstanceSlots {
+    opaque: SlotAllocator,
+    transparent: SlotAllocator,
+    capacity: u32,
+}
+
+impl InstanceSlots {
+    pub fn new(capacity: u32) -> Self {
+        InstanceSlots {
+            opaque: SlotAllocator::new(),
+            transparent: SlotAllocator::new(),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    fn transparent_slot(&self, slot: u32) -> u32 {
+        self.capacity - 1 - slot
+    }
+
+    /// Updates the instance of a voxel, or releases its slot if it's None. The buffer
+    /// writes are appended to `writes`, released slots are overwritten with a hidden
+    /// instance. Returns false if the buffer is full, the slots have to be rebuilt then.
+    pub fn set(
+        &mut self,
+        key: VoxelKey,
+        instance: Option<VoxelInstance>,
+        writes: &mut Vec<(u32, VoxelInstance)>,
+    ) -> bool {
+        let transparent = instance.map(|instance| instance.is_transparent());
+        if transparent != Some(false) {
+            if let Some(slot) = self.opaque.release(&key) {
+                writes.push((slot, hidden_instance()));
+            }
+        }
+        if transparent != Some(true) {
+            if let Some(slot) = self.transparent.release(&key) {
+                writes.push((self.transparent_slot(slot), hidden_instance()));
+            }
+        }
+        let instance = match instance {
+            Some(instance) => instance,
+            None => return true,
+        };
+
+        let allocator = if instance.is_transparent() {
+            &self.transparent
+        } else {
+            &self.opaque
+        };
+        if allocator.grows(&key) && self.opaque.end() + self.transparent.end() >= self.capacity {
+            return false;
+        }
+        let slot = if instance.is_transparent() {
+            let slot = self.transparent.allocate(key);
+            self.transparent_slot(slot)
+        } else {
+            self.opaque.allocate(key)
+        };
+        writes.push((slot, instance));
+        true
+    }
+
+    /// The ranges of slots to draw, the opaque ones come first
+    pub fn ranges(&self) -> Vec<Range<u32>> {
+        let ranges = vec![
+            0..self.opaque.end(),
+            self.capacity - self.transparent.end()..self.capacity,
+        ];
+        ranges
+            .into_iter()
+            .filter(|range| range.start < range.end)
+            .collect()
+    }
+}
+
+/// Sorts the writes by slot and packs them into runs of consecutive slots, so each run
+/// can be copied with a single command. When a slot is written twice, the last write wins.
+/// Returns the instances and the first slot, first instance and length of every run.
+pub fn pack_writes(
+    mut writes: Vec<(u32, VoxelInstance)>,
+) -> (Vec<VoxelInstance>, Vec<(u32, usize, usize)>) {
+    // The sort is stable, so the writes to a slot keep their order
+    writes.sort_by_key(|(slot, _)| *slot);
+    let mut instances: Vec<VoxelInstance> = Vec::new();
+    let mut runs: Vec<(u32, usize, usize)> = Vec::new();
+    for (slot, instance) in writes {
+        match runs.last_mut() {
+            Some((first, _, count)) if *first + *count as u32 - 1 == slot => {
+                *instances.last_mut().unwrap() = instance;
+            }
+            Some((first, _, count)) if *first + *count as u32 == slot => {
+                instances.push(instance);
+                *count += 1;
+            }
+            _ => {
+                runs.push((slot, instances.len(), 1));
+                instances.push(instance);
+            }
+        }
+    }
+    (instances, runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::vertex::instance;
+
+    fn key(x: usize) -> VoxelKey {
+        (0, [x, 0, 0])
+    }
+
+    fn opaque(x: f32) -> VoxelInstance {
+        instance([x, 0.0, 0.0], [1.0; 4], &Material::default())
+    }
+
+    fn glass(x: f32) -> VoxelInstance {
+        let material = Material {
+            transparency: 0.5,
+            ..Material::default()
+        };
+        instance([x, 0.0, 0.0], [1.0; 4], &material)
+    }
+
+    #[test]
+    fn allocator_reuses_released_slots() {
+        let mut allocator = SlotAllocator::new();
+        assert_eq!(allocator.allocate(key(0)), 0);
+        assert_eq!(allocator.allocate(key(1)), 1);
+        assert_eq!(allocator.allocate(key(2)), 2);
+        // A voxel keeps its slot
+        assert_eq!(allocator.allocate(key(1)), 1);
+
+        assert_eq!(allocator.release(&key(1)), Some(1));
+        assert_eq!(allocator.release(&key(1)), None);
+        assert_eq!(allocator.get(&key(1)), None);
+        assert!(!allocator.grows(&key(3)));
+        assert_eq!(allocator.allocate(key(3)), 1);
+        assert_eq!(allocator.end(), 3);
+
+        assert!(allocator.grows(&key(4)));
+        assert!(!allocator.grows(&key(0)));
+        assert_eq!(allocator.allocate(key(4)), 3);
+        assert_eq!(allocator.end(), 4);
+    }
+
+    #[test]
+    fn allocator_releases_in_any_order() {
+        let mut allocator = SlotAllocator::new();
+        for x in 0..5 {
+            allocator.allocate(key(x));
+        }
+        for x in [3, 0, 4].iter() {
+            allocator.release(&key(*x));
+        }
+        let mut reused: Vec<_> = (5..8).map(|x| allocator.allocate(key(x))).collect();
+        reused.sort();
+        assert_eq!(reused, vec![0, 3, 4]);
+        assert_eq!(allocator.end(), 5);
+        assert_eq!(allocator.allocate(key(8)), 5);
+    }
+
+    #[test]
+    fn transparent_slots_from_the_back() {
+        let mut slots = InstanceSlots::new(10);
+        let mut writes = Vec::new();
+        assert!(slots.set(key(0), Some(opaque(0.0)), &mut writes));
+        assert!(slots.set(key(1), Some(glass(1.0)), &mut writes));
+        assert!(slots.set(key(2), Some(opaque(2.0)), &mut writes));
+        assert_eq!(
+            writes,
+            vec![(0, opaque(0.0)), (9, glass(1.0)), (1, opaque(2.0))]
+        );
+        assert_eq!(slots.ranges(), vec![0..2, 9..10]);
+
+        // Updating a voxel writes its slot again
+        writes.clear();
+        assert!(slots.set(key(2), Some(opaque(4.0)), &mut writes));
+        assert_eq!(writes, vec![(1, opaque(4.0))]);
+
+        // The voxel turns transparent, so it moves to the back
+        writes.clear();
+        assert!(slots.set(key(0), Some(glass(0.0)), &mut writes));
+        assert_eq!(writes, vec![(0, hidden_instance()), (8, glass(0.0))]);
+        assert_eq!(slots.ranges(), vec![0..2, 8..10]);
+
+        writes.clear();
+        assert!(slots.set(key(1), None, &mut writes));
+        assert!(slots.set(key(5), None, &mut writes));
+        assert_eq!(writes, vec![(9, hidden_instance())]);
+    }
+
+    #[test]
+    fn full_buffer() {
+        let mut slots = InstanceSlots::new(3);
+        let mut writes = Vec::new();
+        assert!(slots.ranges().is_empty());
+        assert!(slots.set(key(0), Some(opaque(0.0)), &mut writes));
+        assert!(slots.set(key(1), Some(glass(1.0)), &mut writes));
+        assert!(slots.set(key(2), Some(opaque(2.0)), &mut writes));
+        assert_eq!(slots.ranges(), vec![0..2, 2..3]);
+        assert!(!slots.set(key(3), Some(opaque(3.0)), &mut writes));
+        assert!(!slots.set(key(3), Some(glass(3.0)), &mut writes));
+        // Existing voxels can still be updated
+        assert!(slots.set(key(2), Some(opaque(5.0)), &mut writes));
+
+        // A released opaque slot only makes room for opaque voxels
+        assert!(slots.set(key(0), None, &mut writes));
+        assert!(!slots.set(key(3), Some(glass(3.0)), &mut writes));
+        assert!(slots.set(key(3), Some(opaque(3.0)), &mut writes));
+        assert_eq!(slots.ranges(), vec![0..2, 2..3]);
+    }
+
+    #[test]
+    fn pack_consecutive_writes() {
+        let (instances, runs) = pack_writes(Vec::new());
+        assert!(instances.is_empty());
+        assert!(runs.is_empty());
+
+        let writes = vec![
+            (7, opaque(7.0)),
+            (2, opaque(2.0)),
+            (3, hidden_instance()),
+            (8, opaque(8.0)),
+            (3, opaque(3.0)),
+            (4, opaque(4.0)),
+            (0, opaque(0.0)),
+        ];
+        let (instances, runs) = pack_writes(writes);
+        assert_eq!(
+            instances,
+            vec![
+                opaque(0.0),
+                opaque(2.0),
+                opaque(3.0),
+                opaque(4.0),
+                opaque(7.0),
+                opaque(8.0)
+            ]
+        );
+        assert_eq!(runs, vec![(0, 0, 1), (2, 1, 3), (7, 4, 2)]);
+    }
+}
@@ -1,5 +1,5 @@
 use bytemuck::{Pod, Zeroable};
-use cgmath::{EuclideanSpace, Matrix4, Ortho, Point3, Vector3};
+use cgmath::{EuclideanSpace, Matrix4, Ortho, Point3, Transform, Vector3};
 use iced_wgpu::wgpu;
 
 pub struct Light {
@@ -11,6 +11,10 @@ impl Light {
     pub fn new(pos: Point3<f32>, color: wgpu::Color) -> Self {
         Light { pos, color }
     }
+
+    pub fn set_position(&mut self, pos: Point3<f32>) {
+        self.pos = pos;
+    }
 }
 
 #[repr(C)]
@@ -25,16 +29,33 @@ unsafe impl Pod for LightRaw {}
 unsafe impl Zeroable for LightRaw {}
 
 impl Light {
-    pub fn to_raw(&self, mesh_count: f32) -> LightRaw {
+    /// The shadow map projection is fitted around the canvas of the given dimensions,
+    /// so flat or long canvases don't waste shadow resolution
+    pub fn to_raw(&self, dimensions: [f32; 3]) -> LightRaw {
         let origin = Point3::origin();
         let mx_view = Matrix4::look_at(self.pos, origin, Vector3::unit_y());
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { 0.0 } else { dimensions[0] },
+                if i & 2 == 0 { 0.0 } else { dimensions[1] },
+                if i & 4 == 0 { 0.0 } else { dimensions[2] },
+            );
+            let view_pos = mx_view.transform_point(corner);
+            for axis in 0..3 {
+                min[axis] = min[axis].min(view_pos[axis]);
+                max[axis] = max[axis].max(view_pos[axis]);
+            }
+        }
+        // The light looks towards the negative z axis in view space
         let ortho_projection = Ortho {
-            left: -mesh_count,
-            right: mesh_count,
-            bottom: -mesh_count,
-            top: mesh_count,
-            near: -mesh_count,
-            far: 3.0 * mesh_count,
+            left: min.x - 1.0,
+            right: max.x + 1.0,
+            bottom: min.y - 1.0,
+            top: max.y + 1.0,
+            near: -max.z - 1.0,
+            far: -min.z + 1.0,
         };
         let mx_view_proj = cgmath::Matrix4::from(ortho_projection) * mx_view;
         let light_dir = self.pos - origin;
@@ -2,12 +2,24 @@ mod camera;
 mod color;
 mod controls;
 mod editor;
+mod fill;
 mod fps;
 mod geometry;
+mod history;
+mod instance_slots;
 mod light;
+mod material;
+mod obj;
+mod project;
 mod renderer;
+mod scene;
+mod selection;
+mod shape;
+mod symmetry;
+mod terrain;
 mod ui;
 mod vertex;
+mod vox;
 mod voxel_manager;
 
 use editor::Editor;
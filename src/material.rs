@@ -0,0 +1,57 @@
+/// Surface properties of a palette entry, every property is between 0 and 1. The material
+/// belongs to the palette entry rather than to a voxel, so a voxel gets a material of its own
+/// by getting a palette entry of its own. Only the OBJ/MTL export writes materials, there is
+/// no glTF exporter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Material {
+    /// How much of its own color the voxel emits, independently of the light
+    pub emissive: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// How much of the color alpha is removed, glass is highly transparent
+    pub transparency: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            emissive: 0.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            transparency: 0.0,
+        }
+    }
+}
+
+impl Material {
+    pub const PROPERTY_NAMES: [&'static str; 4] =
+        ["Emissive", "Metallic", "Roughness", "Transparency"];
+
+    /// The properties in the order of `PROPERTY_NAMES`, which is also their order in the shader
+    pub fn to_array(self) -> [f32; 4] {
+        [
+            self.emissive,
+            self.metallic,
+            self.roughness,
+            self.transparency,
+        ]
+    }
+
+    pub fn from_array(properties: [f32; 4]) -> Self {
+        Material {
+            emissive: properties[0],
+            metallic: properties[1],
+            roughness: properties[2],
+            transparency: properties[3],
+        }
+    }
+
+    /// The opacity of a surface with the given color and this material
+    pub fn alpha(&self, color: &[f32; 4]) -> f32 {
+        color[3] * (1.0 - self.transparency)
+    }
+
+    pub fn is_transparent(&self, color: &[f32; 4]) -> bool {
+        self.alpha(color) < 1.0
+    }
+}
@@ -0,0 +1,359 @@
+use crate::material::Material;
+use crate::vertex::MeshVertex;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+pub const OBJ_EXTENSION: &str = "obj";
+pub const MTL_EXTENSION: &str = "mtl";
+
+/// How the voxel colors are stored in the exported file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// One material per distinct color in a companion .mtl file
+    Material,
+    /// The `v x y z r g b` extension, supported by most tools
+    VertexColor,
+}
+
+fn color_key(color: &[f32; 4]) -> [u32; 4] {
+    [
+        color[0].to_bits(),
+        color[1].to_bits(),
+        color[2].to_bits(),
+        color[3].to_bits(),
+    ]
+}
+
+pub fn material_name(idx: usize) -> String {
+    format!("color_{}", idx)
+}
+
+/// Returns the distinct color and material pairs of the model and the material index of
+/// every triangle
+pub fn materials(
+    vertices: &[MeshVertex],
+    indices: &[u32],
+) -> (Vec<([f32; 4], Material)>, Vec<usize>) {
+    let mut materials = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut triangle_materials = Vec::new();
+    for id in indices.chunks(3) {
+        let vertex = &vertices[id[0] as usize];
+        let key = (
+            color_key(&vertex.color),
+            color_key(&vertex.material.to_array()),
+        );
+        let idx = *lookup.entry(key).or_insert_with(|| {
+            materials.push((vertex.color, vertex.material));
+            materials.len() - 1
+        });
+        triangle_materials.push(idx);
+    }
+    (materials, triangle_materials)
+}
+
+/// Writes one material per voxel color and material, that is per palette entry in use. The
+/// emission, roughness and metallic values use the common PBR extension of the format.
+pub fn write_mtl<W: Write>(writer: &mut W, materials: &[([f32; 4], Material)]) -> io::Result<()> {
+    writer.write_all(b"# Material library with one material per voxel color\n")?;
+    for (idx, (color, material)) in materials.iter().enumerate() {
+        writer.write_all(
+            format!(
+                "newmtl {}\nKd {:.3} {:.3} {:.3}\nd {:.3}\nKe {:.3} {:.3} {:.3}\nPr {:.3}\nPm {:.3}\nillum 1\n\n",
+                material_name(idx),
+                color[0],
+                color[1],
+                color[2],
+                material.alpha(color),
+                color[0] * material.emissive,
+                color[1] * material.emissive,
+                color[2] * material.emissive,
+                material.roughness,
+                material.metallic,
+            )
+            .as_ref(),
+        )?;
+    }
+    writer.flush()
+}
+
+/// Writes every distinct line once and returns the index of each line in the output.
+/// The faces share positions and normals this way, so the exported surface is closed.
+fn write_unique<W: Write, I: Iterator<Item = String>>(
+    writer: &mut W,
+    lines: I,
+) -> io::Result<Vec<usize>> {
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::new();
+    for line in lines {
+        let next = lookup.len();
+        let idx = *lookup.entry(line.clone()).or_insert(next);
+        if idx == next {
+            writer.write_all(line.as_ref())?;
+        }
+        indices.push(idx);
+    }
+    Ok(indices)
+}
+
+/// Writes the model as an OBJ file. In `ColorMode::Material` mode the faces are grouped
+/// by `usemtl` statements, which refer to the materials of `mtl_file_name`.
+/// `objects` has the name and triangle count of the consecutive triangles of every
+/// object, each of them starts with an `o` statement. The model is written as a single
+/// object when it's empty.
+pub fn write_obj<W: Write>(
+    writer: &mut W,
+    vertex_data: &[MeshVertex],
+    indices: &[u32],
+    color_mode: ColorMode,
+    mtl_file_name: &str,
+    objects: &[(String, usize)],
+) -> io::Result<()> {
+    if color_mode == ColorMode::Material {
+        writer.write_all(format!("mtllib {}\n", mtl_file_name).as_ref())?;
+    }
+
+    let header: &[u8] = match color_mode {
+        ColorMode::Material => b"# List of geometric vertices, with (x, y, z [,w]) coordinates, w is optional and defaults to 1.0.\n",
+        ColorMode::VertexColor => b"# List of geometric vertices, with (x, y, z) coordinates and (r, g, b) vertex colors.\n",
+    };
+    writer.write_all(header)?;
+
+    let position_lines = vertex_data.iter().map(|vd| match color_mode {
+        ColorMode::Material => {
+            format!("v {:.3} {:.3} {:.3} 1.0\n", vd.pos[0], vd.pos[1], vd.pos[2])
+        }
+        ColorMode::VertexColor => format!(
+            "v {:.3} {:.3} {:.3} {:.3} {:.3} {:.3}\n",
+            vd.pos[0], vd.pos[1], vd.pos[2], vd.color[0], vd.color[1], vd.color[2]
+        ),
+    });
+    let positions = write_unique(writer, position_lines)?;
+
+    writer.write_all(
+        b"# List of vertex normals in (x,y,z) form; normals might not be unit vectors.\n",
+    )?;
+
+    let normal_lines = vertex_data.iter().map(|vd| {
+        format!(
+            "vn {:.3} {:.3} {:.3}\n",
+            vd.normal[0], vd.normal[1], vd.normal[2]
+        )
+    });
+    let normals = write_unique(writer, normal_lines)?;
+
+    writer.write_all(b"# Polygonal face element\n")?;
+
+    let triangle_materials = match color_mode {
+        ColorMode::Material => materials(vertex_data, indices).1,
+        ColorMode::VertexColor => vec![0; indices.len() / 3],
+    };
+    // The triangles after the listed objects don't belong to any of them
+    let mut triangle_objects = Vec::new();
+    for (idx, (_, count)) in objects.iter().enumerate() {
+        triangle_objects.resize(triangle_objects.len() + count, idx);
+    }
+    triangle_objects.resize(indices.len() / 3, objects.len());
+    let mut triangles: Vec<(usize, usize, &[u32])> = triangle_objects
+        .into_iter()
+        .zip(triangle_materials)
+        .zip(indices.chunks(3))
+        .map(|((object, material), id)| (object, material, id))
+        .collect();
+    // Stable sort, so the triangles of a material stay in their original order
+    triangles.sort_by_key(|t| (t.0, t.1));
+
+    let mut current_object = None;
+    let mut current_material = None;
+    for (object, material, id) in triangles {
+        if object < objects.len() && current_object != Some(object) {
+            writer.write_all(format!("o {}\n", objects[object].0).as_ref())?;
+            current_object = Some(object);
+            current_material = None;
+        }
+        if color_mode == ColorMode::Material && current_material != Some(material) {
+            writer.write_all(format!("usemtl {}\n", material_name(material)).as_ref())?;
+            current_material = Some(material);
+        }
+        writer.write_all(
+            format!(
+                "f {}//{} {}//{} {}//{}\n",
+                positions[id[0] as usize] + 1,
+                normals[id[0] as usize] + 1,
+                positions[id[1] as usize] + 1,
+                normals[id[1] as usize] + 1,
+                positions[id[2] as usize] + 1,
+                normals[id[2] as usize] + 1
+            )
+            .as_ref(),
+        )?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vertex::mesh_vertex;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 0.5];
+
+    fn two_triangles() -> (Vec<MeshVertex>, Vec<u32>) {
+        let normal = [0.0, 0.0, 1.0];
+        let vertices = vec![
+            mesh_vertex([0.0, 0.0, 0.0], normal, GREEN),
+            mesh_vertex([1.0, 0.0, 0.0], normal, GREEN),
+            mesh_vertex([1.0, 1.0, 0.0], normal, GREEN),
+            mesh_vertex([0.0, 0.0, 1.0], normal, RED),
+            mesh_vertex([1.0, 0.0, 1.0], normal, RED),
+            mesh_vertex([1.0, 1.0, 1.0], normal, RED),
+        ];
+        (vertices, vec![3, 4, 5, 0, 1, 2, 5, 4, 3])
+    }
+
+    fn to_string(bytes: Vec<u8>) -> String {
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn distinct_materials() {
+        let (vertices, indices) = two_triangles();
+        let (materials, triangle_materials) = materials(&vertices, &indices);
+        assert_eq!(
+            materials,
+            vec![(RED, Material::default()), (GREEN, Material::default())]
+        );
+        assert_eq!(triangle_materials, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn obj_with_materials() {
+        let (vertices, indices) = two_triangles();
+        let mut bytes = Vec::new();
+        write_obj(
+            &mut bytes,
+            &vertices,
+            &indices,
+            ColorMode::Material,
+            "model.mtl",
+            &[],
+        )
+        .unwrap();
+        let obj = to_string(bytes);
+
+        assert!(obj.starts_with("mtllib model.mtl\n"));
+        assert!(obj.contains("v 0.000 0.000 1.000 1.0\n"));
+        assert!(obj.contains(
+            "usemtl color_0\nf 4//1 5//1 6//1\nf 6//1 5//1 4//1\nusemtl color_1\nf 1//1 2//1 3//1\n"
+        ));
+    }
+
+    #[test]
+    fn obj_with_vertex_colors() {
+        let (vertices, indices) = two_triangles();
+        let mut bytes = Vec::new();
+        write_obj(
+            &mut bytes,
+            &vertices,
+            &indices,
+            ColorMode::VertexColor,
+            "model.mtl",
+            &[],
+        )
+        .unwrap();
+        let obj = to_string(bytes);
+
+        assert!(!obj.contains("mtllib"));
+        assert!(!obj.contains("usemtl"));
+        assert!(obj.contains("v 0.000 0.000 1.000 1.000 0.000 0.000\n"));
+        assert!(obj.contains("f 4//1 5//1 6//1\nf 1//1 2//1 3//1\nf 6//1 5//1 4//1\n"));
+    }
+
+    #[test]
+    fn shared_positions_and_normals() {
+        let normal = [0.0, 1.0, 0.0];
+        let vertices = vec![
+            mesh_vertex([0.0, 0.0, 0.0], normal, RED),
+            mesh_vertex([1.0, 0.0, 0.0], normal, RED),
+            mesh_vertex([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], GREEN),
+            mesh_vertex([1.0, 0.0, 0.0], normal, GREEN),
+        ];
+        let mut bytes = Vec::new();
+        write_obj(
+            &mut bytes,
+            &vertices,
+            &[0, 1, 2, 3, 2, 1],
+            ColorMode::Material,
+            "model.mtl",
+            &[],
+        )
+        .unwrap();
+        let obj = to_string(bytes);
+
+        assert_eq!(obj.matches("\nv ").count(), 2);
+        assert_eq!(obj.matches("\nvn ").count(), 2);
+        assert!(obj.contains("f 1//1 2//1 1//2\n"));
+        assert!(obj.contains("f 2//1 1//2 2//1\n"));
+    }
+
+    #[test]
+    fn obj_with_objects() {
+        let (vertices, indices) = two_triangles();
+        let mut bytes = Vec::new();
+        write_obj(
+            &mut bytes,
+            &vertices,
+            &indices,
+            ColorMode::Material,
+            "model.mtl",
+            &[("Object 1".to_string(), 2), ("Object 2".to_string(), 1)],
+        )
+        .unwrap();
+        let obj = to_string(bytes);
+
+        assert!(obj.contains(
+            "o Object 1\nusemtl color_0\nf 4//1 5//1 6//1\nusemtl color_1\nf 1//1 2//1 3//1\no Object 2\nusemtl color_0\nf 6//1 5//1 4//1\n"
+        ));
+    }
+
+    #[test]
+    fn distinct_surface_materials() {
+        let (mut vertices, indices) = two_triangles();
+        // The material of a triangle is the material of its first vertex
+        vertices[3].material.emissive = 1.0;
+        vertices[4].material.metallic = 1.0;
+        let (materials, triangle_materials) = materials(&vertices, &indices);
+        assert_eq!(materials.len(), 3);
+        assert_eq!(materials[0].1.emissive, 1.0);
+        assert_eq!(triangle_materials, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn mtl_file() {
+        let mut bytes = Vec::new();
+        let glass = Material {
+            emissive: 0.5,
+            metallic: 0.25,
+            roughness: 0.1,
+            transparency: 0.8,
+        };
+        write_mtl(
+            &mut bytes,
+            &[
+                (RED, Material::default()),
+                (GREEN, Material::default()),
+                (RED, glass),
+            ],
+        )
+        .unwrap();
+        let mtl = to_string(bytes);
+
+        assert!(mtl.contains("newmtl color_0\nKd 1.000 0.000 0.000\nd 1.000\n"));
+        assert!(mtl.contains("newmtl color_1\nKd 0.000 1.000 0.000\nd 0.500\n"));
+        assert!(mtl.contains(
+            "newmtl color_2\nKd 1.000 0.000 0.000\nd 0.200\nKe 0.500 0.000 0.000\nPr 0.100\nPm 0.250\n"
+        ));
+    }
+}
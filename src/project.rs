@@ -0,0 +1,566 @@
+use crate::camera::CameraState;
+use crate::material::Material;
+use crate::scene::{Rotation, Scene, SceneObject, Transform};
+use crate::voxel_manager::VoxelManager;
+use std::io::{self, Read, Write};
+
+pub const PROJECT_EXTENSION: &str = "vxp";
+const MAGIC: &[u8; 4] = b"VXPR";
+pub const VERSION: u32 = 6;
+
+/// Everything needed to continue editing a document later on
+pub struct Project {
+    pub scene: Scene,
+    pub camera: CameraState,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32<W: Write>(writer: &mut W, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_bits().to_le_bytes())
+}
+
+fn write_color<W: Write>(writer: &mut W, color: &[f32; 4]) -> io::Result<()> {
+    for c in color.iter() {
+        write_f32(writer, *c)?;
+    }
+    Ok(())
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(reader)?))
+}
+
+fn read_color<R: Read>(reader: &mut R) -> io::Result<[f32; 4]> {
+    let mut color = [0.0; 4];
+    for c in color.iter_mut() {
+        *c = read_f32(reader)?;
+    }
+    Ok(color)
+}
+
+fn write_i32<W: Write>(writer: &mut W, value: i32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    Ok(read_u32(reader)? as i32)
+}
+
+fn write_name<W: Write>(writer: &mut W, name: &str) -> io::Result<()> {
+    write_u32(writer, name.len() as u32)?;
+    writer.write_all(name.as_bytes())
+}
+
+fn read_name<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
+    let name_len = read_u32(reader)? as u64;
+    let mut name = Vec::new();
+    reader.by_ref().take(name_len).read_to_end(&mut name)?;
+    if name.len() as u64 != name_len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8(name).ok())
+}
+
+fn write_camera<W: Write>(writer: &mut W, camera: &CameraState) -> io::Result<()> {
+    for t in camera.target.iter() {
+        write_f32(writer, *t)?;
+    }
+    write_f32(writer, camera.rotation.0)?;
+    for r in camera.rotation.1.iter() {
+        write_f32(writer, *r)?;
+    }
+    write_f32(writer, camera.distance)?;
+    write_f32(writer, camera.pitch)?;
+    write_f32(writer, camera.yaw)
+}
+
+fn read_camera<R: Read>(reader: &mut R) -> io::Result<CameraState> {
+    let mut target = [0.0; 3];
+    for t in target.iter_mut() {
+        *t = read_f32(reader)?;
+    }
+    let w = read_f32(reader)?;
+    let mut xyz = [0.0; 3];
+    for r in xyz.iter_mut() {
+        *r = read_f32(reader)?;
+    }
+    Ok(CameraState {
+        target,
+        rotation: (w, xyz),
+        distance: read_f32(reader)?,
+        pitch: read_f32(reader)?,
+        yaw: read_f32(reader)?,
+    })
+}
+
+/// Writes the extent, the palette colors and the palette materials
+fn write_grid<W: Write>(writer: &mut W, voxel_manager: &VoxelManager) -> io::Result<()> {
+    for e in voxel_manager.extent().iter() {
+        write_u32(writer, *e as u32)?;
+    }
+
+    let palette = voxel_manager.palette();
+    write_u32(writer, palette.len() as u32)?;
+    for color in palette.iter() {
+        write_color(writer, color)?;
+    }
+    for material in voxel_manager.materials().iter() {
+        for property in material.to_array().iter() {
+            write_f32(writer, *property)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_grid<R: Read>(reader: &mut R, version: u32) -> io::Result<VoxelManager> {
+    let mut extent = [read_u32(reader)? as usize; 3];
+    if version > 1 {
+        extent[1] = read_u32(reader)? as usize;
+        extent[2] = read_u32(reader)? as usize;
+    }
+    let mut voxel_manager = VoxelManager::new(extent);
+
+    let palette_len = read_u32(reader)? as usize;
+    if palette_len > u16::MAX as usize {
+        return Err(invalid_data(format!(
+            "The palette has {} colors, at most {} are supported",
+            palette_len,
+            u16::MAX
+        )));
+    }
+    let mut palette = Vec::new();
+    for _ in 0..palette_len {
+        palette.push(read_color(reader)?);
+    }
+    voxel_manager.set_palette(palette);
+    if version > 3 {
+        let mut materials = Vec::new();
+        for _ in 0..palette_len {
+            let mut properties = [0.0; 4];
+            for p in properties.iter_mut() {
+                *p = read_f32(reader)?;
+            }
+            materials.push(Material::from_array(properties));
+        }
+        voxel_manager.set_materials(materials);
+    }
+    Ok(voxel_manager)
+}
+
+fn write_layers<W: Write>(writer: &mut W, voxel_manager: &VoxelManager) -> io::Result<()> {
+    write_u32(writer, voxel_manager.layers().len() as u32)?;
+    write_u32(writer, voxel_manager.active_layer() as u32)?;
+    for layer in voxel_manager.layers().iter() {
+        write_name(writer, &layer.name)?;
+        write_u8(writer, layer.visible as u8)?;
+        write_u8(writer, layer.locked as u8)?;
+
+        let voxels = layer.voxels();
+        write_u32(writer, voxels.len() as u32)?;
+        for (pos, idx) in voxels.iter() {
+            for p in pos.iter() {
+                write_u32(writer, *p as u32)?;
+            }
+            write_u16(writer, *idx)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_voxels<R: Read>(
+    reader: &mut R,
+    version: u32,
+    voxel_manager: &mut VoxelManager,
+    layer: usize,
+) -> io::Result<()> {
+    let extent = voxel_manager.extent();
+    let palette_len = voxel_manager.palette().len();
+    let voxel_count = read_u32(reader)?;
+    for _ in 0..voxel_count {
+        let mut pos = [0; 3];
+        for (p, e) in pos.iter_mut().zip(extent.iter()) {
+            *p = read_u32(reader)? as usize;
+            if *p >= *e {
+                return Err(invalid_data(format!(
+                    "Voxel coordinate {} is outside of the {}x{}x{} grid",
+                    p, extent[0], extent[1], extent[2]
+                )));
+            }
+        }
+        if version < 3 {
+            let color = read_color(reader)?;
+            voxel_manager.set(pos[0], pos[1], pos[2], color);
+        } else {
+            let idx = read_u16(reader)?;
+            if idx as usize >= palette_len {
+                return Err(invalid_data(format!(
+                    "Palette index {} is outside of the {} color palette",
+                    idx, palette_len
+                )));
+            }
+            voxel_manager.set_layer_cell(layer, pos[0], pos[1], pos[2], Some(idx));
+        }
+    }
+    Ok(())
+}
+
+fn read_layers<R: Read>(
+    reader: &mut R,
+    version: u32,
+    voxel_manager: &mut VoxelManager,
+) -> io::Result<()> {
+    if version < 5 {
+        return read_voxels(reader, version, voxel_manager, 0);
+    }
+    let layer_count = read_u32(reader)? as usize;
+    let active_layer = read_u32(reader)? as usize;
+    if layer_count == 0 || active_layer >= layer_count {
+        return Err(invalid_data(format!(
+            "Active layer {} of {} layers doesn't exist",
+            active_layer, layer_count
+        )));
+    }
+    for layer in 0..layer_count {
+        if layer > 0 {
+            voxel_manager.add_layer();
+        }
+        let name = read_name(reader)?
+            .ok_or_else(|| invalid_data(format!("Layer {} has an invalid name", layer)))?;
+        voxel_manager.rename_layer(layer, name);
+        voxel_manager.set_layer_visible(layer, read_u8(reader)? != 0);
+        voxel_manager.set_layer_locked(layer, read_u8(reader)? != 0);
+        read_voxels(reader, version, voxel_manager, layer)?;
+    }
+    voxel_manager.set_active_layer(active_layer);
+    Ok(())
+}
+
+/// Checks that every row and every column has a single 1 or -1
+fn is_rotation(rotation: &Rotation) -> bool {
+    let mut columns = [false; 3];
+    for row in rotation.iter() {
+        match row.iter().position(|v| *v != 0) {
+            Some(column)
+                if row[column].abs() == 1
+                    && !columns[column]
+                    && row.iter().filter(|v| **v != 0).count() == 1 =>
+            {
+                columns[column] = true
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Layout of the file (every value is little endian):
+/// magic, version, camera state, object count, active object, then for every object its
+/// name, rotation matrix, translation, extent on the x, y and z axes, palette colors,
+/// palette materials, layer count, active layer, and the name, visibility, lock and
+/// filled voxels with their palette index of every layer.
+/// Files before version 6 stored a single object without name and transform, with the
+/// camera state between the palette and the layers. Version 1 files stored a single
+/// extent for cubic grids, files before version 3 stored the color of every voxel, files
+/// before version 4 had no materials and files before version 5 had a single layer.
+pub fn save<W: Write>(writer: &mut W, scene: &Scene, camera: &CameraState) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+    write_camera(writer, camera)?;
+
+    write_u32(writer, scene.objects().len() as u32)?;
+    write_u32(writer, scene.active() as u32)?;
+    for object in scene.objects().iter() {
+        write_name(writer, &object.name)?;
+        for row in object.transform.rotation.iter() {
+            for value in row.iter() {
+                write_i32(writer, *value)?;
+            }
+        }
+        for t in object.transform.translation.iter() {
+            write_i32(writer, *t)?;
+        }
+        write_grid(writer, &object.voxel_manager)?;
+        write_layers(writer, &object.voxel_manager)?;
+    }
+    writer.flush()
+}
+
+pub fn load<R: Read>(reader: &mut R) -> io::Result<Project> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a voxel-editor project file".to_string()));
+    }
+    let version = read_u32(reader)?;
+    if version > VERSION {
+        return Err(invalid_data(format!(
+            "Project file version {} is newer than the supported version {}",
+            version, VERSION
+        )));
+    }
+
+    if version < 6 {
+        let mut voxel_manager = read_grid(reader, version)?;
+        let camera = read_camera(reader)?;
+        read_layers(reader, version, &mut voxel_manager)?;
+        return Ok(Project {
+            scene: Scene::new(voxel_manager),
+            camera,
+        });
+    }
+
+    let camera = read_camera(reader)?;
+    let object_count = read_u32(reader)? as usize;
+    let active_object = read_u32(reader)? as usize;
+    if object_count == 0 || active_object >= object_count {
+        return Err(invalid_data(format!(
+            "Active object {} of {} objects doesn't exist",
+            active_object, object_count
+        )));
+    }
+    let mut objects = Vec::new();
+    for object in 0..object_count {
+        let name = read_name(reader)?
+            .ok_or_else(|| invalid_data(format!("Object {} has an invalid name", object)))?;
+        let mut transform = Transform::default();
+        for row in transform.rotation.iter_mut() {
+            for value in row.iter_mut() {
+                *value = read_i32(reader)?;
+            }
+        }
+        if !is_rotation(&transform.rotation) {
+            return Err(invalid_data(format!(
+                "Object {} has an invalid rotation {:?}",
+                object, transform.rotation
+            )));
+        }
+        for t in transform.translation.iter_mut() {
+            *t = read_i32(reader)?;
+        }
+        let mut voxel_manager = read_grid(reader, version)?;
+        read_layers(reader, version, &mut voxel_manager)?;
+        objects.push(SceneObject {
+            name,
+            voxel_manager,
+            transform,
+        });
+    }
+
+    Ok(Project {
+        scene: Scene::from_objects(objects, active_object),
+        camera,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::BoundingBox;
+    use cgmath::Vector3;
+
+    fn camera() -> CameraState {
+        CameraState {
+            target: [16.0, 16.0, 16.0],
+            rotation: (0.5, [0.1, 0.2, 0.3]),
+            distance: 64.0,
+            pitch: 0.25,
+            yaw: -1.5,
+        }
+    }
+
+    fn populated_manager() -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new([8, 6, 10]);
+        let idx = voxel_manager.color_index([0.1, 0.2, 0.3, 1.0]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(1.0, 1.0, 1.0),
+                Vector3::new(3.0, 2.0, 4.0),
+                [0.1, 0.2, 0.3, 1.0],
+            ),
+            idx,
+        );
+        let idx = voxel_manager.color_index([1.0 / 3.0, 0.0, 1.0, 0.5]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(0.0, 5.0, 7.0),
+                Vector3::new(1.0, 1.0, 1.0),
+                [1.0 / 3.0, 0.0, 1.0, 0.5],
+            ),
+            idx,
+        );
+        voxel_manager
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut voxel_manager = VoxelManager::new([8, 6, 10]);
+        voxel_manager.set_palette(vec![[1.0, 0.0, 0.0, 1.0], [0.02, 0.02, 0.02, 1.0]]);
+        for (pos, color) in populated_manager().voxels() {
+            voxel_manager.set(pos[0], pos[1], pos[2], color);
+        }
+        voxel_manager.set_material(
+            1,
+            Material {
+                emissive: 0.5,
+                transparency: 0.25,
+                ..Material::default()
+            },
+        );
+        voxel_manager.add_layer();
+        voxel_manager.rename_layer(1, "Interior ✓".to_string());
+        voxel_manager.set_index(1, 1, 1, 0);
+        voxel_manager.set_index(7, 5, 9, 1);
+        voxel_manager.set_layer_visible(1, false);
+        voxel_manager.set_layer_locked(1, true);
+        voxel_manager.set_active_layer(0);
+        let mut scene = Scene::new(voxel_manager.clone());
+        scene.add_object(populated_manager());
+        let mut transform = scene.objects()[1].transform;
+        transform.rotate(2, [8, 6, 10]);
+        transform.translation[1] = -4;
+        scene.set_transform(1, transform);
+        scene.set_active(0);
+        let mut bytes = Vec::new();
+        save(&mut bytes, &scene, &camera()).unwrap();
+
+        let project = load(&mut bytes.as_slice()).unwrap();
+        assert_eq!(project.scene, scene);
+        assert_eq!(project.scene.active(), 0);
+        assert_eq!(project.scene.objects()[1].transform, transform);
+        let loaded = project.scene.voxel_manager();
+        assert_eq!(*loaded, voxel_manager);
+        assert_eq!(loaded.active_layer(), 0);
+        assert_eq!(loaded.layers()[1].voxels().len(), 2);
+        assert_eq!(loaded.palette(), voxel_manager.palette());
+        assert_eq!(loaded.materials(), voxel_manager.materials());
+        assert_eq!(loaded.indexed_voxels(), voxel_manager.indexed_voxels());
+        assert_eq!(project.camera, camera());
+    }
+
+    #[test]
+    fn loads_color_version_2() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        for value in [2, 4, 5, 6, 1].iter() {
+            bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+        }
+        for _ in 0..14 {
+            bytes.extend_from_slice(&1.0f32.to_bits().to_le_bytes());
+        }
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        for pos in [[3u32, 0, 2], [0, 4, 5]].iter() {
+            for value in pos.iter() {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            for _ in 0..4 {
+                bytes.extend_from_slice(&0.5f32.to_bits().to_le_bytes());
+            }
+        }
+
+        let project = load(&mut bytes.as_slice()).unwrap();
+        let voxel_manager = project.scene.voxel_manager();
+        assert_eq!(voxel_manager.extent(), [4, 5, 6]);
+        assert_eq!(voxel_manager.palette(), &[[1.0; 4], [0.5; 4]]);
+        assert_eq!(voxel_manager.get_index(3, 0, 2), Some(1));
+        assert_eq!(voxel_manager.get_index(0, 4, 5), Some(1));
+    }
+
+    #[test]
+    fn rejects_invalid_palette_index() {
+        let mut bytes = Vec::new();
+        save(&mut bytes, &Scene::new(populated_manager()), &camera()).unwrap();
+        let len = bytes.len();
+        bytes[len - 2..].copy_from_slice(&7u16.to_le_bytes());
+
+        let err = load(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Palette index 7"));
+    }
+
+    #[test]
+    fn loads_cubic_version_1() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        for value in [1, 4, 0].iter() {
+            bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+        }
+        for _ in 0..10 {
+            bytes.extend_from_slice(&1.0f32.to_bits().to_le_bytes());
+        }
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for value in [3u32, 0, 2].iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for _ in 0..4 {
+            bytes.extend_from_slice(&0.5f32.to_bits().to_le_bytes());
+        }
+
+        let project = load(&mut bytes.as_slice()).unwrap();
+        assert_eq!(project.scene.objects().len(), 1);
+        assert_eq!(project.scene.voxel_manager().extent(), [4, 4, 4]);
+        assert_eq!(project.scene.voxel_manager().get(3, 0, 2), Some([0.5; 4]));
+    }
+
+    #[test]
+    fn rejects_invalid_rotation() {
+        let mut bytes = Vec::new();
+        save(&mut bytes, &Scene::new(populated_manager()), &camera()).unwrap();
+        // The rotation follows the camera, the object count, the active object and the name
+        let rotation = 8 + 4 * 11 + 8 + 4 + "Object 1".len();
+        bytes[rotation..rotation + 4].copy_from_slice(&2i32.to_le_bytes());
+
+        let err = load(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("invalid rotation"));
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let mut bytes = Vec::new();
+        save(&mut bytes, &Scene::new(populated_manager()), &camera()).unwrap();
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        let err = load(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("newer"));
+    }
+
+    #[test]
+    fn rejects_foreign_file() {
+        let bytes = b"# obj file\n".to_vec();
+        assert!(load(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -1,13 +1,17 @@
 use crate::camera::CameraWrapper;
 use crate::color::*;
 use crate::geometry::*;
+use crate::instance_slots::{pack_writes, InstanceSlots, VoxelKey};
 use crate::light::*;
+use crate::scene::Scene;
+use crate::shape;
+use crate::symmetry::Symmetry;
 use crate::ui::{build_ui_pipeline, Ui};
 use crate::vertex::*;
-use crate::voxel_manager::VoxelManager;
 use cgmath;
 use iced_wgpu::wgpu;
 use iced_winit::mouse::Interaction;
+use std::ops::Range;
 use std::rc::Rc;
 
 pub const DEFAULT_MESH_COUNT: u16 = 32;
@@ -52,14 +56,227 @@ fn generate_cursor_vertices(bbox: &BoundingBox) -> (Vec<Vertex>, Vec<u16>) {
     (bbox.vertices(), index_data)
 }
 
+/// The most mirror images of the cursor, one for every combination of the three planes
+const MAX_MIRRORED_CURSORS: usize = 7;
+
+/// The most boxes in the preview of a shape, the indices of their vertices have to fit in u16
+const MAX_SHAPE_CURSORS: usize = 2048;
+
+/// Fewest slots of the instance buffer, so the first edits of an empty scene don't rebuild it
+const MIN_INSTANCE_CAPACITY: usize = 4096;
+
+/// Each voxel has a slot in the instance buffer. It's sized for the voxels of the scene with
+/// room for the ones added by the next edits, and rebuilt when it's full.
+fn instance_capacity(instance_count: usize) -> usize {
+    (2 * instance_count).max(MIN_INSTANCE_CAPACITY)
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> Rc<wgpu::Buffer> {
+    Rc::new(device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (capacity * std::mem::size_of::<VoxelInstance>()) as u64,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+    }))
+}
+
+/// The light shines from the same direction whatever the shape of the canvas is
+fn light_position(dimensions: [u16; 3]) -> cgmath::Point3<f32> {
+    let size = *dimensions.iter().max().unwrap() as f32;
+    cgmath::Point3::new(size / 2.0, size * 1.5, size * 2.0)
+}
+
+/// The voxel pipeline either draws instanced cubes or a mesh, whose per vertex color data
+/// uses the instance layout with a zero offset. Transparent voxels are blended over what
+/// is already drawn, so they have to come after the opaque ones.
+fn create_voxel_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    instance_step_mode: wgpu::InputStepMode,
+    index_format: wgpu::IndexFormat,
+) -> wgpu::RenderPipeline {
+    use std::mem;
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format,
+            vertex_buffers: &[
+                wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<VoxelVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        // Position
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        // Normal
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 3 * 4,
+                            shader_location: 1,
+                        },
+                    ],
+                },
+                wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<VoxelInstance>() as wgpu::BufferAddress,
+                    step_mode: instance_step_mode,
+                    attributes: &[
+                        // Offset
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 0,
+                            shader_location: 2,
+                        },
+                        // Color
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 3 * 4,
+                            shader_location: 3,
+                        },
+                        // Material
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 7 * 4,
+                            shader_location: 4,
+                        },
+                    ],
+                },
+            ],
+        },
+        sample_count: SAMPLE_COUNT,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+fn create_shadow_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+    instance_step_mode: wgpu::InputStepMode,
+    index_format: wgpu::IndexFormat,
+) -> wgpu::RenderPipeline {
+    use std::mem;
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        layout,
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            depth_bias: 2,
+            depth_bias_slope_scale: 2.0,
+            depth_bias_clamp: 0.0,
+        }),
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        color_states: &[],
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: SHADOW_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format,
+            vertex_buffers: &[
+                wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<VoxelVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        // Position
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                    ],
+                },
+                wgpu::VertexBufferDescriptor {
+                    stride: mem::size_of::<VoxelInstance>() as wgpu::BufferAddress,
+                    step_mode: instance_step_mode,
+                    attributes: &[
+                        // Offset
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 0,
+                            shader_location: 1,
+                        },
+                        // Color, the unused slots have no alpha
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 4 * 3,
+                            shader_location: 2,
+                        },
+                    ],
+                },
+            ],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
 struct Pipeline {
-    bind_group: wgpu::BindGroup,
-    pipeline: wgpu::RenderPipeline,
+    bind_group: Rc<wgpu::BindGroup>,
+    pipeline: Rc<wgpu::RenderPipeline>,
     vertex_buf: Rc<wgpu::Buffer>,
     instance_buf: Option<Rc<wgpu::Buffer>>,
     index_buf: Rc<wgpu::Buffer>,
     index_count: usize,
-    instance_count: usize,
+    /// The ranges of instances to draw
+    instances: Vec<Range<u32>>,
 }
 
 impl Pipeline {
@@ -71,7 +288,9 @@ impl Pipeline {
         if let Some(ref instance_buf) = self.instance_buf {
             render_pass.set_vertex_buffer(1, instance_buf, 0, 0);
         }
-        render_pass.draw_indexed(0..self.index_count as u32, 0, 0..self.instance_count as u32);
+        for instances in self.instances.iter() {
+            render_pass.draw_indexed(0..self.index_count as u32, 0, instances.clone());
+        }
     }
 }
 
@@ -89,15 +308,30 @@ pub struct Renderer {
     mesh_pipeline: Pipeline,
     render_cursor: bool,
     cursor_pipeline: Pipeline,
+    /// Draws the mirror images of the cursor in the symmetric drawing mode
+    mirrored_cursor_pipeline: Pipeline,
+    /// Draws the cells of the shape being drawn instead of the cursor
+    shape_cursor_pipeline: Pipeline,
+    symmetry: Symmetry,
+    render_selection: bool,
+    selection_pipeline: Pipeline,
     voxel_pipeline: Pipeline,
     shadow_pipeline: Pipeline,
+    greedy_voxel_pipeline: Pipeline,
+    greedy_shadow_pipeline: Pipeline,
+    greedy_meshing: bool,
+    /// Slots of the voxels in the instance buffer of the voxel pipelines
+    instance_slots: InstanceSlots,
     shadow_view: wgpu::TextureView,
     ui_pipeline: wgpu::RenderPipeline,
     cursor_cube: BoundingBox,
     draw_cube: Option<BoundingBox>,
-    mesh_count: u16,
+    dimensions: [u16; 3],
     light: Light,
     lights_are_dirty: bool,
+    /// Voxel of the active object hit by the cursor ray, drawn blue
+    #[cfg(feature = "debug_ray")]
+    debug_hit: Option<[usize; 3]>,
 }
 
 impl Renderer {
@@ -107,7 +341,7 @@ impl Renderer {
         queue: wgpu::Queue,
         sc_desc: wgpu::SwapChainDescriptor,
         swap_chain: wgpu::SwapChain,
-        mesh_count: u16,
+        dimensions: [u16; 3],
         camera: &mut CameraWrapper,
     ) -> Self {
         use std::mem;
@@ -116,7 +350,7 @@ impl Renderer {
         let vertex_size = mem::size_of::<Vertex>();
 
         //****************************** Setting up mesh pipeline ******************************
-        let (vertex_data, mesh_index_data) = generate_mesh_vertices(mesh_count);
+        let (vertex_data, mesh_index_data) = generate_mesh_vertices(dimensions);
 
         let vertex_buf_mesh = device.create_buffer_with_data(
             bytemuck::cast_slice(&vertex_data),
@@ -242,12 +476,46 @@ impl Renderer {
             bytemuck::cast_slice(&vertex_data),
             wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
         );
+        let vertex_buf_selection = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vertex_data),
+            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        );
 
         let index_buf_cursor = Rc::new(device.create_buffer_with_data(
             bytemuck::cast_slice(&cursor_index_data),
             wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
         ));
 
+        // The mirror images of the cursor are drawn from one buffer
+        let vertex_buf_mirrored = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vertex_data.repeat(MAX_MIRRORED_CURSORS)),
+            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        );
+        let mirrored_index_data: Vec<u16> = (0..MAX_MIRRORED_CURSORS)
+            .flat_map(|i| {
+                let offset = (i * vertex_data.len()) as u16;
+                cursor_index_data.iter().map(move |idx| idx + offset)
+            })
+            .collect();
+        let index_buf_mirrored = device.create_buffer_with_data(
+            bytemuck::cast_slice(&mirrored_index_data),
+            wgpu::BufferUsage::INDEX,
+        );
+        let vertex_buf_shape = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vertex_data.repeat(MAX_SHAPE_CURSORS)),
+            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        );
+        let shape_index_data: Vec<u16> = (0..MAX_SHAPE_CURSORS)
+            .flat_map(|i| {
+                let offset = (i * vertex_data.len()) as u16;
+                cursor_index_data.iter().map(move |idx| idx + offset)
+            })
+            .collect();
+        let index_buf_shape = device.create_buffer_with_data(
+            bytemuck::cast_slice(&shape_index_data),
+            wgpu::BufferUsage::INDEX,
+        );
+
         // Create pipeline layout
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
@@ -334,9 +602,11 @@ impl Renderer {
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
         });
+        // The selection box is drawn with the cursor pipeline
+        let cursor_pipeline = Rc::new(cursor_pipeline);
+        let cursor_bind_group = Rc::new(cursor_bind_group);
 
         //****************************** Setting up voxel pipeline ******************************
-        let mc = mesh_count as u64;
         let origin_cube = BoundingBox::new(
             cgmath::Vector3::new(0.0, 0.0, 0.0),
             cgmath::Vector3::new(1.0, 1.0, 1.0),
@@ -351,13 +621,9 @@ impl Renderer {
                 wgpu::BufferUsage::VERTEX,
             ));
 
-        let instance_buf_voxel = Rc::new(device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            // We can have a maximum number of mc * mc * mc voxel
-            // Each voxel has one instance data
-            size: mc * mc * mc * mem::size_of::<VoxelInstance>() as u64,
-            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-        }));
+        let instance_slots = InstanceSlots::new(MIN_INSTANCE_CAPACITY as u32);
+        let instance_buf_voxel =
+            create_instance_buffer(&device, instance_slots.capacity() as usize);
 
         let light_uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
@@ -428,7 +694,7 @@ impl Renderer {
         });
 
         // Create bind group
-        let voxel_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let voxel_bind_group = Rc::new(device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
             bindings: &[
                 wgpu::Binding {
@@ -455,7 +721,7 @@ impl Renderer {
                 },
             ],
             label: None,
-        });
+        }));
 
         let vs_bytes = include_bytes!("../shaders/voxel.vert.spv");
         let fs_bytes = include_bytes!("../shaders/voxel.frag.spv");
@@ -466,84 +732,24 @@ impl Renderer {
         let fs_module_voxel = device
             .create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&fs_bytes[..])).unwrap());
 
-        let voxel_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &pipeline_layout,
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module_voxel,
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module_voxel,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: sc_desc.format,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[
-                    wgpu::VertexBufferDescriptor {
-                        stride: mem::size_of::<VoxelVertex>() as wgpu::BufferAddress,
-                        step_mode: wgpu::InputStepMode::Vertex,
-                        attributes: &[
-                            // Position
-                            wgpu::VertexAttributeDescriptor {
-                                format: wgpu::VertexFormat::Float3,
-                                offset: 0,
-                                shader_location: 0,
-                            },
-                            // Normal
-                            wgpu::VertexAttributeDescriptor {
-                                format: wgpu::VertexFormat::Float3,
-                                offset: 3 * 4,
-                                shader_location: 1,
-                            },
-                        ],
-                    },
-                    wgpu::VertexBufferDescriptor {
-                        stride: mem::size_of::<VoxelInstance>() as wgpu::BufferAddress,
-                        step_mode: wgpu::InputStepMode::Instance,
-                        attributes: &[
-                            // Offset
-                            wgpu::VertexAttributeDescriptor {
-                                format: wgpu::VertexFormat::Float3,
-                                offset: 0,
-                                shader_location: 2,
-                            },
-                            // Color
-                            wgpu::VertexAttributeDescriptor {
-                                format: wgpu::VertexFormat::Float4,
-                                offset: 3 * 4,
-                                shader_location: 3,
-                            },
-                        ],
-                    },
-                ],
-            },
-            sample_count: SAMPLE_COUNT,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
+        let voxel_pipeline = create_voxel_pipeline(
+            &device,
+            &pipeline_layout,
+            &vs_module_voxel,
+            &fs_module_voxel,
+            sc_desc.format,
+            wgpu::InputStepMode::Instance,
+            wgpu::IndexFormat::Uint16,
+        );
+        let greedy_voxel_pipeline = create_voxel_pipeline(
+            &device,
+            &pipeline_layout,
+            &vs_module_voxel,
+            &fs_module_voxel,
+            sc_desc.format,
+            wgpu::InputStepMode::Vertex,
+            wgpu::IndexFormat::Uint32,
+        );
 
         //****************************** Setting up shadow pipeline ******************************
 
@@ -561,7 +767,7 @@ impl Renderer {
         });
 
         // Create bind group
-        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let shadow_bind_group = Rc::new(device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
             bindings: &[wgpu::Binding {
                 binding: 0,
@@ -571,7 +777,7 @@ impl Renderer {
                 },
             }],
             label: None,
-        });
+        }));
 
         let vs_bytes = include_bytes!("../shaders/shadow.vert.spv");
         let fs_bytes = include_bytes!("../shaders/shadow.frag.spv");
@@ -582,67 +788,22 @@ impl Renderer {
         let fs_module_shadow = device
             .create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&fs_bytes[..])).unwrap());
 
-        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &pipeline_layout,
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module_shadow,
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module_shadow,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                depth_bias: 2,
-                depth_bias_slope_scale: 2.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: SHADOW_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[
-                    wgpu::VertexBufferDescriptor {
-                        stride: mem::size_of::<VoxelVertex>() as wgpu::BufferAddress,
-                        step_mode: wgpu::InputStepMode::Vertex,
-                        attributes: &[
-                            // Position
-                            wgpu::VertexAttributeDescriptor {
-                                format: wgpu::VertexFormat::Float3,
-                                offset: 0,
-                                shader_location: 0,
-                            },
-                        ],
-                    },
-                    wgpu::VertexBufferDescriptor {
-                        stride: mem::size_of::<VoxelInstance>() as wgpu::BufferAddress,
-                        step_mode: wgpu::InputStepMode::Instance,
-                        attributes: &[
-                            // Offset
-                            wgpu::VertexAttributeDescriptor {
-                                format: wgpu::VertexFormat::Float3,
-                                offset: 0,
-                                shader_location: 1,
-                            },
-                        ],
-                    },
-                ],
-            },
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
+        let shadow_pipeline = create_shadow_pipeline(
+            &device,
+            &pipeline_layout,
+            &vs_module_shadow,
+            &fs_module_shadow,
+            wgpu::InputStepMode::Instance,
+            wgpu::IndexFormat::Uint16,
+        );
+        let greedy_shadow_pipeline = create_shadow_pipeline(
+            &device,
+            &pipeline_layout,
+            &vs_module_shadow,
+            &fs_module_shadow,
+            wgpu::InputStepMode::Vertex,
+            wgpu::IndexFormat::Uint32,
+        );
 
         let ui_pipeline = build_ui_pipeline(&device);
         let multisampled_framebuffer = create_texture_view(
@@ -669,13 +830,40 @@ impl Renderer {
             swap_chain,
             depth_buffer,
             mesh_pipeline: Pipeline {
-                pipeline: mesh_pipeline,
-                bind_group: mesh_bind_group,
+                pipeline: Rc::new(mesh_pipeline),
+                bind_group: Rc::new(mesh_bind_group),
                 vertex_buf: Rc::new(vertex_buf_mesh),
                 instance_buf: None,
                 index_buf: Rc::new(index_buf_mesh),
                 index_count: mesh_index_data.len(),
-                instance_count: 1,
+                instances: vec![0..1],
+            },
+            mirrored_cursor_pipeline: Pipeline {
+                pipeline: cursor_pipeline.clone(),
+                bind_group: cursor_bind_group.clone(),
+                vertex_buf: Rc::new(vertex_buf_mirrored),
+                instance_buf: None,
+                index_buf: Rc::new(index_buf_mirrored),
+                index_count: 0,
+                instances: vec![0..1],
+            },
+            shape_cursor_pipeline: Pipeline {
+                pipeline: cursor_pipeline.clone(),
+                bind_group: cursor_bind_group.clone(),
+                vertex_buf: Rc::new(vertex_buf_shape),
+                instance_buf: None,
+                index_buf: Rc::new(index_buf_shape),
+                index_count: 0,
+                instances: vec![0..1],
+            },
+            selection_pipeline: Pipeline {
+                pipeline: cursor_pipeline.clone(),
+                bind_group: cursor_bind_group.clone(),
+                vertex_buf: Rc::new(vertex_buf_selection),
+                instance_buf: None,
+                index_buf: index_buf_cursor.clone(),
+                index_count: cursor_index_data.len(),
+                instances: vec![0..1],
             },
             cursor_pipeline: Pipeline {
                 pipeline: cursor_pipeline,
@@ -684,38 +872,57 @@ impl Renderer {
                 instance_buf: None,
                 index_buf: index_buf_cursor.clone(),
                 index_count: cursor_index_data.len(),
-                instance_count: 1,
+                instances: vec![0..1],
             },
             voxel_pipeline: Pipeline {
-                pipeline: voxel_pipeline,
-                bind_group: voxel_bind_group,
+                pipeline: Rc::new(voxel_pipeline),
+                bind_group: voxel_bind_group.clone(),
                 vertex_buf: vertex_buf_voxel.clone(),
                 instance_buf: Some(instance_buf_voxel.clone()),
                 index_buf: index_buf_cursor.clone(),
                 index_count: cursor_index_data.len(),
-                instance_count: 0,
+                instances: Vec::new(),
             },
             shadow_pipeline: Pipeline {
-                pipeline: shadow_pipeline,
+                pipeline: Rc::new(shadow_pipeline),
+                bind_group: shadow_bind_group.clone(),
+                vertex_buf: vertex_buf_voxel.clone(),
+                instance_buf: Some(instance_buf_voxel.clone()),
+                index_buf: index_buf_cursor.clone(),
+                index_count: cursor_index_data.len(),
+                instances: Vec::new(),
+            },
+            // The buffers of the greedy pipelines are replaced when the mesh is generated
+            greedy_voxel_pipeline: Pipeline {
+                pipeline: Rc::new(greedy_voxel_pipeline),
+                bind_group: voxel_bind_group,
+                vertex_buf: vertex_buf_voxel.clone(),
+                instance_buf: Some(instance_buf_voxel.clone()),
+                index_buf: index_buf_cursor.clone(),
+                index_count: 0,
+                instances: vec![0..1],
+            },
+            greedy_shadow_pipeline: Pipeline {
+                pipeline: Rc::new(greedy_shadow_pipeline),
                 bind_group: shadow_bind_group,
                 vertex_buf: vertex_buf_voxel,
                 instance_buf: Some(instance_buf_voxel),
                 index_buf: index_buf_cursor,
-                index_count: cursor_index_data.len(),
-                instance_count: 0,
+                index_count: 0,
+                instances: vec![0..1],
             },
+            greedy_meshing: false,
+            instance_slots,
             cursor_cube,
             draw_cube: None,
             render_cursor: true,
+            symmetry: Symmetry::default(),
+            render_selection: false,
             mvp_buf: uniform_buf,
             multisampled_framebuffer,
-            mesh_count,
+            dimensions,
             light: Light::new(
-                cgmath::Point3::new(
-                    (DEFAULT_MESH_COUNT / 2) as f32,
-                    DEFAULT_MESH_COUNT as f32 * 1.5,
-                    DEFAULT_MESH_COUNT as f32 * 2.0,
-                ),
+                light_position(dimensions),
                 wgpu::Color {
                     r: 1.0,
                     g: 1.0,
@@ -728,9 +935,33 @@ impl Renderer {
             lights_are_dirty: true,
             command_buffers: Vec::new(),
             ui_pipeline,
+            #[cfg(feature = "debug_ray")]
+            debug_hit: None,
         }
     }
 
+    pub fn dimensions(&self) -> [u16; 3] {
+        self.dimensions
+    }
+
+    /// Rebuilds the grid and the shadow projection for a canvas of the given dimensions
+    pub fn set_dimensions(&mut self, dimensions: [u16; 3]) {
+        self.dimensions = dimensions;
+        let (vertex_data, index_data) = generate_mesh_vertices(dimensions);
+        self.mesh_pipeline.vertex_buf = Rc::new(self.device.create_buffer_with_data(
+            bytemuck::cast_slice(&vertex_data),
+            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        ));
+        self.mesh_pipeline.index_buf = Rc::new(self.device.create_buffer_with_data(
+            bytemuck::cast_slice(&index_data),
+            wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
+        ));
+        self.mesh_pipeline.index_count = index_data.len();
+
+        self.light.set_position(light_position(dimensions));
+        self.lights_are_dirty = true;
+    }
+
     pub fn update_view(&mut self, camera: &mut CameraWrapper) {
         let matrices = camera.mvp_matrices(self.sc_desc.width as f32 / self.sc_desc.height as f32);
         let matrices_ref = matrices.as_ref();
@@ -760,6 +991,7 @@ impl Renderer {
                 &self.cursor_pipeline.vertex_buf,
                 &mut self.command_buffers,
             );
+            self.update_mirrored_cursor(self.cursor_cube);
             self.render_cursor = true;
         } else {
             self.render_cursor = false;
@@ -776,9 +1008,45 @@ impl Renderer {
             &self.cursor_pipeline.vertex_buf,
             &mut self.command_buffers,
         );
+        self.update_mirrored_cursor(self.cursor_cube);
         self.render_cursor = true;
     }
 
+    pub fn symmetry(&self) -> Symmetry {
+        self.symmetry
+    }
+
+    /// Sets the mirror planes the cursor is mirrored on
+    pub fn set_symmetry(&mut self, symmetry: Symmetry) {
+        self.symmetry = symmetry;
+    }
+
+    /// Shows the mirror images of the cursor or of the rectangle being drawn
+    fn update_mirrored_cursor(&mut self, bbox: BoundingBox) {
+        if !self.symmetry.is_enabled() {
+            self.mirrored_cursor_pipeline.index_count = 0;
+            return;
+        }
+        let [x, y, z] = self.dimensions;
+        let boxes = self
+            .symmetry
+            .mirrored_boxes(&bbox, [x as usize, y as usize, z as usize]);
+        if !boxes.is_empty() {
+            let vertex_data: Vec<Vertex> = boxes.iter().flat_map(|bbox| bbox.vertices()).collect();
+            Self::write_buffer(
+                &self.device,
+                bytemuck::cast_slice(&vertex_data),
+                &self.mirrored_cursor_pipeline.vertex_buf,
+                &mut self.command_buffers,
+            );
+        }
+        self.mirrored_cursor_pipeline.index_count = boxes.len() * self.cursor_pipeline.index_count;
+    }
+
+    pub fn hide_cursor(&mut self) {
+        self.render_cursor = false;
+    }
+
     pub fn update_draw_rectangle_on_plane(
         &mut self,
         pos: cgmath::Vector3<f32>,
@@ -798,6 +1066,7 @@ impl Renderer {
                 &self.cursor_pipeline.vertex_buf,
                 &mut self.command_buffers,
             );
+            self.update_mirrored_cursor(draw_cube);
             self.draw_cube = Some(draw_cube);
             self.render_cursor = true;
         } else {
@@ -815,76 +1084,221 @@ impl Renderer {
             &self.cursor_pipeline.vertex_buf,
             &mut self.command_buffers,
         );
+        self.update_mirrored_cursor(draw_cube);
         self.draw_cube = Some(draw_cube);
         self.render_cursor = true;
     }
 
-    pub fn draw_rectangle(&mut self, color: [f32; 4], voxel_manager: &mut VoxelManager) {
-        if let Some(mut cube) = self.draw_cube.take() {
-            cube.rearrange();
-            cube.color = color;
-            voxel_manager.add_box(cube);
-            let instance_data = voxel_manager.instance_data();
+    /// The rectangle being drawn, from the corner of the start cell to the corner of the end cell
+    pub fn draw_rectangle(&self) -> Option<BoundingBox> {
+        self.draw_cube
+    }
+
+    /// Shows the cells of the shape being drawn instead of the rectangle. Shapes with too many
+    /// boxes to show are shown by the outline of every slice.
+    pub fn show_shape(&mut self, cells: &[[i32; 3]]) {
+        let mut boxes = shape::preview_boxes(cells, HALF_ALPHA_RED.into());
+        if boxes.len() > MAX_SHAPE_CURSORS {
+            boxes = shape::outline_boxes(cells, HALF_ALPHA_RED.into());
+        }
+        if boxes.is_empty() || boxes.len() > MAX_SHAPE_CURSORS {
+            self.shape_cursor_pipeline.index_count = 0;
+            return;
+        }
+        let vertex_data: Vec<Vertex> = boxes.iter().flat_map(|bbox| bbox.vertices()).collect();
+        Self::write_buffer(
+            &self.device,
+            bytemuck::cast_slice(&vertex_data),
+            &self.shape_cursor_pipeline.vertex_buf,
+            &mut self.command_buffers,
+        );
+        self.shape_cursor_pipeline.index_count = boxes.len() * self.cursor_pipeline.index_count;
+    }
+
+    /// The number of cells from the start to the end of the rectangle being drawn, which is
+    /// how far a selection is dragged
+    pub fn draw_rectangle_offset(&self) -> Option<[i32; 3]> {
+        // The rectangle goes from the outer corner of the start cell to the outer corner of
+        // the end cell, so it's one cell longer than the offset on every axis
+        let offset = |extent: f32| {
+            let cells = extent.round() as i32;
+            cells - cells.signum()
+        };
+        self.draw_cube.map(|cube| {
+            [
+                offset(cube.extent.x),
+                offset(cube.extent.y),
+                offset(cube.extent.z),
+            ]
+        })
+    }
+
+    /// Shows the box around the selected voxels, or hides it
+    pub fn show_selection(&mut self, bbox: Option<BoundingBox>) {
+        if let Some(bbox) = bbox {
+            let vertex_data = bbox.vertices();
             Self::write_buffer(
                 &self.device,
-                bytemuck::cast_slice(&instance_data),
-                &self.voxel_pipeline.instance_buf.as_ref().unwrap(),
+                bytemuck::cast_slice(&vertex_data),
+                &self.selection_pipeline.vertex_buf,
                 &mut self.command_buffers,
             );
-            self.voxel_pipeline.instance_count = instance_data.len();
-            self.shadow_pipeline.instance_count = self.voxel_pipeline.instance_count;
         }
+        self.render_selection = bbox.is_some();
     }
 
-    pub fn fill_rectangle(&mut self, color: [f32; 4], voxel_manager: &mut VoxelManager) {
-        if let Some(mut cube) = self.draw_cube.take() {
+    /// Finishes the rectangle being drawn and returns the box it covers
+    pub fn take_draw_rectangle(&mut self, color: [f32; 4]) -> Option<BoundingBox> {
+        self.shape_cursor_pipeline.index_count = 0;
+        self.draw_cube.take().map(|mut cube| {
             cube.rearrange();
             cube.color = color;
-            voxel_manager.refill(cube);
-            let instance_data = voxel_manager.instance_data();
-            if instance_data.len() > 0 {
-                Self::write_buffer(
-                    &self.device,
-                    bytemuck::cast_slice(&instance_data),
-                    &self.voxel_pipeline.instance_buf.as_ref().unwrap(),
-                    &mut self.command_buffers,
-                );
-            }
-            self.voxel_pipeline.instance_count = instance_data.len();
-            self.shadow_pipeline.instance_count = self.voxel_pipeline.instance_count;
+            cube
+        })
+    }
+
+    fn set_instance_ranges(&mut self) {
+        self.voxel_pipeline.instances = self.instance_slots.ranges();
+        self.shadow_pipeline.instances = self.instance_slots.ranges();
+    }
+
+    /// Gives every instance a new slot, the buffer is replaced if it's too small or much
+    /// larger than needed
+    fn rebuild_instances(&mut self, instances: Vec<(VoxelKey, VoxelInstance)>) {
+        let mut capacity = self.instance_slots.capacity() as usize;
+        let needed = instance_capacity(instances.len());
+        if instances.len() > capacity || 2 * needed < capacity {
+            capacity = needed;
+            let instance_buf = create_instance_buffer(&self.device, capacity);
+            self.voxel_pipeline.instance_buf = Some(instance_buf.clone());
+            self.shadow_pipeline.instance_buf = Some(instance_buf);
+        }
+        self.instance_slots = InstanceSlots::new(capacity as u32);
+        let mut writes = Vec::with_capacity(instances.len());
+        for (key, instance) in instances {
+            self.instance_slots.set(key, Some(instance), &mut writes);
         }
+        self.write_instance_slots(writes);
+        self.set_instance_ranges();
     }
 
-    #[cfg(feature = "debug_ray")]
-    pub fn debug_update(&mut self, voxel_manager: &VoxelManager) {
-        let instance_data = voxel_manager.instance_data();
-        if instance_data.len() == 0 {
+    /// Copies the instances to their slots with one staging buffer for all of them
+    fn write_instance_slots(&mut self, writes: Vec<(u32, VoxelInstance)>) {
+        if writes.is_empty() {
             return;
         }
-        Self::write_buffer(
-            &self.device,
-            bytemuck::cast_slice(&instance_data),
-            &self.voxel_pipeline.instance_buf.as_ref().unwrap(),
-            &mut self.command_buffers,
+        let (instances, runs) = pack_writes(writes);
+        let size = std::mem::size_of::<VoxelInstance>() as u64;
+        let staging_buf = self.device.create_buffer_with_data(
+            bytemuck::cast_slice(&instances),
+            wgpu::BufferUsage::COPY_SRC,
         );
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let instance_buf = self.voxel_pipeline.instance_buf.as_ref().unwrap();
+        for (slot, first, count) in runs {
+            encoder.copy_buffer_to_buffer(
+                &staging_buf,
+                first as u64 * size,
+                instance_buf,
+                slot as u64 * size,
+                count as u64 * size,
+            );
+        }
+        self.command_buffers.push(encoder.finish());
     }
 
-    pub fn erase_rectangle(&mut self, voxel_manager: &mut VoxelManager) {
-        if let Some(mut cube) = self.draw_cube.take() {
-            cube.rearrange();
-            voxel_manager.erase_box(cube);
-            let instance_data = voxel_manager.instance_data();
-            if instance_data.len() > 0 {
-                Self::write_buffer(
-                    &self.device,
-                    bytemuck::cast_slice(&instance_data),
-                    &self.voxel_pipeline.instance_buf.as_ref().unwrap(),
-                    &mut self.command_buffers,
-                );
+    /// Uploads the voxels of the scene which changed since the last update. Only their slots
+    /// are written, unless the objects moved or the buffer is full.
+    pub fn update_instances(&mut self, scene: &mut Scene) {
+        let mut writes = Vec::new();
+        let patched = match scene.take_instance_changes() {
+            Some(changes) => changes
+                .into_iter()
+                .all(|(key, instance)| self.instance_slots.set(key, instance, &mut writes)),
+            None => false,
+        };
+        if patched {
+            self.write_instance_slots(writes);
+            self.set_instance_ranges();
+        } else {
+            self.rebuild_instances(scene.instances());
+        }
+        if self.greedy_meshing {
+            self.update_greedy_mesh(scene);
+        }
+    }
+
+    pub fn greedy_meshing(&self) -> bool {
+        self.greedy_meshing
+    }
+
+    /// Switches between drawing every voxel as an instance and drawing the greedy mesh
+    pub fn set_greedy_meshing(&mut self, enabled: bool, scene: &Scene) {
+        self.greedy_meshing = enabled;
+        if enabled {
+            self.update_greedy_mesh(scene);
+        }
+    }
+
+    fn update_greedy_mesh(&mut self, scene: &Scene) {
+        let (mesh_vertices, indices) = scene.greedy_vertices();
+        self.greedy_voxel_pipeline.index_count = indices.len();
+        self.greedy_shadow_pipeline.index_count = indices.len();
+        if indices.is_empty() {
+            return;
+        }
+        let vertices: Vec<VoxelVertex> = mesh_vertices
+            .iter()
+            .map(|v| voxel_vertex(v.pos, v.normal))
+            .collect();
+        let colors: Vec<VoxelInstance> = mesh_vertices
+            .iter()
+            .map(|v| instance([0.0; 3], v.color, &v.material))
+            .collect();
+
+        let vertex_buf =
+            Rc::new(self.device.create_buffer_with_data(
+                bytemuck::cast_slice(&vertices),
+                wgpu::BufferUsage::VERTEX,
+            ));
+        let color_buf = Rc::new(
+            self.device
+                .create_buffer_with_data(bytemuck::cast_slice(&colors), wgpu::BufferUsage::VERTEX),
+        );
+        let index_buf = Rc::new(
+            self.device
+                .create_buffer_with_data(bytemuck::cast_slice(&indices), wgpu::BufferUsage::INDEX),
+        );
+        for pipeline in [
+            &mut self.greedy_voxel_pipeline,
+            &mut self.greedy_shadow_pipeline,
+        ]
+        .iter_mut()
+        {
+            pipeline.vertex_buf = vertex_buf.clone();
+            pipeline.instance_buf = Some(color_buf.clone());
+            pipeline.index_buf = index_buf.clone();
+        }
+    }
+
+    #[cfg(feature = "debug_ray")]
+    pub fn debug_hit(&mut self, hit: Option<[usize; 3]>) {
+        self.debug_hit = hit;
+    }
+
+    #[cfg(feature = "debug_ray")]
+    pub fn debug_update(&mut self, scene: &Scene) {
+        let hit = self.debug_hit.map(|pos| (scene.active(), pos));
+        let mut instances = scene.instances();
+        // The hit voxel is only recolored on the GPU, the document doesn't change
+        for (key, instance) in instances.iter_mut() {
+            if Some(*key) == hit {
+                instance.set_color(BLUE);
             }
-            self.voxel_pipeline.instance_count = instance_data.len();
-            self.shadow_pipeline.instance_count = self.voxel_pipeline.instance_count;
         }
+        self.rebuild_instances(instances);
     }
 
     #[cfg(feature = "debug_ray")]
@@ -893,7 +1307,7 @@ impl Renderer {
         near_pos: Option<cgmath::Vector3<f32>>,
         far_pos: cgmath::Vector3<f32>,
     ) {
-        let (mut vertex_data, mut index_data) = generate_mesh_vertices(self.mesh_count);
+        let (mut vertex_data, mut index_data) = generate_mesh_vertices(self.dimensions);
         for _ in 0..2 {
             vertex_data.pop();
             index_data.pop();
@@ -968,10 +1382,10 @@ impl Renderer {
     pub fn render(
         &mut self,
         ui: &mut Ui,
-        #[cfg(feature = "debug_ray")] voxel_manager: &mut VoxelManager,
+        #[cfg(feature = "debug_ray")] scene: &Scene,
     ) -> Interaction {
         #[cfg(feature = "debug_ray")]
-        self.debug_update(voxel_manager);
+        self.debug_update(scene);
 
         let frame = match self.swap_chain.get_next_texture() {
             Ok(frame) => frame,
@@ -987,7 +1401,11 @@ impl Renderer {
             self.lights_are_dirty = false;
             Self::write_buffer(
                 &self.device,
-                bytemuck::bytes_of(&self.light.to_raw(self.mesh_count as f32)),
+                bytemuck::bytes_of(&self.light.to_raw([
+                    self.dimensions[0] as f32,
+                    self.dimensions[1] as f32,
+                    self.dimensions[2] as f32,
+                ])),
                 &self.light_uniform_buf,
                 &mut self.command_buffers,
             );
@@ -997,7 +1415,17 @@ impl Renderer {
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        if self.shadow_pipeline.instance_count > 0 {
+        let (voxel_pipeline, shadow_pipeline) = if self.greedy_meshing {
+            (
+                &mut self.greedy_voxel_pipeline,
+                &mut self.greedy_shadow_pipeline,
+            )
+        } else {
+            (&mut self.voxel_pipeline, &mut self.shadow_pipeline)
+        };
+        let has_voxels = !voxel_pipeline.instances.is_empty() && voxel_pipeline.index_count > 0;
+
+        if has_voxels {
             let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
@@ -1010,7 +1438,7 @@ impl Renderer {
                     clear_stencil: 0,
                 }),
             });
-            self.shadow_pipeline.draw(&mut shadow_pass);
+            shadow_pipeline.draw(&mut shadow_pass);
         }
         {
             let mut rpass_depth = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -1037,8 +1465,8 @@ impl Renderer {
                 }),
             });
             self.mesh_pipeline.draw(&mut rpass_depth);
-            if self.voxel_pipeline.instance_count > 0 {
-                self.voxel_pipeline.draw(&mut rpass_depth);
+            if has_voxels {
+                voxel_pipeline.draw(&mut rpass_depth);
             }
         }
         {
@@ -1052,8 +1480,16 @@ impl Renderer {
                 }],
                 depth_stencil_attachment: None,
             });
+            if self.render_selection {
+                self.selection_pipeline.draw(&mut rpass);
+            }
             if self.render_cursor {
-                self.cursor_pipeline.draw(&mut rpass);
+                self.mirrored_cursor_pipeline.draw(&mut rpass);
+                if self.shape_cursor_pipeline.index_count > 0 {
+                    self.shape_cursor_pipeline.draw(&mut rpass);
+                } else {
+                    self.cursor_pipeline.draw(&mut rpass);
+                }
             }
         }
         // Render ui
@@ -0,0 +1,560 @@
+use crate::instance_slots::VoxelKey;
+use crate::material::Material;
+use crate::vertex::{MeshVertex, VoxelInstance};
+use crate::voxel_manager::{DirtyRegion, VoxelManager};
+use std::collections::HashMap;
+use std::mem;
+
+/// Rotation matrix with a single 1 or -1 in every row and column
+pub type Rotation = [[i32; 3]; 3];
+
+pub const IDENTITY: Rotation = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+pub fn mul_matrix(a: &Rotation, b: &Rotation) -> Rotation {
+    let mut result = [[0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+pub fn mul_vector(m: &Rotation, v: [i32; 3]) -> [i32; 3] {
+    let mut result = [0; 3];
+    for (i, value) in result.iter_mut().enumerate() {
+        *value = (0..3).map(|k| m[i][k] * v[k]).sum();
+    }
+    result
+}
+
+fn transpose(m: &Rotation) -> Rotation {
+    let mut result = [[0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    result
+}
+
+/// Counter-clockwise quarter turn around the axis, looking from its positive end
+pub fn quarter_turn(axis: usize) -> Rotation {
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut rotation = [[0; 3]; 3];
+    rotation[axis][axis] = 1;
+    rotation[a][b] = -1;
+    rotation[b][a] = 1;
+    rotation
+}
+
+/// Placement of an object in the scene, a rotation by quarter turns followed by a
+/// translation. The point `p` of the object is at `rotation * p + translation`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Transform {
+    pub rotation: Rotation,
+    pub translation: [i32; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            rotation: IDENTITY,
+            translation: [0; 3],
+        }
+    }
+}
+
+impl Transform {
+    /// The scene cell covered by a cell of the object. The cell centers are transformed
+    /// on doubled coordinates, so the result is exact.
+    pub fn cell(&self, cell: [i32; 3]) -> [i32; 3] {
+        let doubled = mul_vector(
+            &self.rotation,
+            [2 * cell[0] + 1, 2 * cell[1] + 1, 2 * cell[2] + 1],
+        );
+        let mut result = [0; 3];
+        for i in 0..3 {
+            result[i] = (doubled[i] - 1) / 2 + self.translation[i];
+        }
+        result
+    }
+
+    pub fn point(&self, point: [f32; 3]) -> [f32; 3] {
+        let mut result = self.direction(point);
+        for (r, t) in result.iter_mut().zip(self.translation.iter()) {
+            *r += *t as f32;
+        }
+        result
+    }
+
+    /// Rotates a direction, like a normal, which isn't affected by the translation
+    pub fn direction(&self, direction: [f32; 3]) -> [f32; 3] {
+        let mut result = [0.0; 3];
+        for (i, value) in result.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|k| self.rotation[i][k] as f32 * direction[k])
+                .sum();
+        }
+        result
+    }
+
+    pub fn inverse(&self) -> Transform {
+        let rotation = transpose(&self.rotation);
+        let offset = mul_vector(&rotation, self.translation);
+        Transform {
+            rotation,
+            translation: [-offset[0], -offset[1], -offset[2]],
+        }
+    }
+
+    /// The transform which applies `inner` first and `self` after it
+    pub fn after(&self, inner: &Transform) -> Transform {
+        let offset = mul_vector(&self.rotation, inner.translation);
+        let mut translation = [0; 3];
+        for i in 0..3 {
+            translation[i] = offset[i] + self.translation[i];
+        }
+        Transform {
+            rotation: mul_matrix(&self.rotation, &inner.rotation),
+            translation,
+        }
+    }
+
+    /// Turns an object of the given extent a quarter around the axis through its center,
+    /// so it stays in place. The center is rounded to a cell for odd sizes.
+    pub fn rotate(&mut self, axis: usize, extent: [usize; 3]) {
+        let doubled_center = [extent[0] as i32, extent[1] as i32, extent[2] as i32];
+        let center = mul_vector(&self.rotation, doubled_center);
+        self.rotation = mul_matrix(&quarter_turn(axis), &self.rotation);
+        let rotated = mul_vector(&self.rotation, doubled_center);
+        for i in 0..3 {
+            self.translation[i] += (center[i] - rotated[i]).div_euclid(2);
+        }
+    }
+
+    /// The scene cells covered by a grid of the given extent, the end is exclusive
+    pub fn bounds(&self, extent: [usize; 3]) -> ([i32; 3], [i32; 3]) {
+        let first = self.cell([0; 3]);
+        let last = self.cell([
+            extent[0] as i32 - 1,
+            extent[1] as i32 - 1,
+            extent[2] as i32 - 1,
+        ]);
+        let mut start = [0; 3];
+        let mut end = [0; 3];
+        for i in 0..3 {
+            start[i] = first[i].min(last[i]);
+            end[i] = first[i].max(last[i]) + 1;
+        }
+        (start, end)
+    }
+}
+
+/// A voxel model of the scene with its own grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneObject {
+    pub name: String,
+    pub voxel_manager: VoxelManager,
+    pub transform: Transform,
+}
+
+/// The objects of a document. The edits go to the active object, which is drawn at the
+/// origin with the other objects placed around it.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    objects: Vec<SceneObject>,
+    active: usize,
+    /// Set when the objects moved, so every instance has to be placed again
+    rebuild_instances: bool,
+}
+
+impl PartialEq for Scene {
+    fn eq(&self, other: &Self) -> bool {
+        self.objects == other.objects && self.active == other.active
+    }
+}
+
+impl Scene {
+    pub fn new(voxel_manager: VoxelManager) -> Self {
+        Scene {
+            objects: vec![SceneObject {
+                name: "Object 1".to_string(),
+                voxel_manager,
+                transform: Transform::default(),
+            }],
+            active: 0,
+            rebuild_instances: true,
+        }
+    }
+
+    /// Builds a scene from its objects, for example when it's loaded
+    pub fn from_objects(objects: Vec<SceneObject>, active: usize) -> Self {
+        assert!(active < objects.len());
+        Scene {
+            objects,
+            active,
+            rebuild_instances: true,
+        }
+    }
+
+    pub fn objects(&self) -> &[SceneObject] {
+        &self.objects
+    }
+
+    /// Index of the object being edited
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    pub fn set_active(&mut self, idx: usize) {
+        assert!(idx < self.objects.len());
+        self.active = idx;
+        self.rebuild_instances = true;
+    }
+
+    pub fn voxel_manager(&self) -> &VoxelManager {
+        &self.objects[self.active].voxel_manager
+    }
+
+    pub fn voxel_manager_mut(&mut self) -> &mut VoxelManager {
+        &mut self.objects[self.active].voxel_manager
+    }
+
+    pub fn set_transform(&mut self, idx: usize, transform: Transform) {
+        self.objects[idx].transform = transform;
+        self.rebuild_instances = true;
+    }
+
+    /// Adds an object on the positive x side of the others and makes it the active one
+    pub fn add_object(&mut self, voxel_manager: VoxelManager) -> usize {
+        let name = (1..)
+            .map(|n| format!("Object {}", n))
+            .find(|name| self.objects.iter().all(|object| object.name != *name))
+            .unwrap();
+        let x = self
+            .objects
+            .iter()
+            .map(|object| object.transform.bounds(object.voxel_manager.extent()).1[0])
+            .max()
+            .unwrap_or(0);
+        self.objects.push(SceneObject {
+            name,
+            voxel_manager,
+            transform: Transform {
+                rotation: IDENTITY,
+                translation: [x + 1, 0, 0],
+            },
+        });
+        self.active = self.objects.len() - 1;
+        self.rebuild_instances = true;
+        self.active
+    }
+
+    /// Removes the object, the last remaining object can't be removed
+    pub fn remove_object(&mut self, idx: usize) -> SceneObject {
+        assert!(self.objects.len() > 1, "The scene needs an object");
+        let object = self.objects.remove(idx);
+        if self.active > idx || self.active == self.objects.len() {
+            self.active -= 1;
+        }
+        self.rebuild_instances = true;
+        object
+    }
+
+    /// Placement of an object relative to the active object
+    fn relative_transform(&self, idx: usize) -> Transform {
+        self.objects[self.active]
+            .transform
+            .inverse()
+            .after(&self.objects[idx].transform)
+    }
+
+    /// Moves the instance of a voxel of the object to its place relative to the active object
+    fn place_instance(transform: &Transform, pos: [usize; 3], instance: &mut VoxelInstance) {
+        let cell = transform.cell([pos[0] as i32, pos[1] as i32, pos[2] as i32]);
+        instance.set_offset([cell[0] as f32, cell[1] as f32, cell[2] as f32]);
+    }
+
+    /// Instances of the visible voxels of every object, placed relative to the active object
+    pub fn instances(&self) -> Vec<(VoxelKey, VoxelInstance)> {
+        let mut instances = Vec::new();
+        for (idx, object) in self.objects.iter().enumerate() {
+            let transform = self.relative_transform(idx);
+            for (pos, mut instance) in object.voxel_manager.instances() {
+                Self::place_instance(&transform, pos, &mut instance);
+                instances.push(((idx, pos), instance));
+            }
+        }
+        instances
+    }
+
+    /// Returns the voxels whose instance changed since the last call, with None for the
+    /// voxels which aren't drawn anymore. Returns None if every instance has to be rebuilt.
+    pub fn take_instance_changes(&mut self) -> Option<Vec<(VoxelKey, Option<VoxelInstance>)>> {
+        let mut rebuild = mem::replace(&mut self.rebuild_instances, false);
+        let mut changes = Vec::new();
+        for idx in 0..self.objects.len() {
+            // The regions are taken even when rebuilding, so they don't show up next time
+            let keys = match self.objects[idx].voxel_manager.take_dirty_region() {
+                DirtyRegion::All => {
+                    rebuild = true;
+                    continue;
+                }
+                DirtyRegion::Chunks(keys) => keys,
+            };
+            if rebuild {
+                continue;
+            }
+            let transform = self.relative_transform(idx);
+            for key in keys {
+                for (pos, mut instance) in self.objects[idx].voxel_manager.chunk_instances(key) {
+                    if let Some(instance) = instance.as_mut() {
+                        Self::place_instance(&transform, pos, instance);
+                    }
+                    changes.push(((idx, pos), instance));
+                }
+            }
+        }
+        if rebuild {
+            None
+        } else {
+            Some(changes)
+        }
+    }
+
+    /// Appends the greedy mesh of the object with the transform applied
+    fn append_mesh(
+        &self,
+        idx: usize,
+        transform: &Transform,
+        vertices: &mut Vec<MeshVertex>,
+        indices: &mut Vec<u32>,
+    ) {
+        let (object_vertices, object_indices) = self.objects[idx].voxel_manager.greedy_vertices();
+        let first = vertices.len() as u32;
+        vertices.extend(object_vertices.into_iter().map(|vertex| MeshVertex {
+            pos: transform.point(vertex.pos),
+            normal: transform.direction(vertex.normal),
+            ..vertex
+        }));
+        indices.extend(object_indices.into_iter().map(|idx| idx + first));
+    }
+
+    /// Greedy meshes of every object, placed relative to the active object
+    pub fn greedy_vertices(&self) -> (Vec<MeshVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for idx in 0..self.objects.len() {
+            let transform = self.relative_transform(idx);
+            self.append_mesh(idx, &transform, &mut vertices, &mut indices);
+        }
+        (vertices, indices)
+    }
+
+    /// Greedy meshes of every object in scene coordinates, together with the name and
+    /// triangle count of every object
+    pub fn scene_mesh(&self) -> (Vec<MeshVertex>, Vec<u32>, Vec<(String, usize)>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut objects = Vec::new();
+        for (idx, object) in self.objects.iter().enumerate() {
+            let first = indices.len();
+            self.append_mesh(idx, &object.transform, &mut vertices, &mut indices);
+            objects.push((object.name.clone(), (indices.len() - first) / 3));
+        }
+        (vertices, indices, objects)
+    }
+
+    /// Merges the visible voxels of every object into a single grid which just fits them,
+    /// the later objects cover the earlier ones
+    pub fn merged(&self) -> VoxelManager {
+        let mut start = [i32::MAX; 3];
+        let mut end = [i32::MIN; 3];
+        for object in self.objects.iter() {
+            let (object_start, object_end) = object.transform.bounds(object.voxel_manager.extent());
+            for i in 0..3 {
+                start[i] = start[i].min(object_start[i]);
+                end[i] = end[i].max(object_end[i]);
+            }
+        }
+        let mut merged = VoxelManager::new([
+            (end[0] - start[0]) as usize,
+            (end[1] - start[1]) as usize,
+            (end[2] - start[2]) as usize,
+        ]);
+
+        // Palette entries with the same color and material are merged
+        let mut entries = HashMap::new();
+        for object in self.objects.iter() {
+            let voxel_manager = &object.voxel_manager;
+            for (pos, idx) in voxel_manager.indexed_voxels() {
+                let color = voxel_manager.palette()[idx as usize];
+                let material: Material = voxel_manager.materials()[idx as usize];
+                let key = (
+                    color.iter().map(|c| c.to_bits()).collect::<Vec<_>>(),
+                    material
+                        .to_array()
+                        .iter()
+                        .map(|p| p.to_bits())
+                        .collect::<Vec<_>>(),
+                );
+                let merged_idx = *entries.entry(key).or_insert_with(|| {
+                    let merged_idx = merged.add_palette_color(color);
+                    merged.set_material(merged_idx, material);
+                    merged_idx
+                });
+                let cell = object
+                    .transform
+                    .cell([pos[0] as i32, pos[1] as i32, pos[2] as i32]);
+                merged.set_index(
+                    (cell[0] - start[0]) as usize,
+                    (cell[1] - start[1]) as usize,
+                    (cell[2] - start[2]) as usize,
+                    merged_idx,
+                );
+            }
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every rotation of the cube, generated from the quarter turns
+    fn rotations() -> Vec<Rotation> {
+        let mut rotations = vec![IDENTITY];
+        let mut next = 0;
+        while next < rotations.len() {
+            for axis in 0..3 {
+                let rotation = mul_matrix(&quarter_turn(axis), &rotations[next]);
+                if !rotations.contains(&rotation) {
+                    rotations.push(rotation);
+                }
+            }
+            next += 1;
+        }
+        rotations
+    }
+
+    #[test]
+    fn transform_cells() {
+        assert_eq!(rotations().len(), 24);
+        let cells = [[0, 0, 0], [3, 1, 2], [-2, 5, 7]];
+        for rotation in rotations() {
+            let transform = Transform {
+                rotation,
+                translation: [4, -3, 9],
+            };
+            let inverse = transform.inverse();
+            assert_eq!(transform.after(&inverse), Transform::default());
+            for cell in cells.iter() {
+                assert_eq!(inverse.cell(transform.cell(*cell)), *cell);
+                // The cell covers the transformed unit cube of the object cell
+                let center = transform.point([
+                    cell[0] as f32 + 0.5,
+                    cell[1] as f32 + 0.5,
+                    cell[2] as f32 + 0.5,
+                ]);
+                let world = transform.cell(*cell);
+                for i in 0..3 {
+                    assert_eq!(center[i], world[i] as f32 + 0.5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_in_place() {
+        let extent = [4, 2, 6];
+        let mut transform = Transform {
+            rotation: IDENTITY,
+            translation: [10, 0, 0],
+        };
+        let bounds = transform.bounds(extent);
+        for axis in 0..3 {
+            for _ in 0..4 {
+                transform.rotate(axis, extent);
+            }
+            assert_eq!(transform.bounds(extent), bounds);
+        }
+        transform.rotate(1, extent);
+        assert_eq!(transform.bounds(extent), ([9, 0, 1], [15, 2, 5]));
+    }
+
+    fn object_with_voxel(extent: [usize; 3], pos: [usize; 3], color: [f32; 4]) -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new(extent);
+        voxel_manager.set(pos[0], pos[1], pos[2], color);
+        voxel_manager
+    }
+
+    #[test]
+    fn objects_placed_relative_to_active() {
+        let mut scene = Scene::new(object_with_voxel([4; 3], [0, 0, 0], [1.0; 4]));
+        assert_eq!(
+            scene.add_object(object_with_voxel([2; 3], [1, 0, 0], [0.5, 0.5, 0.5, 1.0])),
+            1
+        );
+        assert_eq!(scene.objects()[1].name, "Object 2");
+        assert_eq!(scene.objects()[1].transform.translation, [5, 0, 0]);
+
+        let offsets = |scene: &Scene| -> Vec<[f32; 3]> {
+            scene.instances().iter().map(|(_, i)| i.offset()).collect()
+        };
+        assert_eq!(offsets(&scene), vec![[-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]]);
+        scene.set_active(0);
+        assert_eq!(offsets(&scene), vec![[0.0, 0.0, 0.0], [6.0, 0.0, 0.0]]);
+
+        let merged = scene.merged();
+        assert_eq!(merged.extent(), [7, 4, 4]);
+        assert_eq!(
+            merged.voxels(),
+            vec![([0, 0, 0], [1.0; 4]), ([6, 0, 0], [0.5, 0.5, 0.5, 1.0])]
+        );
+
+        let (vertices, indices, objects) = scene.scene_mesh();
+        assert_eq!(
+            objects,
+            vec![("Object 1".to_string(), 12), ("Object 2".to_string(), 12)]
+        );
+        let max_x = indices
+            .iter()
+            .map(|idx| vertices[*idx as usize].pos[0])
+            .fold(0.0, f32::max);
+        assert_eq!(max_x, 7.0);
+
+        let removed = scene.remove_object(0);
+        assert_eq!(removed.name, "Object 1");
+        assert_eq!(scene.active(), 0);
+        assert_eq!(scene.objects().len(), 1);
+    }
+
+    #[test]
+    fn instance_changes() {
+        let mut scene = Scene::new(object_with_voxel([4; 3], [0, 0, 0], [1.0; 4]));
+        scene.add_object(object_with_voxel([2; 3], [1, 0, 0], [1.0; 4]));
+        assert!(scene.take_instance_changes().is_none());
+        assert_eq!(scene.take_instance_changes(), Some(vec![]));
+
+        scene.voxel_manager_mut().clear(1, 0, 0);
+        let changes = scene.take_instance_changes().unwrap();
+        // Every cell of the chunk is reported, relative to the active object
+        assert_eq!(changes.len(), 8);
+        assert!(changes
+            .iter()
+            .all(|(key, instance)| key.0 == 1 && instance.is_none()));
+
+        scene.voxel_manager_mut().set(1, 1, 0, [0.5; 4]);
+        let changes = scene.take_instance_changes().unwrap();
+        let placed: Vec<_> = changes
+            .iter()
+            .filter_map(|(key, instance)| instance.map(|i| (*key, i.offset())))
+            .collect();
+        assert_eq!(placed, vec![((1, [1, 1, 0]), [1.0, 1.0, 0.0])]);
+
+        scene.set_active(0);
+        assert!(scene.take_instance_changes().is_none());
+    }
+}
@@ -0,0 +1,438 @@
+use crate::color::HALF_ALPHA_YELLOW;
+use crate::geometry::BoundingBox;
+use crate::material::Material;
+use crate::voxel_manager::VoxelManager;
+use cgmath::Vector3;
+use std::collections::HashMap;
+
+/// Cells of the active layer to fill with a palette entry or to empty, in the order they
+/// are written
+pub type CellEdits = Vec<([usize; 3], Option<u16>)>;
+
+/// Filled cells with their palette entries
+pub type Voxels = Vec<([usize; 3], u16)>;
+
+fn offset_cell(cell: [usize; 3], offset: [usize; 3]) -> [usize; 3] {
+    [
+        cell[0] + offset[0],
+        cell[1] + offset[1],
+        cell[2] + offset[2],
+    ]
+}
+
+/// Voxels of the active layer picked with a box. Once the selection is moved or pasted it
+/// floats above the grid: the cells it covers are remembered and restored when it moves on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    corner: [usize; 3],
+    extent: [usize; 3],
+    /// Positions relative to the corner with the palette entry of every selected voxel
+    voxels: Vec<([usize; 3], u16)>,
+    /// What the cells under the voxels contain without the selection, None until it moves
+    covered: Option<CellEdits>,
+}
+
+impl Selection {
+    /// Selects the voxels of the active layer inside the box, the box is cropped to the grid
+    pub fn from_box(voxel_manager: &VoxelManager, bbox: &BoundingBox) -> Self {
+        let grid = voxel_manager.extent();
+        let corner = [
+            (bbox.corner.x as usize).min(grid[0]),
+            (bbox.corner.y as usize).min(grid[1]),
+            (bbox.corner.z as usize).min(grid[2]),
+        ];
+        let extent = [
+            (bbox.extent.x as usize).min(grid[0] - corner[0]),
+            (bbox.extent.y as usize).min(grid[1] - corner[1]),
+            (bbox.extent.z as usize).min(grid[2] - corner[2]),
+        ];
+        Self::from_cells(voxel_manager, corner, extent)
+    }
+
+    /// Selects the voxels of the active layer inside the box of cells, which has to be
+    /// inside of the grid
+    pub fn from_cells(
+        voxel_manager: &VoxelManager,
+        corner: [usize; 3],
+        extent: [usize; 3],
+    ) -> Self {
+        let layer = &voxel_manager.layers()[voxel_manager.active_layer()];
+        let mut voxels = Vec::new();
+        for x in 0..extent[0] {
+            for y in 0..extent[1] {
+                for z in 0..extent[2] {
+                    let [gx, gy, gz] = offset_cell([x, y, z], corner);
+                    if let Some(idx) = layer.get(gx, gy, gz) {
+                        voxels.push(([x, y, z], idx));
+                    }
+                }
+            }
+        }
+        Selection {
+            corner,
+            extent,
+            voxels,
+            covered: None,
+        }
+    }
+
+    pub fn corner(&self) -> [usize; 3] {
+        self.corner
+    }
+
+    pub fn extent(&self) -> [usize; 3] {
+        self.extent
+    }
+
+    /// The box shown around the selection
+    pub fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::new(
+            Vector3::new(
+                self.corner[0] as f32,
+                self.corner[1] as f32,
+                self.corner[2] as f32,
+            ),
+            Vector3::new(
+                self.extent[0] as f32,
+                self.extent[1] as f32,
+                self.extent[2] as f32,
+            ),
+            HALF_ALPHA_YELLOW,
+        )
+    }
+
+    pub fn contains(&self, cell: [usize; 3]) -> bool {
+        (0..3).all(|i| cell[i] >= self.corner[i] && cell[i] < self.corner[i] + self.extent[i])
+    }
+
+    /// The cells under the voxels as they are without the selection. The voxels leave empty
+    /// cells behind when the selection is lifted for the first time.
+    fn covered(&self) -> CellEdits {
+        match self.covered {
+            Some(ref covered) => covered.clone(),
+            None => self
+                .voxels
+                .iter()
+                .map(|(pos, _)| (offset_cell(*pos, self.corner), None))
+                .collect(),
+        }
+    }
+
+    /// Moves the selection with its voxels by the offset and returns the cells to write.
+    /// Returns None if the selection would leave the grid.
+    pub fn translate(
+        &mut self,
+        offset: [i32; 3],
+        voxel_manager: &VoxelManager,
+    ) -> Option<CellEdits> {
+        let grid = voxel_manager.extent();
+        let mut corner = [0; 3];
+        for i in 0..3 {
+            let start = self.corner[i] as i32 + offset[i];
+            if start < 0 || start as usize + self.extent[i] > grid[i] {
+                return None;
+            }
+            corner[i] = start as usize;
+        }
+
+        let layer = &voxel_manager.layers()[voxel_manager.active_layer()];
+        let restored: HashMap<_, _> = self.covered().into_iter().collect();
+        let covered = self
+            .voxels
+            .iter()
+            .map(|(pos, _)| {
+                let cell = offset_cell(*pos, corner);
+                let below = match restored.get(&cell) {
+                    Some(value) => *value,
+                    None => layer.get(cell[0], cell[1], cell[2]),
+                };
+                (cell, below)
+            })
+            .collect();
+
+        let mut cells = restored;
+        for (pos, idx) in self.voxels.iter() {
+            cells.insert(offset_cell(*pos, corner), Some(*idx));
+        }
+        self.corner = corner;
+        self.covered = Some(covered);
+        let mut cells: Vec<_> = cells.into_iter().collect();
+        cells.sort_by_key(|(cell, _)| *cell);
+        Some(cells)
+    }
+
+    /// Removes the selected voxels and returns the cells to write, the cells under a moved
+    /// selection get their voxels back
+    pub fn delete(self) -> CellEdits {
+        self.covered()
+    }
+
+    /// The voxels of the active layer without the selection but with the ones it covers, and
+    /// the selected voxels, like two models to combine
+    pub fn split(&self, voxel_manager: &VoxelManager) -> (Voxels, Voxels) {
+        let layer = &voxel_manager.layers()[voxel_manager.active_layer()];
+        let mut rest: HashMap<_, _> = layer.voxels().into_iter().collect();
+        for (cell, value) in self.covered() {
+            match value {
+                Some(idx) => rest.insert(cell, idx),
+                None => rest.remove(&cell),
+            };
+        }
+        let mut rest: Vec<_> = rest.into_iter().collect();
+        rest.sort();
+        let selected = self
+            .voxels
+            .iter()
+            .map(|(pos, idx)| (offset_cell(*pos, self.corner), *idx))
+            .collect();
+        (rest, selected)
+    }
+
+    /// Copies the selected voxels with their colors and materials, so they can be pasted
+    /// into any object
+    pub fn copy(&self, voxel_manager: &VoxelManager) -> Clipboard {
+        Clipboard {
+            corner: self.corner,
+            extent: self.extent,
+            voxels: self
+                .voxels
+                .iter()
+                .map(|(pos, idx)| {
+                    let idx = *idx as usize;
+                    (
+                        *pos,
+                        voxel_manager.palette()[idx],
+                        voxel_manager.materials()[idx],
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Voxels copied from a selection
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clipboard {
+    /// Where the voxels were copied from
+    corner: [usize; 3],
+    extent: [usize; 3],
+    voxels: Vec<([usize; 3], [f32; 4], Material)>,
+}
+
+impl Clipboard {
+    /// Returns the palette entry with the color and material, the entry is added if needed
+    fn palette_index(voxel_manager: &mut VoxelManager, color: [f32; 4], material: Material) -> u16 {
+        if material == Material::default() {
+            return voxel_manager.color_index(color);
+        }
+        let existing = (0..voxel_manager.palette().len()).find(|idx| {
+            voxel_manager.palette()[*idx] == color && voxel_manager.materials()[*idx] == material
+        });
+        match existing {
+            Some(idx) => idx as u16,
+            None => {
+                let idx = voxel_manager.add_palette_color(color);
+                voxel_manager.set_material(idx, material);
+                idx
+            }
+        }
+    }
+
+    /// Pastes the voxels as a floating selection at the corner, or where they were copied
+    /// from. The selection is moved into the grid and cropped if the grid is too small.
+    /// Returns the selection with the cells to write, the palette gets the missing entries.
+    pub fn paste(
+        &self,
+        voxel_manager: &mut VoxelManager,
+        corner: Option<[usize; 3]>,
+    ) -> (Selection, CellEdits) {
+        let grid = voxel_manager.extent();
+        let mut corner = corner.unwrap_or(self.corner);
+        let mut extent = self.extent;
+        for i in 0..3 {
+            corner[i] = corner[i].min(grid[i].saturating_sub(extent[i]));
+            extent[i] = extent[i].min(grid[i] - corner[i]);
+        }
+
+        let mut voxels = Vec::new();
+        for (pos, color, material) in self.voxels.iter() {
+            if (0..3).all(|i| pos[i] < extent[i]) {
+                let idx = Self::palette_index(voxel_manager, *color, *material);
+                voxels.push((*pos, idx));
+            }
+        }
+        let layer = &voxel_manager.layers()[voxel_manager.active_layer()];
+        let covered = voxels
+            .iter()
+            .map(|(pos, _)| {
+                let cell = offset_cell(*pos, corner);
+                (cell, layer.get(cell[0], cell[1], cell[2]))
+            })
+            .collect();
+        let cells = voxels
+            .iter()
+            .map(|(pos, idx)| (offset_cell(*pos, corner), Some(*idx)))
+            .collect();
+        let selection = Selection {
+            corner,
+            extent,
+            voxels,
+            covered: Some(covered),
+        };
+        (selection, cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(corner: [f32; 3], extent: [f32; 3]) -> BoundingBox {
+        BoundingBox::new(corner.into(), extent.into(), [1.0; 4])
+    }
+
+    fn apply(voxel_manager: &mut VoxelManager, cells: &CellEdits) {
+        for ([x, y, z], value) in cells.iter() {
+            let layer = voxel_manager.active_layer();
+            voxel_manager.set_layer_cell(layer, *x, *y, *z, *value);
+        }
+    }
+
+    /// A grid with a red voxel at the origin and a blue one next to it on the x axis
+    fn grid() -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new([6, 4, 4]);
+        voxel_manager.set(0, 0, 0, [1.0, 0.0, 0.0, 1.0]);
+        voxel_manager.set(1, 0, 0, [0.0, 0.0, 1.0, 1.0]);
+        voxel_manager
+    }
+
+    #[test]
+    fn select_active_layer() {
+        let mut voxel_manager = grid();
+        voxel_manager.add_layer();
+        voxel_manager.set(0, 1, 0, [1.0; 4]);
+        let selection = Selection::from_box(&voxel_manager, &bbox([0.0; 3], [2.0, 2.0, 1.0]));
+        // Only the voxel of the active layer is selected
+        assert_eq!(selection.voxels, vec![([0, 1, 0], 2)]);
+        assert!(selection.contains([1, 1, 0]));
+        assert!(!selection.contains([2, 1, 0]));
+
+        let selection = Selection::from_box(&voxel_manager, &bbox([4.0, 0.0, 0.0], [5.0; 3]));
+        assert_eq!(selection.extent(), [2, 4, 4]);
+    }
+
+    #[test]
+    fn move_leaves_empty_cells() {
+        let mut voxel_manager = grid();
+        let mut selection = Selection::from_box(&voxel_manager, &bbox([0.0; 3], [1.0; 3]));
+        let cells = selection.translate([1, 0, 0], &voxel_manager).unwrap();
+        apply(&mut voxel_manager, &cells);
+        // The moved voxel covers the blue one
+        assert_eq!(voxel_manager.indexed_voxels(), vec![([1, 0, 0], 0)]);
+
+        let cells = selection.translate([2, 0, 1], &voxel_manager).unwrap();
+        apply(&mut voxel_manager, &cells);
+        // The blue voxel is back once the selection moved on
+        assert_eq!(
+            voxel_manager.indexed_voxels(),
+            vec![([1, 0, 0], 1), ([3, 0, 1], 0)]
+        );
+        assert_eq!(selection.corner(), [3, 0, 1]);
+
+        // The selection can't leave the grid
+        assert_eq!(selection.translate([3, 0, 0], &voxel_manager), None);
+        assert_eq!(selection.translate([0, 0, -2], &voxel_manager), None);
+        assert_eq!(selection.corner(), [3, 0, 1]);
+
+        apply(&mut voxel_manager, &selection.delete());
+        assert_eq!(voxel_manager.indexed_voxels(), vec![([1, 0, 0], 1)]);
+    }
+
+    #[test]
+    fn delete_selection() {
+        let mut voxel_manager = grid();
+        let selection = Selection::from_box(&voxel_manager, &bbox([0.0; 3], [6.0, 1.0, 1.0]));
+        apply(&mut voxel_manager, &selection.delete());
+        assert!(voxel_manager.indexed_voxels().is_empty());
+    }
+
+    #[test]
+    fn paste_floats_over_the_grid() {
+        let mut voxel_manager = grid();
+        let selection = Selection::from_box(&voxel_manager, &bbox([0.0; 3], [2.0, 1.0, 1.0]));
+        let clipboard = selection.copy(&voxel_manager);
+
+        // The copy is pasted where the voxels were copied from
+        let (mut pasted, cells) = clipboard.paste(&mut voxel_manager, None);
+        assert_eq!(pasted.corner(), [0, 0, 0]);
+        apply(&mut voxel_manager, &cells);
+        let original = grid();
+        assert_eq!(voxel_manager, original);
+
+        // Moving the pasted copy doesn't delete the voxels it was pasted over
+        let cells = pasted.translate([0, 2, 0], &voxel_manager).unwrap();
+        apply(&mut voxel_manager, &cells);
+        assert_eq!(
+            voxel_manager.indexed_voxels(),
+            vec![
+                ([0, 0, 0], 0),
+                ([0, 2, 0], 0),
+                ([1, 0, 0], 1),
+                ([1, 2, 0], 1)
+            ]
+        );
+        apply(&mut voxel_manager, &pasted.delete());
+        assert_eq!(voxel_manager, original);
+
+        // The copy is moved into the grid and cropped to it
+        let mut small = VoxelManager::new([1, 2, 2]);
+        let (pasted, cells) = clipboard.paste(&mut small, Some([3, 1, 0]));
+        assert_eq!(pasted.corner(), [0, 1, 0]);
+        assert_eq!(pasted.extent(), [1, 1, 1]);
+        apply(&mut small, &cells);
+        assert_eq!(small.voxels(), vec![([0, 1, 0], [1.0, 0.0, 0.0, 1.0])]);
+    }
+
+    #[test]
+    fn split_from_the_model() {
+        let mut voxel_manager = grid();
+        let mut selection = Selection::from_box(&voxel_manager, &bbox([0.0; 3], [1.0; 3]));
+        assert_eq!(
+            selection.split(&voxel_manager),
+            (vec![([1, 0, 0], 1)], vec![([0, 0, 0], 0)])
+        );
+        // The moved selection covers the blue voxel
+        let cells = selection.translate([1, 0, 0], &voxel_manager).unwrap();
+        apply(&mut voxel_manager, &cells);
+        assert_eq!(
+            selection.split(&voxel_manager),
+            (vec![([1, 0, 0], 1)], vec![([1, 0, 0], 0)])
+        );
+    }
+
+    #[test]
+    fn paste_adds_palette_entries() {
+        let mut voxel_manager = grid();
+        let glass = Material {
+            transparency: 0.5,
+            ..Material::default()
+        };
+        voxel_manager.set_material(1, glass);
+        let selection = Selection::from_box(&voxel_manager, &bbox([0.0; 3], [2.0, 1.0, 1.0]));
+        let clipboard = selection.copy(&voxel_manager);
+
+        let mut other = VoxelManager::new([4; 3]);
+        other.set_palette(vec![[0.0, 0.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0]]);
+        let (_, cells) = clipboard.paste(&mut other, Some([0; 3]));
+        apply(&mut other, &cells);
+        // The red entry is reused, the blue glass needs a new entry
+        assert_eq!(other.indexed_voxels(), vec![([0, 0, 0], 1), ([1, 0, 0], 2)]);
+        assert_eq!(other.palette()[2], [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(other.materials()[2], glass);
+
+        // Pasting again finds the glass entry
+        let (_, cells) = clipboard.paste(&mut other, Some([2, 0, 0]));
+        assert_eq!(cells, vec![([2, 0, 0], Some(1)), ([3, 0, 0], Some(2))]);
+        assert_eq!(other.palette().len(), 3);
+    }
+}
@@ -0,0 +1,460 @@
+use crate::geometry::BoundingBox;
+use cgmath::Vector3;
+use std::collections::{BTreeMap, HashMap};
+
+/// The shapes drawn into the dragged box
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Shape {
+    /// A line from the start to the end of the drag
+    Line,
+    /// A sphere fitted inside the largest cube from the start of the drag which fits in the
+    /// dragged box
+    Sphere,
+    Ellipsoid,
+    /// A cylinder with its caps at the ends of the axis
+    Cylinder,
+    /// A cone with its base at the low end of the axis and its apex at the high end
+    Cone,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShapeOptions {
+    /// The axis of the cylinders and cones
+    pub axis: usize,
+    /// Only the cells on the surface of the shape are filled
+    pub hollow: bool,
+}
+
+impl Shape {
+    /// The cells of the shape fitted inside the box between the start and end cells, which
+    /// are both part of the box
+    pub fn rasterize(self, start: [i32; 3], end: [i32; 3], options: ShapeOptions) -> Vec<[i32; 3]> {
+        if self == Shape::Line {
+            return line(start, end);
+        }
+        if self == Shape::Sphere {
+            let side = (0..3).map(|i| (end[i] - start[i]).abs()).min().unwrap();
+            let mut end = end;
+            for i in 0..3 {
+                end[i] = start[i] + side * (end[i] - start[i]).signum();
+            }
+            return Shape::Ellipsoid.rasterize(start, end, options);
+        }
+        let mut corner = [0; 3];
+        let mut size = [0; 3];
+        for i in 0..3 {
+            corner[i] = start[i].min(end[i]);
+            size[i] = (end[i] - start[i]).abs() + 1;
+        }
+        let inside = |cell: [i32; 3]| {
+            (0..3).all(|i| cell[i] >= 0 && cell[i] < size[i])
+                && self.contains(cell, size, options.axis)
+        };
+        let mut cells = Vec::new();
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let cell = [x, y, z];
+                    if !inside(cell) {
+                        continue;
+                    }
+                    // The cells of a hollow shape have a face towards the outside
+                    if options.hollow
+                        && FACE_OFFSETS
+                            .iter()
+                            .all(|o| inside([x + o[0], y + o[1], z + o[2]]))
+                    {
+                        continue;
+                    }
+                    cells.push([corner[0] + x, corner[1] + y, corner[2] + z]);
+                }
+            }
+        }
+        cells
+    }
+
+    /// Whether the center of the cell is inside of the shape fitted to a box of the size
+    fn contains(self, cell: [i32; 3], size: [i32; 3], axis: usize) -> bool {
+        // Distance from the center of the box, scaled so the sides of the box are at 1
+        let mut distance = [0.0; 3];
+        for i in 0..3 {
+            distance[i] = (2.0 * cell[i] as f32 + 1.0) / size[i] as f32 - 1.0;
+        }
+        let across: f32 = (0..3)
+            .filter(|i| *i != axis)
+            .map(|i| distance[i] * distance[i])
+            .sum();
+        match self {
+            Shape::Line | Shape::Sphere => unreachable!("Only ellipsoids are fitted to a box"),
+            Shape::Ellipsoid => across + distance[axis] * distance[axis] <= 1.0,
+            Shape::Cylinder => across <= 1.0,
+            Shape::Cone => {
+                // The radius shrinks from the base to the apex
+                let radius = (1.0 - distance[axis]) / 2.0;
+                across <= radius * radius
+            }
+        }
+    }
+}
+
+const FACE_OFFSETS: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+/// The cells of a 3D Bresenham line, one for every step along the axis the line is longest on
+pub fn line(start: [i32; 3], end: [i32; 3]) -> Vec<[i32; 3]> {
+    let mut delta = [0; 3];
+    let mut step = [0; 3];
+    for i in 0..3 {
+        delta[i] = (end[i] - start[i]).abs();
+        step[i] = (end[i] - start[i]).signum();
+    }
+    let driving = (0..3).max_by_key(|i| (delta[*i], 3 - i)).unwrap();
+    let mut errors = [0; 3];
+    for i in 0..3 {
+        errors[i] = 2 * delta[i] - delta[driving];
+    }
+    let mut cell = start;
+    let mut cells = vec![cell];
+    for _ in 0..delta[driving] {
+        cell[driving] += step[driving];
+        for i in (0..3).filter(|i| *i != driving) {
+            if errors[i] > 0 {
+                cell[i] += step[i];
+                errors[i] -= 2 * delta[driving];
+            }
+            errors[i] += 2 * delta[i];
+        }
+        cells.push(cell);
+    }
+    cells
+}
+
+/// The cells of the box between the start and end cells which are less than `wall` cells
+/// from its sides, like the walls, floor and ceiling of a room
+pub fn box_walls(start: [i32; 3], end: [i32; 3], wall: i32) -> Vec<[i32; 3]> {
+    let mut cells = Vec::new();
+    for z in start[2].min(end[2])..=start[2].max(end[2]) {
+        for y in start[1].min(end[1])..=start[1].max(end[1]) {
+            for x in start[0].min(end[0])..=start[0].max(end[0]) {
+                let cell = [x, y, z];
+                let in_wall = (0..3).any(|i| {
+                    let low = start[i].min(end[i]);
+                    let high = start[i].max(end[i]);
+                    cell[i] - low < wall || high - cell[i] < wall
+                });
+                if in_wall {
+                    cells.push(cell);
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// The cells at the start and the end of the box dragged from the start cell to the end cell,
+/// before it's rearranged
+pub fn drag_ends(bbox: &BoundingBox) -> ([i32; 3], [i32; 3]) {
+    let mut start = [0; 3];
+    let mut end = [0; 3];
+    for i in 0..3 {
+        let corner = bbox.corner[i].round() as i32;
+        let extent = bbox.extent[i].round() as i32;
+        start[i] = if extent > 0 { corner } else { corner - 1 };
+        end[i] = if extent > 0 {
+            corner + extent - 1
+        } else {
+            corner + extent
+        };
+    }
+    (start, end)
+}
+
+/// The cells inside of a grid with the given extent
+pub fn grid_cells(cells: &[[i32; 3]], extent: [usize; 3]) -> Vec<[usize; 3]> {
+    cells
+        .iter()
+        .filter(|cell| (0..3).all(|i| cell[i] >= 0 && (cell[i] as usize) < extent[i]))
+        .map(|cell| [cell[0] as usize, cell[1] as usize, cell[2] as usize])
+        .collect()
+}
+
+/// Boxes covering the cells, the runs of cells along the x axis are merged into one box and
+/// the equal runs of consecutive rows of a slice are merged into one box
+pub fn preview_boxes(cells: &[[i32; 3]], color: [f32; 4]) -> Vec<BoundingBox> {
+    let mut cells = cells.to_vec();
+    cells.sort_by_key(|cell| (cell[2], cell[1], cell[0]));
+    cells.dedup();
+    // The runs along the x axis as (start cell, length)
+    let mut runs: Vec<([i32; 3], i32)> = Vec::new();
+    for cell in cells {
+        match runs.last_mut() {
+            Some((start, length))
+                if start[1] == cell[1] && start[2] == cell[2] && start[0] + *length == cell[0] =>
+            {
+                *length += 1
+            }
+            _ => runs.push((cell, 1)),
+        }
+    }
+    // The box of the last run with the same slice, start and length
+    let mut last_boxes: HashMap<(i32, i32, i32), usize> = HashMap::new();
+    let mut boxes: Vec<BoundingBox> = Vec::new();
+    for (start, length) in runs {
+        let key = (start[2], start[0], length);
+        match last_boxes.get(&key) {
+            Some(&index) if boxes[index].corner.y + boxes[index].extent.y == start[1] as f32 => {
+                boxes[index].extent.y += 1.0
+            }
+            _ => {
+                last_boxes.insert(key, boxes.len());
+                boxes.push(BoundingBox::new(
+                    Vector3::new(start[0] as f32, start[1] as f32, start[2] as f32),
+                    Vector3::new(length as f32, 1.0, 1.0),
+                    color,
+                ));
+            }
+        }
+    }
+    boxes
+}
+
+/// One box around the cells of every slice along the z axis, which outlines shapes with too
+/// many boxes to preview
+pub fn outline_boxes(cells: &[[i32; 3]], color: [f32; 4]) -> Vec<BoundingBox> {
+    let mut slices: BTreeMap<i32, ([i32; 2], [i32; 2])> = BTreeMap::new();
+    for cell in cells {
+        let bounds = slices
+            .entry(cell[2])
+            .or_insert(([cell[0], cell[1]], [cell[0], cell[1]]));
+        bounds.0 = [bounds.0[0].min(cell[0]), bounds.0[1].min(cell[1])];
+        bounds.1 = [bounds.1[0].max(cell[0]), bounds.1[1].max(cell[1])];
+    }
+    slices
+        .into_iter()
+        .map(|(z, (min, max))| {
+            BoundingBox::new(
+                Vector3::new(min[0] as f32, min[1] as f32, z as f32),
+                Vector3::new(
+                    (max[0] - min[0] + 1) as f32,
+                    (max[1] - min[1] + 1) as f32,
+                    1.0,
+                ),
+                color,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const SOLID: ShapeOptions = ShapeOptions {
+        axis: 2,
+        hollow: false,
+    };
+    const HOLLOW: ShapeOptions = ShapeOptions {
+        axis: 2,
+        hollow: true,
+    };
+
+    fn count(shape: Shape, size: [i32; 3], options: ShapeOptions) -> usize {
+        shape
+            .rasterize([0; 3], [size[0] - 1, size[1] - 1, size[2] - 1], options)
+            .len()
+    }
+
+    /// Whether mirroring the cells of the box along the axis gives the same cells
+    fn mirror_symmetric(cells: &[[i32; 3]], size: [i32; 3], axis: usize) -> bool {
+        let set: HashSet<_> = cells.iter().cloned().collect();
+        cells.iter().all(|cell| {
+            let mut mirrored = *cell;
+            mirrored[axis] = size[axis] - 1 - cell[axis];
+            set.contains(&mirrored)
+        })
+    }
+
+    #[test]
+    fn line_cells() {
+        assert_eq!(line([1, 1, 1], [1, 1, 1]), vec![[1, 1, 1]]);
+        assert_eq!(
+            line([0, 0, 0], [3, 0, 0]),
+            vec![[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0]]
+        );
+        assert_eq!(
+            line([2, 2, 2], [0, 0, 0]),
+            vec![[2, 2, 2], [1, 1, 1], [0, 0, 0]]
+        );
+
+        let cells = line([0, 5, 1], [7, 1, 3]);
+        // One cell for every step along the longest axis, every step goes to a neighbour
+        assert_eq!(cells.len(), 8);
+        assert_eq!((cells[0], cells[7]), ([0, 5, 1], [7, 1, 3]));
+        for pair in cells.windows(2) {
+            assert_eq!(pair[1][0] - pair[0][0], 1);
+            assert!((1..3).all(|i| (pair[1][i] - pair[0][i]).abs() <= 1));
+        }
+        // The line through the middle of the box is point symmetric
+        let cells = line([0, 0, 0], [4, 2, 2]);
+        assert_eq!(
+            cells,
+            vec![[0, 0, 0], [1, 0, 0], [2, 1, 1], [3, 1, 1], [4, 2, 2]]
+        );
+        assert_eq!(Shape::Line.rasterize([0; 3], [4, 2, 2], HOLLOW), cells);
+    }
+
+    #[test]
+    fn ellipsoid_cells() {
+        assert_eq!(count(Shape::Ellipsoid, [1; 3], SOLID), 1);
+        // The corners are cut off the cube of 3 cells
+        assert_eq!(count(Shape::Ellipsoid, [3; 3], SOLID), 19);
+        assert_eq!(count(Shape::Ellipsoid, [3; 3], HOLLOW), 18);
+        // The cells with a distance of at most 2.5 from the center
+        assert_eq!(count(Shape::Ellipsoid, [5; 3], SOLID), 81);
+        // The cube of 27 cells in the middle is hidden
+        assert_eq!(count(Shape::Ellipsoid, [5; 3], HOLLOW), 54);
+
+        let size = [6, 5, 3];
+        let cells = Shape::Ellipsoid.rasterize([0; 3], [5, 4, 2], SOLID);
+        assert!((0..3).all(|axis| mirror_symmetric(&cells, size, axis)));
+        // The shape is fitted inside the box wherever it's dragged from
+        let moved = Shape::Ellipsoid.rasterize([7, 6, 4], [2, 2, 2], SOLID);
+        assert_eq!(moved.len(), cells.len());
+        assert!(moved.contains(&[2, 4, 3]) && moved.contains(&[7, 4, 3]));
+    }
+
+    #[test]
+    fn sphere_cells() {
+        // The largest cube in the box is 8 cells wide, from 3 to 10 on the x and y axes and
+        // from 10 to 17 on the z axis
+        let cells = Shape::Sphere.rasterize([10, 10, 10], [3, 2, 20], SOLID);
+        assert_eq!(cells.len(), count(Shape::Ellipsoid, [8; 3], SOLID));
+        let center = [7.0, 7.0, 14.0];
+        assert!(cells.iter().all(|cell| {
+            let distance: f32 = (0..3)
+                .map(|i| (cell[i] as f32 + 0.5 - center[i]).powi(2))
+                .sum();
+            distance <= 4.0 * 4.0
+        }));
+        assert!(cells.contains(&[3, 6, 13]) && cells.contains(&[10, 6, 13]));
+        assert!(cells.contains(&[6, 6, 10]) && cells.contains(&[6, 6, 17]));
+        assert_eq!(count(Shape::Sphere, [1, 4, 4], SOLID), 1);
+    }
+
+    #[test]
+    fn cylinder_cells() {
+        // Discs of 21 cells stacked along the axis, only the middle disc has hidden cells
+        assert_eq!(count(Shape::Cylinder, [5, 5, 3], SOLID), 63);
+        assert_eq!(count(Shape::Cylinder, [5, 5, 3], HOLLOW), 54);
+        let x_axis = ShapeOptions { axis: 0, ..SOLID };
+        assert_eq!(count(Shape::Cylinder, [3, 5, 5], x_axis), 63);
+        // Across a box of 5 by 3 cells the ellipses have 11 cells
+        assert_eq!(count(Shape::Cylinder, [5, 5, 3], x_axis), 55);
+
+        let size = [5, 7, 6];
+        let cells = Shape::Cylinder.rasterize([0; 3], [4, 6, 5], SOLID);
+        assert!((0..3).all(|axis| mirror_symmetric(&cells, size, axis)));
+    }
+
+    #[test]
+    fn cone_cells() {
+        // A disc of 5 cells below the apex
+        assert_eq!(count(Shape::Cone, [3; 3], SOLID), 7);
+        assert_eq!(count(Shape::Cone, [3; 3], HOLLOW), 7);
+
+        let size = [7, 7, 5];
+        let cells = Shape::Cone.rasterize([0; 3], [6, 6, 4], SOLID);
+        assert!(mirror_symmetric(&cells, size, 0));
+        assert!(mirror_symmetric(&cells, size, 1));
+        assert!(!mirror_symmetric(&cells, size, 2));
+        // The slices shrink towards the apex
+        let slice = |z| cells.iter().filter(|cell| cell[2] == z).count();
+        assert!((1..5).all(|z| slice(z) <= slice(z - 1)));
+        assert_eq!(slice(4), 1);
+    }
+
+    #[test]
+    fn walls_of_a_box() {
+        assert_eq!(box_walls([0; 3], [4, 4, 4], 1).len(), 125 - 27);
+        assert_eq!(box_walls([4, 4, 4], [0; 3], 2).len(), 125 - 1);
+        // Thick walls fill the box
+        assert_eq!(box_walls([0; 3], [4, 4, 4], 3).len(), 125);
+        // The walls of a flat box cover it
+        assert_eq!(box_walls([1, 1, 1], [5, 5, 1], 1).len(), 25);
+        assert!(!box_walls([0; 3], [3, 3, 3], 1).contains(&[2, 1, 2]));
+    }
+
+    #[test]
+    fn drag_end_cells() {
+        // Dragged from the cell at (3, 1, 1) to the one at (1, 1, 2)
+        let bbox = BoundingBox::new(
+            Vector3::new(4.0, 1.0, 1.0),
+            Vector3::new(-3.0, 1.0, 2.0),
+            [1.0; 4],
+        );
+        assert_eq!(drag_ends(&bbox), ([3, 1, 1], [1, 1, 2]));
+    }
+
+    #[test]
+    fn preview_merges_runs() {
+        let cells = [[0, 0, 0], [2, 0, 0], [1, 0, 0], [1, 1, 0], [4, 0, 0]];
+        let boxes = preview_boxes(&cells, [1.0; 4]);
+        let corners: Vec<([f32; 3], [f32; 3])> = boxes
+            .iter()
+            .map(|bbox| (bbox.corner.into(), bbox.extent.into()))
+            .collect();
+        assert_eq!(
+            corners,
+            vec![
+                ([0.0, 0.0, 0.0], [3.0, 1.0, 1.0]),
+                ([4.0, 0.0, 0.0], [1.0, 1.0, 1.0]),
+                ([1.0, 1.0, 0.0], [1.0, 1.0, 1.0]),
+            ]
+        );
+        assert_eq!(
+            grid_cells(&[[-1, 0, 0], [1, 2, 0], [1, 1, 1]], [2, 2, 2]),
+            vec![[1, 1, 1]]
+        );
+    }
+
+    #[test]
+    fn preview_merges_rows() {
+        let size = 64;
+        let cells: Vec<[i32; 3]> = (0..size)
+            .flat_map(|z| (0..size).flat_map(move |y| (0..size).map(move |x| [x, y, z])))
+            .filter(|cell| cell.iter().any(|&i| i == 0 || i == size - 1))
+            .collect();
+        let boxes = preview_boxes(&cells, [1.0; 4]);
+        // The floor and the ceiling, and four boxes for the walls of every other slice
+        assert_eq!(boxes.len(), 2 + 4 * (size as usize - 2));
+        let covered: f32 = boxes
+            .iter()
+            .map(|bbox| bbox.extent.x * bbox.extent.y * bbox.extent.z)
+            .sum();
+        assert_eq!(covered as usize, cells.len());
+    }
+
+    #[test]
+    fn outline_slices() {
+        let cells = [[1, 2, 0], [3, 0, 0], [2, 2, 0], [5, 5, 2]];
+        let boxes = outline_boxes(&cells, [1.0; 4]);
+        let corners: Vec<([f32; 3], [f32; 3])> = boxes
+            .iter()
+            .map(|bbox| (bbox.corner.into(), bbox.extent.into()))
+            .collect();
+        assert_eq!(
+            corners,
+            vec![
+                ([1.0, 0.0, 0.0], [3.0, 3.0, 1.0]),
+                ([5.0, 5.0, 2.0], [1.0, 1.0, 1.0]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use crate::geometry::BoundingBox;
+use cgmath::Vector3;
+
+/// Mirror planes of the symmetric drawing mode, every edit is repeated on the other side of
+/// the enabled planes
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Symmetry {
+    /// Whether the edits are mirrored along the x, y and z axes
+    pub enabled: [bool; 3],
+    /// Position of the mirror plane on every axis in cells, a multiple of a half cell.
+    /// The plane goes through the center of the grid if it's None.
+    pub planes: [Option<f32>; 3],
+}
+
+impl Symmetry {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.iter().any(|enabled| *enabled)
+    }
+
+    /// Position of the mirror plane on the axis in a grid of the given extent
+    pub fn plane(&self, axis: usize, extent: [usize; 3]) -> f32 {
+        self.planes[axis].unwrap_or(extent[axis] as f32 / 2.0)
+    }
+
+    /// The mirror images of the box on the other sides of the enabled planes, cropped to the
+    /// grid. The box itself isn't included, neither are the images outside of the grid.
+    pub fn mirrored_boxes(&self, bbox: &BoundingBox, extent: [usize; 3]) -> Vec<BoundingBox> {
+        let axes: Vec<usize> = (0..3).filter(|axis| self.enabled[*axis]).collect();
+        let mut boxes = Vec::new();
+        // Every combination of the planes gives an image
+        for mask in 1..1 << axes.len() {
+            let mut mirrored = *bbox;
+            for (bit, &axis) in axes.iter().enumerate() {
+                if mask & 1 << bit != 0 {
+                    mirrored.corner[axis] = 2.0 * self.plane(axis, extent) - bbox.corner[axis];
+                    mirrored.extent[axis] = -bbox.extent[axis];
+                }
+            }
+            if let Some(cropped) = crop(&mirrored, extent) {
+                boxes.push(cropped);
+            }
+        }
+        boxes
+    }
+}
+
+/// The part of the box inside of the grid with a positive extent, if there is any
+fn crop(bbox: &BoundingBox, extent: [usize; 3]) -> Option<BoundingBox> {
+    let mut corner = Vector3::new(0.0, 0.0, 0.0);
+    let mut size = Vector3::new(0.0, 0.0, 0.0);
+    for axis in 0..3 {
+        let end = bbox.corner[axis] + bbox.extent[axis];
+        let start = bbox.corner[axis].min(end).max(0.0);
+        let end = bbox.corner[axis].max(end).min(extent[axis] as f32);
+        if start >= end {
+            return None;
+        }
+        corner[axis] = start;
+        size[axis] = end - start;
+    }
+    Some(BoundingBox::new(corner, size, bbox.color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell_box(corner: [f32; 3], extent: [f32; 3]) -> BoundingBox {
+        BoundingBox::new(corner.into(), extent.into(), [1.0; 4])
+    }
+
+    fn corners(boxes: &[BoundingBox]) -> Vec<([f32; 3], [f32; 3])> {
+        boxes
+            .iter()
+            .map(|bbox| (bbox.corner.into(), bbox.extent.into()))
+            .collect()
+    }
+
+    #[test]
+    fn mirror_at_grid_center() {
+        let mut symmetry = Symmetry::default();
+        let bbox = cell_box([1.0, 2.0, 3.0], [2.0, 1.0, 1.0]);
+        assert!(!symmetry.is_enabled());
+        assert!(symmetry.mirrored_boxes(&bbox, [8; 3]).is_empty());
+
+        symmetry.enabled[0] = true;
+        assert_eq!(
+            corners(&symmetry.mirrored_boxes(&bbox, [8; 3])),
+            vec![([5.0, 2.0, 3.0], [2.0, 1.0, 1.0])]
+        );
+        // The plane goes through the middle cell of odd grids
+        assert_eq!(
+            corners(&symmetry.mirrored_boxes(&bbox, [7, 8, 8])),
+            vec![([4.0, 2.0, 3.0], [2.0, 1.0, 1.0])]
+        );
+        // A box on the plane is its own image
+        let middle = cell_box([3.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        assert_eq!(
+            corners(&symmetry.mirrored_boxes(&middle, [7, 8, 8])),
+            vec![([3.0, 0.0, 0.0], [1.0, 1.0, 1.0])]
+        );
+    }
+
+    #[test]
+    fn every_combination_of_planes() {
+        let symmetry = Symmetry {
+            enabled: [true, false, true],
+            planes: [None, Some(1.5), Some(4.0)],
+        };
+        let bbox = cell_box([0.0, 0.0, 1.0], [1.0, 1.0, 1.0]);
+        assert_eq!(
+            corners(&symmetry.mirrored_boxes(&bbox, [4, 4, 8])),
+            vec![
+                ([3.0, 0.0, 1.0], [1.0, 1.0, 1.0]),
+                ([0.0, 0.0, 6.0], [1.0, 1.0, 1.0]),
+                ([3.0, 0.0, 6.0], [1.0, 1.0, 1.0]),
+            ]
+        );
+
+        let symmetry = Symmetry {
+            enabled: [true; 3],
+            planes: [None; 3],
+        };
+        assert_eq!(symmetry.mirrored_boxes(&bbox, [4; 3]).len(), 7);
+    }
+
+    #[test]
+    fn images_are_cropped_to_the_grid() {
+        let symmetry = Symmetry {
+            enabled: [true, false, false],
+            planes: [Some(2.0), None, None],
+        };
+        // The cursor boxes may have negative extents
+        let bbox = cell_box([3.0, 1.0, 1.0], [-3.0, 1.0, 1.0]);
+        assert_eq!(
+            corners(&symmetry.mirrored_boxes(&bbox, [5; 3])),
+            vec![([1.0, 1.0, 1.0], [3.0, 1.0, 1.0])]
+        );
+        let bbox = cell_box([0.0, 1.0, 1.0], [2.0, 1.0, 1.0]);
+        let symmetry = Symmetry {
+            planes: [Some(5.0), None, None],
+            ..symmetry
+        };
+        assert!(symmetry.mirrored_boxes(&bbox, [5; 3]).is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::selection::Voxels;
+use std::num::NonZeroU16;
+
+/// Most octaves of noise a terrain can have, finer ones are smaller than a voxel anyway
+pub const MAX_OCTAVES: usize = 8;
+/// Most columns the preview shows along an axis
+pub const PREVIEW_COLUMNS: usize = 64;
+
+/// How the noise fills the grid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TerrainMode {
+    /// Fills every column up to a height given by 2D noise
+    Heightfield,
+    /// Fills the cells where 3D noise is dense enough, which leaves caves and overhangs
+    Density,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainParams {
+    pub seed: u64,
+    pub mode: TerrainMode,
+    /// Size of the largest features in cells
+    pub scale: f32,
+    pub octaves: usize,
+    /// How much finer each octave is than the last
+    pub lacunarity: f32,
+    /// How much weaker each octave is than the last
+    pub gain: f32,
+    /// Average ground level as a fraction of the grid height
+    pub level: f32,
+}
+
+impl Default for TerrainParams {
+    fn default() -> TerrainParams {
+        TerrainParams {
+            seed: 0,
+            mode: TerrainMode::Heightfield,
+            scale: 32.0,
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+            level: 0.5,
+        }
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Dot product of the offset with one of the 12 gradients along the edges of a cube
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    let u = if h & 1 == 0 { u } else { -u };
+    let v = if h & 2 == 0 { v } else { -v };
+    u + v
+}
+
+/// Gradient noise after Ken Perlin's improved noise, with the permutation table shuffled by
+/// a seed
+pub struct Noise {
+    perm: [u8; 512],
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Noise {
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().take(256).enumerate() {
+            *p = i as u8;
+        }
+        // The same linear congruential generator on every platform, so a seed always gives the
+        // same terrain
+        let mut state = seed;
+        for i in (1..256).rev() {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            perm.swap(i, (state >> 33) as usize % (i + 1));
+        }
+        for i in 0..256 {
+            perm[256 + i] = perm[i];
+        }
+        Noise { perm }
+    }
+
+    /// Noise at the point, about -1 to 1 and 0 on the integer lattice
+    pub fn sample(&self, point: [f32; 3]) -> f32 {
+        let mut cell = [0; 3];
+        let mut offset = [0.0; 3];
+        for i in 0..3 {
+            let floor = point[i].floor();
+            cell[i] = (floor as i64 & 255) as usize;
+            offset[i] = point[i] - floor;
+        }
+        let p = &self.perm;
+        let [x, y, z] = cell;
+        let a = p[x] as usize + y;
+        let aa = p[a] as usize + z;
+        let ab = p[a + 1] as usize + z;
+        let b = p[x + 1] as usize + y;
+        let ba = p[b] as usize + z;
+        let bb = p[b + 1] as usize + z;
+        let [fx, fy, fz] = offset;
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], fx, fy, fz), grad(p[ba], fx - 1.0, fy, fz)),
+                lerp(
+                    u,
+                    grad(p[ab], fx, fy - 1.0, fz),
+                    grad(p[bb], fx - 1.0, fy - 1.0, fz),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], fx, fy, fz - 1.0),
+                    grad(p[ba + 1], fx - 1.0, fy, fz - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], fx, fy - 1.0, fz - 1.0),
+                    grad(p[bb + 1], fx - 1.0, fy - 1.0, fz - 1.0),
+                ),
+            ),
+        )
+    }
+
+    /// Sum of octaves of noise, each `lacunarity` times finer and `gain` times weaker than the
+    /// last, scaled back to about -1 to 1
+    pub fn fractal(&self, point: [f32; 3], octaves: usize, lacunarity: f32, gain: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        for _ in 0..octaves.max(1) {
+            let [x, y, z] = point;
+            sum += amplitude * self.sample([x * frequency, y * frequency, z * frequency]);
+            total += amplitude;
+            amplitude *= gain;
+            frequency *= lacunarity;
+        }
+        sum / total
+    }
+}
+
+impl TerrainParams {
+    /// Fractal noise at the center of the cell
+    fn noise_at(&self, noise: &Noise, cell: [usize; 3]) -> f32 {
+        let mut point = [0.0; 3];
+        for i in 0..3 {
+            point[i] = (cell[i] as f32 + 0.5) / self.scale;
+        }
+        noise.fractal(point, self.octaves, self.lacunarity, self.gain)
+    }
+
+    /// Number of filled cells of the column in heightfield mode
+    fn column_height(&self, noise: &Noise, x: usize, z: usize, height: usize) -> usize {
+        // The height noise is a slice of the 3D noise below the grid
+        let n = self.noise_at(noise, [x, 0, z]);
+        let h = ((self.level + n) * height as f32).round();
+        h.max(0.0).min(height as f32) as usize
+    }
+
+    /// Whether the cell is solid in density mode. The density falls with the height, so the
+    /// grid is solid well below the ground level and empty well above it.
+    fn dense(&self, noise: &Noise, cell: [usize; 3], height: usize) -> bool {
+        let falloff = self.level - (cell[1] as f32 + 0.5) / height as f32;
+        self.noise_at(noise, cell) + falloff > 0.0
+    }
+}
+
+/// The number of height bands the palette colors, none if the palette is empty
+pub fn palette_bands(palette: &[[f32; 4]]) -> Option<NonZeroU16> {
+    NonZeroU16::new(palette.len().min(u16::MAX as usize) as u16)
+}
+
+/// Palette entry of the height band of the row, the first entry at the bottom and the last at
+/// the top of the grid
+fn band(y: usize, height: usize, bands: NonZeroU16) -> u16 {
+    (y * bands.get() as usize / height) as u16
+}
+
+/// Fills a grid of the extent with terrain colored by height bands of the first `bands`
+/// palette entries. The voxels are ordered by position.
+pub fn generate(params: &TerrainParams, extent: [usize; 3], bands: NonZeroU16) -> Voxels {
+    let noise = Noise::new(params.seed);
+    let [width, height, depth] = extent;
+    let mut heights = Vec::new();
+    if params.mode == TerrainMode::Heightfield {
+        for x in 0..width {
+            for z in 0..depth {
+                heights.push(params.column_height(&noise, x, z, height));
+            }
+        }
+    }
+    let mut voxels = Vec::new();
+    for x in 0..width {
+        for y in 0..height {
+            for z in 0..depth {
+                let filled = match params.mode {
+                    TerrainMode::Heightfield => y < heights[x * depth + z],
+                    TerrainMode::Density => params.dense(&noise, [x, y, z], height),
+                };
+                if filled {
+                    voxels.push(([x, y, z], band(y, height, bands)));
+                }
+            }
+        }
+    }
+    voxels
+}
+
+/// The palette entry of the highest voxel of every `step`th column seen from above, row by
+/// row along the z axis. It previews the terrain without generating all of it.
+pub fn top_view(
+    params: &TerrainParams,
+    extent: [usize; 3],
+    bands: NonZeroU16,
+    step: usize,
+) -> Vec<Option<u16>> {
+    let noise = Noise::new(params.seed);
+    let [width, height, depth] = extent;
+    let mut columns = Vec::new();
+    for z in (0..depth).step_by(step) {
+        for x in (0..width).step_by(step) {
+            let top = match params.mode {
+                TerrainMode::Heightfield => {
+                    params.column_height(&noise, x, z, height).checked_sub(1)
+                }
+                TerrainMode::Density => (0..height)
+                    .rev()
+                    .find(|y| params.dense(&noise, [x, *y, z], height)),
+            };
+            columns.push(top.map(|y| band(y, height, bands)));
+        }
+    }
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_manager::VoxelManager;
+
+    fn bands(count: u16) -> NonZeroU16 {
+        NonZeroU16::new(count).unwrap()
+    }
+
+    fn params(mode: TerrainMode) -> TerrainParams {
+        TerrainParams {
+            seed: 7,
+            mode,
+            scale: 4.0,
+            octaves: 3,
+            ..TerrainParams::default()
+        }
+    }
+
+    /// The slice of the grid at the z coordinate as rows of filled and empty cells from the
+    /// top down
+    fn slice(voxels: &Voxels, extent: [usize; 3], z: usize) -> Vec<String> {
+        (0..extent[1])
+            .rev()
+            .map(|y| {
+                (0..extent[0])
+                    .map(
+                        |x| match voxels.iter().find(|(cell, _)| *cell == [x, y, z]) {
+                            Some((_, idx)) => idx.to_string(),
+                            None => String::from("."),
+                        },
+                    )
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn noise_is_seeded() {
+        let points: Vec<_> = (0..50)
+            .map(|i| [i as f32 * 0.37, i as f32 * 0.11, 5.0 - i as f32 * 0.23])
+            .collect();
+        let samples = |seed| -> Vec<f32> {
+            let noise = Noise::new(seed);
+            points.iter().map(|point| noise.sample(*point)).collect()
+        };
+        assert_eq!(samples(1), samples(1));
+        assert_ne!(samples(1), samples(2));
+        assert!(samples(3).iter().all(|n| n.abs() <= 1.1));
+        assert_eq!(Noise::new(4).sample([3.0, -2.0, 7.0]), 0.0);
+    }
+
+    #[test]
+    fn fractal_stays_in_range() {
+        let noise = Noise::new(9);
+        for i in 0..100 {
+            let point = [i as f32 * 0.71, i as f32 * 0.13, i as f32 * 0.29];
+            assert!(noise.fractal(point, MAX_OCTAVES, 2.0, 0.5).abs() <= 1.1);
+        }
+        // A single octave is the noise itself
+        let point = [1.3, 2.7, 0.4];
+        assert_eq!(noise.fractal(point, 1, 2.0, 0.5), noise.sample(point));
+    }
+
+    #[test]
+    fn heightfield_snapshot() {
+        let extent = [8, 8, 8];
+        let voxels = generate(&params(TerrainMode::Heightfield), extent, bands(3));
+        assert_eq!(
+            voxels,
+            generate(&params(TerrainMode::Heightfield), extent, bands(3))
+        );
+        assert_eq!(
+            slice(&voxels, extent, 0),
+            vec![
+                "........", "........", "..11111.", ".1111111", "11111111", "00000000", "00000000",
+                "00000000",
+            ]
+        );
+        assert_eq!(voxels.len(), 303);
+    }
+
+    #[test]
+    fn density_snapshot() {
+        let extent = [8, 8, 8];
+        let voxels = generate(&params(TerrainMode::Density), extent, bands(3));
+        assert_eq!(
+            voxels,
+            generate(&params(TerrainMode::Density), extent, bands(3))
+        );
+        assert_eq!(
+            slice(&voxels, extent, 3),
+            vec![
+                "........", "........", "........", ".....11.", "1..11.11", "0.000000", "00000000",
+                "00000000",
+            ]
+        );
+        assert_eq!(voxels.len(), 265);
+    }
+
+    #[test]
+    fn top_view_matches_the_terrain() {
+        let extent = [8, 8, 8];
+        for mode in [TerrainMode::Heightfield, TerrainMode::Density].iter() {
+            let params = params(*mode);
+            let voxels = generate(&params, extent, bands(3));
+            let columns = top_view(&params, extent, bands(3), 1);
+            assert_eq!(columns.len(), 64);
+            for (i, column) in columns.iter().enumerate() {
+                let (x, z) = (i % 8, i / 8);
+                let top = voxels
+                    .iter()
+                    .rfind(|(cell, _)| cell[0] == x && cell[2] == z)
+                    .map(|(_, idx)| *idx);
+                assert_eq!(*column, top);
+            }
+            assert_eq!(top_view(&params, extent, bands(3), 3).len(), 9);
+        }
+    }
+
+    #[test]
+    fn height_bands() {
+        assert_eq!(band(0, 6, bands(3)), 0);
+        assert_eq!(band(1, 6, bands(3)), 0);
+        assert_eq!(band(2, 6, bands(3)), 1);
+        assert_eq!(band(5, 6, bands(3)), 2);
+        assert_eq!(band(5, 6, bands(1)), 0);
+    }
+
+    #[test]
+    fn empty_palette_has_no_bands() {
+        assert_eq!(palette_bands(&[]), None);
+        assert_eq!(palette_bands(VoxelManager::new([8; 3]).palette()), None);
+        assert_eq!(palette_bands(&[[1.0; 4]; 3]), Some(bands(3)));
+    }
+}
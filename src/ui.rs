@@ -1,4 +1,4 @@
-use crate::controls::Controls;
+use crate::controls::{Controls, Message};
 use iced_wgpu::{wgpu, Backend, Renderer, Settings, Viewport};
 use iced_winit::{conversion, mouse::Interaction, program, winit, Debug, Size};
 use winit::{
@@ -52,6 +52,10 @@ impl Ui {
         &self.state.program()
     }
 
+    pub fn queue_message(&mut self, message: Message) {
+        self.state.queue_message(message);
+    }
+
     pub fn update_state(&mut self) {
         if !self.state.is_queue_empty() {
             self.state.update(
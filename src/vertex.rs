@@ -1,4 +1,5 @@
 use crate::color::*;
+use crate::material::Material;
 use bytemuck::{Pod, Zeroable};
 
 #[derive(Clone, Copy)]
@@ -13,10 +14,40 @@ pub struct VoxelVertex {
     pub normal: [f32; 3],
 }
 
-#[derive(Clone, Copy)]
+/// Vertex of the exported model, it's not uploaded to the GPU
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshVertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 4],
+    pub material: Material,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct VoxelInstance {
     _offset: [f32; 3],
     _col: [f32; 4],
+    _material: [f32; 4],
+}
+
+impl VoxelInstance {
+    pub fn offset(&self) -> [f32; 3] {
+        self._offset
+    }
+
+    pub fn set_offset(&mut self, offset: [f32; 3]) {
+        self._offset = offset;
+    }
+
+    #[cfg(feature = "debug_ray")]
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self._col = color;
+    }
+
+    /// Transparent instances are drawn after the opaque ones
+    pub fn is_transparent(&self) -> bool {
+        Material::from_array(self._material).is_transparent(&self._col)
+    }
 }
 
 unsafe impl Pod for Vertex {}
@@ -39,10 +70,20 @@ pub fn vertex(pos: [f32; 3], col: [f32; 4]) -> Vertex {
     }
 }
 
-pub fn instance(offset: [f32; 3], col: [f32; 4]) -> VoxelInstance {
+pub fn instance(offset: [f32; 3], col: [f32; 4], material: &Material) -> VoxelInstance {
     VoxelInstance {
         _offset: [offset[0], offset[1], offset[2]],
         _col: col,
+        _material: material.to_array(),
+    }
+}
+
+/// Fills an unused slot of the instance buffer, the shaders discard instances without alpha
+pub fn hidden_instance() -> VoxelInstance {
+    VoxelInstance {
+        _offset: [0.0; 3],
+        _col: [0.0; 4],
+        _material: [0.0; 4],
     }
 }
 
@@ -53,61 +94,81 @@ pub fn voxel_vertex(pos: [f32; 3], normal: [f32; 3]) -> VoxelVertex {
     }
 }
 
-pub fn generate_mesh_vertices(meshes: u16) -> (Vec<Vertex>, Vec<u16>) {
+pub fn mesh_vertex(pos: [f32; 3], normal: [f32; 3], color: [f32; 4]) -> MeshVertex {
+    MeshVertex {
+        pos,
+        normal,
+        color,
+        material: Material::default(),
+    }
+}
+
+pub fn generate_mesh_vertices(dimensions: [u16; 3]) -> (Vec<Vertex>, Vec<u16>) {
     let mut vertex_data = Vec::new();
     let mut index_data: Vec<u16> = Vec::new();
-    let mesh_count = meshes as f32;
+    let [size_x, size_y, size_z] = [
+        dimensions[0] as f32,
+        dimensions[1] as f32,
+        dimensions[2] as f32,
+    ];
 
     // X axis
     vertex_data.push(vertex([0.0, 0.0, 0.0], RED));
     index_data.push((vertex_data.len() - 1) as u16);
-    vertex_data.push(vertex([mesh_count, 0.0, 0.0], RED));
+    vertex_data.push(vertex([size_x, 0.0, 0.0], RED));
     index_data.push((vertex_data.len() - 1) as u16);
 
     // Y axis
     vertex_data.push(vertex([0.0, 0.0, 0.0], GREEN));
     index_data.push((vertex_data.len() - 1) as u16);
-    vertex_data.push(vertex([0.0, mesh_count, 0.0], GREEN));
+    vertex_data.push(vertex([0.0, size_y, 0.0], GREEN));
     index_data.push((vertex_data.len() - 1) as u16);
 
     // Z axis
     vertex_data.push(vertex([0.0, 0.0, 0.0], BLUE));
     index_data.push((vertex_data.len() - 1) as u16);
-    vertex_data.push(vertex([0.0, 0.0, mesh_count], BLUE));
+    vertex_data.push(vertex([0.0, 0.0, size_z], BLUE));
     index_data.push((vertex_data.len() - 1) as u16);
 
-    for i in 1..(meshes + 1) {
+    for i in 1..(dimensions[0] + 1) {
         // back
-        vertex_data.push(white_vertex([0.0, 0.0 + i as f32, 0.0]));
+        vertex_data.push(white_vertex([0.0 + i as f32, 0.0, 0.0]));
         index_data.push((vertex_data.len() - 1) as u16);
-        vertex_data.push(white_vertex([mesh_count, 0.0 + i as f32, 0.0]));
+        vertex_data.push(white_vertex([0.0 + i as f32, size_y, 0.0]));
         index_data.push((vertex_data.len() - 1) as u16);
 
+        // bottom
         vertex_data.push(white_vertex([0.0 + i as f32, 0.0, 0.0]));
         index_data.push((vertex_data.len() - 1) as u16);
-        vertex_data.push(white_vertex([0.0 + i as f32, mesh_count, 0.0]));
+        vertex_data.push(white_vertex([0.0 + i as f32, 0.0, size_z]));
         index_data.push((vertex_data.len() - 1) as u16);
+    }
 
-        // left
+    for i in 1..(dimensions[1] + 1) {
+        // back
         vertex_data.push(white_vertex([0.0, 0.0 + i as f32, 0.0]));
         index_data.push((vertex_data.len() - 1) as u16);
-        vertex_data.push(white_vertex([0.0, 0.0 + i as f32, mesh_count]));
+        vertex_data.push(white_vertex([size_x, 0.0 + i as f32, 0.0]));
         index_data.push((vertex_data.len() - 1) as u16);
 
-        vertex_data.push(white_vertex([0.0, 0.0, 0.0 + i as f32]));
+        // left
+        vertex_data.push(white_vertex([0.0, 0.0 + i as f32, 0.0]));
         index_data.push((vertex_data.len() - 1) as u16);
-        vertex_data.push(white_vertex([0.0, mesh_count, 0.0 + i as f32]));
+        vertex_data.push(white_vertex([0.0, 0.0 + i as f32, size_z]));
         index_data.push((vertex_data.len() - 1) as u16);
+    }
 
-        // bottom
-        vertex_data.push(white_vertex([0.0 + i as f32, 0.0, 0.0]));
+    for i in 1..(dimensions[2] + 1) {
+        // left
+        vertex_data.push(white_vertex([0.0, 0.0, 0.0 + i as f32]));
         index_data.push((vertex_data.len() - 1) as u16);
-        vertex_data.push(white_vertex([0.0 + i as f32, 0.0, mesh_count]));
+        vertex_data.push(white_vertex([0.0, size_y, 0.0 + i as f32]));
         index_data.push((vertex_data.len() - 1) as u16);
 
+        // bottom
         vertex_data.push(white_vertex([0.0, 0.0, 0.0 + i as f32]));
         index_data.push((vertex_data.len() - 1) as u16);
-        vertex_data.push(white_vertex([mesh_count, 0.0, 0.0 + i as f32]));
+        vertex_data.push(white_vertex([size_x, 0.0, 0.0 + i as f32]));
         index_data.push((vertex_data.len() - 1) as u16);
     }
 
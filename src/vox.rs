@@ -0,0 +1,1099 @@
+use crate::scene::{mul_matrix, mul_vector, Rotation, SceneObject, Transform, IDENTITY};
+use crate::voxel_manager::VoxelManager;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+pub const VOX_EXTENSION: &str = "vox";
+const MAGIC: &[u8; 4] = b"VOX ";
+const VERSION: i32 = 150;
+/// Color index 0 means empty, so only 255 colors can be used
+const MAX_COLORS: usize = 255;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The palette MagicaVoxel uses when a file has no RGBA chunk, indexed by color index.
+/// It is the web-safe color cube without black followed by red, green, blue and gray ramps.
+fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+    let mut idx = 1;
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    for r in steps.iter() {
+        for g in steps.iter() {
+            for b in steps.iter() {
+                if idx < 216 {
+                    palette[idx] = [*r, *g, *b, 0xff];
+                    idx += 1;
+                }
+            }
+        }
+    }
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..4 {
+        for value in ramp.iter() {
+            let mut color = [0, 0, 0, 0xff];
+            if channel == 3 {
+                color = [*value, *value, *value, 0xff];
+            } else {
+                color[channel] = *value;
+            }
+            palette[idx] = color;
+            idx += 1;
+        }
+    }
+    palette
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ByteReader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.data.len() - self.pos {
+            return Err(invalid_data("Unexpected end of .vox data".to_string()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Err(invalid_data(format!(
+                "Negative length {} in .vox data",
+                len
+            )));
+        }
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> io::Result<HashMap<String, String>> {
+        let mut dict = HashMap::new();
+        for _ in 0..self.len()? {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+
+    fn chunk(&mut self) -> io::Result<Chunk<'a>> {
+        let mut id = [0; 4];
+        id.copy_from_slice(self.bytes(4)?);
+        let content_len = self.len()?;
+        let children_len = self.len()?;
+        Ok(Chunk {
+            id,
+            content: self.bytes(content_len)?,
+            children: self.bytes(children_len)?,
+        })
+    }
+}
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    content: &'a [u8],
+    children: &'a [u8],
+}
+
+struct Model {
+    size: [i32; 3],
+    voxels: Vec<[u8; 4]>,
+}
+
+enum Node {
+    Transform {
+        child: i32,
+        rotation: Rotation,
+        translation: [i32; 3],
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<i32>,
+    },
+}
+
+/// Decodes the packed `_r` rotation byte of a transform node
+fn parse_rotation(value: &str) -> io::Result<Rotation> {
+    let bits: u8 = value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_data(format!("Invalid rotation {:?}", value)))?;
+    let first = (bits & 0b11) as usize;
+    let second = ((bits >> 2) & 0b11) as usize;
+    if first > 2 || second > 2 || first == second {
+        return Err(invalid_data(format!("Invalid rotation {:?}", value)));
+    }
+    let third = 3 - first - second;
+    let mut rotation = [[0; 3]; 3];
+    for (row, column) in [first, second, third].iter().enumerate() {
+        rotation[row][*column] = if bits & (1 << (4 + row)) != 0 { -1 } else { 1 };
+    }
+    Ok(rotation)
+}
+
+fn parse_translation(value: &str) -> io::Result<[i32; 3]> {
+    let mut translation = [0; 3];
+    let mut parts = value.split_whitespace();
+    for t in translation.iter_mut() {
+        *t = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(|| invalid_data(format!("Invalid translation {:?}", value)))?;
+    }
+    Ok(translation)
+}
+
+/// MagicaVoxel positions a model by its center. The math is done on doubled
+/// coordinates so that even and odd sized models land on whole cells.
+fn world_position(
+    pos: [i32; 3],
+    size: [i32; 3],
+    rotation: &Rotation,
+    translation: [i32; 3],
+) -> [i32; 3] {
+    let doubled = [
+        2 * pos[0] + 1 - size[0],
+        2 * pos[1] + 1 - size[1],
+        2 * pos[2] + 1 - size[2],
+    ];
+    let rotated = mul_vector(rotation, doubled);
+    let rotated_size = mul_vector(rotation, size);
+    let mut world = [0; 3];
+    for i in 0..3 {
+        world[i] = translation[i] + (rotated[i] + rotated_size[i].abs() % 2 - 1) / 2;
+    }
+    world
+}
+
+struct Scene {
+    models: Vec<Model>,
+    nodes: HashMap<i32, Node>,
+    palette: [[u8; 4]; 256],
+}
+
+impl Scene {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let mut reader = ByteReader::new(data);
+        if reader.bytes(4)? != MAGIC {
+            return Err(invalid_data("Not a MagicaVoxel .vox file".to_string()));
+        }
+        let _version = reader.i32()?;
+        let main = reader.chunk()?;
+        if &main.id != b"MAIN" {
+            return Err(invalid_data("Missing MAIN chunk".to_string()));
+        }
+
+        let mut scene = Scene {
+            models: Vec::new(),
+            nodes: HashMap::new(),
+            palette: default_palette(),
+        };
+        let mut size = None;
+        let mut children = ByteReader::new(main.children);
+        while !children.is_empty() {
+            let chunk = children.chunk()?;
+            let mut content = ByteReader::new(chunk.content);
+            match &chunk.id {
+                b"SIZE" => size = Some([content.i32()?, content.i32()?, content.i32()?]),
+                b"XYZI" => {
+                    let size = size
+                        .take()
+                        .ok_or_else(|| invalid_data("XYZI chunk without SIZE".to_string()))?;
+                    let mut voxels = Vec::new();
+                    for _ in 0..content.len()? {
+                        voxels.push([content.u8()?, content.u8()?, content.u8()?, content.u8()?]);
+                    }
+                    scene.models.push(Model { size, voxels });
+                }
+                b"RGBA" => {
+                    for idx in 1..256 {
+                        let color = content.bytes(4)?;
+                        scene.palette[idx] = [color[0], color[1], color[2], color[3]];
+                    }
+                }
+                b"nTRN" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let mut rotation = IDENTITY;
+                    let mut translation = [0; 3];
+                    // Only the first frame is used, animations are not supported
+                    if content.len()? > 0 {
+                        let frame = content.dict()?;
+                        if let Some(value) = frame.get("_r") {
+                            rotation = parse_rotation(value)?;
+                        }
+                        if let Some(value) = frame.get("_t") {
+                            translation = parse_translation(value)?;
+                        }
+                    }
+                    scene.nodes.insert(
+                        id,
+                        Node::Transform {
+                            child,
+                            rotation,
+                            translation,
+                        },
+                    );
+                }
+                b"nGRP" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let mut children = Vec::new();
+                    for _ in 0..content.len()? {
+                        children.push(content.i32()?);
+                    }
+                    scene.nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let mut models = Vec::new();
+                    for _ in 0..content.len()? {
+                        models.push(content.i32()?);
+                        let _model_attributes = content.dict()?;
+                    }
+                    scene.nodes.insert(id, Node::Shape { models });
+                }
+                // PACK, materials, layers, cameras, etc. don't affect the voxel data
+                _ => {}
+            }
+        }
+        Ok(scene)
+    }
+
+    fn place_model<'a>(
+        &'a self,
+        model_id: i32,
+        rotation: &Rotation,
+        translation: [i32; 3],
+        placed: &mut Vec<Placed<'a>>,
+    ) -> io::Result<()> {
+        let model = self
+            .models
+            .get(model_id as usize)
+            .ok_or_else(|| invalid_data(format!("Reference to missing model {}", model_id)))?;
+        placed.push(Placed {
+            model,
+            rotation: *rotation,
+            translation,
+        });
+        Ok(())
+    }
+
+    fn walk<'a>(
+        &'a self,
+        node_id: i32,
+        rotation: &Rotation,
+        translation: [i32; 3],
+        depth: usize,
+        placed: &mut Vec<Placed<'a>>,
+    ) -> io::Result<()> {
+        // Guards against cycles in malformed scene graphs
+        if depth > self.nodes.len() {
+            return Err(invalid_data("Cycle in the .vox scene graph".to_string()));
+        }
+        match self.nodes.get(&node_id) {
+            Some(Node::Transform {
+                child,
+                rotation: local_rotation,
+                translation: local_translation,
+            }) => {
+                let offset = mul_vector(rotation, *local_translation);
+                let translation = [
+                    translation[0] + offset[0],
+                    translation[1] + offset[1],
+                    translation[2] + offset[2],
+                ];
+                let rotation = mul_matrix(rotation, local_rotation);
+                self.walk(*child, &rotation, translation, depth + 1, placed)
+            }
+            Some(Node::Group { children }) => {
+                for child in children.iter() {
+                    self.walk(*child, rotation, translation, depth + 1, placed)?;
+                }
+                Ok(())
+            }
+            Some(Node::Shape { models }) => {
+                for model_id in models.iter() {
+                    self.place_model(*model_id, rotation, translation, placed)?;
+                }
+                Ok(())
+            }
+            None => Err(invalid_data(format!(
+                "Reference to missing node {}",
+                node_id
+            ))),
+        }
+    }
+
+    /// Returns every model with its accumulated world transform
+    fn placed_models(&self) -> io::Result<Vec<Placed<'_>>> {
+        let mut placed = Vec::new();
+        if self.nodes.is_empty() {
+            for model_id in 0..self.models.len() {
+                self.place_model(model_id as i32, &IDENTITY, [0; 3], &mut placed)?;
+            }
+        } else {
+            self.walk(0, &IDENTITY, [0; 3], 0, &mut placed)?;
+        }
+        Ok(placed)
+    }
+}
+
+struct Placed<'a> {
+    model: &'a Model,
+    rotation: Rotation,
+    translation: [i32; 3],
+}
+
+impl<'a> Placed<'a> {
+    fn world_position(&self, pos: [i32; 3]) -> [i32; 3] {
+        world_position(pos, self.model.size, &self.rotation, self.translation)
+    }
+}
+
+/// MagicaVoxel is Z-up, the editor grid is Y-up
+fn to_grid_axes(pos: [i32; 3]) -> [i32; 3] {
+    [pos[0], pos[2], -pos[1]]
+}
+
+pub fn color_from_rgba(rgba: [u8; 4]) -> [f32; 4] {
+    [
+        rgba[0] as f32 / 255.0,
+        rgba[1] as f32 / 255.0,
+        rgba[2] as f32 / 255.0,
+        rgba[3] as f32 / 255.0,
+    ]
+}
+
+/// Parses a .vox file and returns its content in a voxel grid of the given extent
+pub fn import(data: &[u8], extent: [usize; 3]) -> io::Result<VoxelManager> {
+    let scene = Scene::parse(data)?;
+    let placed = scene.placed_models()?;
+
+    // The bounds are computed from the model boxes, so empty space inside a model is kept
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for p in placed.iter() {
+        let size = p.model.size;
+        for corner in [[0, 0, 0], [size[0] - 1, size[1] - 1, size[2] - 1]].iter() {
+            let pos = to_grid_axes(p.world_position(*corner));
+            for i in 0..3 {
+                min[i] = min[i].min(pos[i]);
+                max[i] = max[i].max(pos[i]);
+            }
+        }
+    }
+
+    let mut voxel_manager = VoxelManager::new(extent);
+    if placed.is_empty() {
+        return Ok(voxel_manager);
+    }
+    let dimensions = [
+        max[0] - min[0] + 1,
+        max[1] - min[1] + 1,
+        max[2] - min[2] + 1,
+    ];
+    if (0..3).any(|i| dimensions[i] as usize > extent[i]) {
+        return Err(invalid_data(format!(
+            "The model size {}x{}x{} does not fit into the {}x{}x{} grid",
+            dimensions[0], dimensions[1], dimensions[2], extent[0], extent[1], extent[2]
+        )));
+    }
+
+    // The used colors are added to the document palette in the order of the file's palette
+    let mut used: Vec<u8> = placed
+        .iter()
+        .flat_map(|p| p.model.voxels.iter().map(|voxel| voxel[3]))
+        .collect();
+    used.sort_unstable();
+    used.dedup();
+    let mut indices = [0; 256];
+    for idx in used {
+        indices[idx as usize] =
+            voxel_manager.color_index(color_from_rgba(scene.palette[idx as usize]));
+    }
+
+    for p in placed.iter() {
+        for voxel in p.model.voxels.iter() {
+            let local = [voxel[0] as i32, voxel[1] as i32, voxel[2] as i32];
+            if (0..3).any(|i| local[i] >= p.model.size[i]) {
+                return Err(invalid_data(format!(
+                    "Voxel {:?} is outside of its model",
+                    local
+                )));
+            }
+            let pos = to_grid_axes(p.world_position(local));
+            voxel_manager.set_index(
+                (pos[0] - min[0]) as usize,
+                (pos[1] - min[1]) as usize,
+                (pos[2] - min[2]) as usize,
+                indices[voxel[3] as usize],
+            );
+        }
+    }
+    Ok(voxel_manager)
+}
+
+pub fn import_file<R: Read>(reader: &mut R, extent: [usize; 3]) -> io::Result<VoxelManager> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    import(&data, extent)
+}
+
+pub fn color_to_rgba(color: [f32; 4]) -> [u8; 4] {
+    let mut rgba = [0; 4];
+    for (c, value) in rgba.iter_mut().zip(color.iter()) {
+        *c = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    rgba
+}
+
+fn color_distance(a: [u8; 4], b: [u8; 4]) -> i32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).pow(2))
+        .sum()
+}
+
+/// Builds a palette of at most 255 colors and maps every used color to its palette index.
+/// If there are more colors the most frequent ones are kept and the rest are
+/// replaced with their nearest match.
+fn quantize(colors: &[[u8; 4]]) -> (Vec<[u8; 4]>, HashMap<[u8; 4], u8>) {
+    let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+    for color in colors.iter() {
+        *counts.entry(*color).or_insert(0) += 1;
+    }
+    let mut distinct: Vec<([u8; 4], usize)> = counts.into_iter().collect();
+    distinct.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let palette: Vec<[u8; 4]> = distinct.iter().take(MAX_COLORS).map(|c| c.0).collect();
+    let mut indices = HashMap::new();
+    for (color, _) in distinct.iter() {
+        let nearest = palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, p)| color_distance(*color, **p))
+            .map(|(idx, _)| idx)
+            .unwrap();
+        indices.insert(*color, nearest as u8 + 1);
+    }
+    (palette, indices)
+}
+
+/// MagicaVoxel is Z-up, the editor grid is Y-up
+fn to_vox_axes(pos: [usize; 3], depth: usize) -> [u8; 4] {
+    [pos[0] as u8, (depth - 1 - pos[2]) as u8, pos[1] as u8, 0]
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    bytes.extend_from_slice(content);
+    bytes.extend_from_slice(children);
+}
+
+fn write_i32s(bytes: &mut Vec<u8>, values: &[i32]) {
+    for value in values.iter() {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn write_dict(bytes: &mut Vec<u8>, pairs: &[(&str, String)]) {
+    write_i32s(bytes, &[pairs.len() as i32]);
+    for (key, value) in pairs.iter() {
+        for string in [key, value.as_str()].iter() {
+            write_i32s(bytes, &[string.len() as i32]);
+            bytes.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+/// Writes a transform node with a single frame
+fn write_transform_node(
+    bytes: &mut Vec<u8>,
+    id: i32,
+    child: i32,
+    attributes: &[(&str, String)],
+    frame: &[(&str, String)],
+) {
+    let mut content = Vec::new();
+    write_i32s(&mut content, &[id]);
+    write_dict(&mut content, attributes);
+    write_i32s(&mut content, &[child, -1, 0, 1]);
+    write_dict(&mut content, frame);
+    write_chunk(bytes, b"nTRN", &content, &[]);
+}
+
+/// Encodes a rotation as the packed `_r` byte, the inverse of `parse_rotation`
+fn encode_rotation(rotation: &Rotation) -> u8 {
+    let mut bits = 0;
+    for (row, values) in rotation.iter().enumerate() {
+        let column = values.iter().position(|v| *v != 0).unwrap();
+        if row < 2 {
+            bits |= (column as u8) << (2 * row);
+        }
+        if values[column] < 0 {
+            bits |= 1 << (4 + row);
+        }
+    }
+    bits
+}
+
+/// The rotation and translation of the transform node which places an exported model
+/// where the transform places the object in the Y-up scene
+fn vox_transform(transform: &Transform, extent: [usize; 3]) -> (Rotation, [i32; 3]) {
+    // Maps the Y-up axes to the Z-up axes, like `to_vox_axes` does for the cells
+    const TO_VOX: Rotation = [[1, 0, 0], [0, 0, -1], [0, 1, 0]];
+    const TO_GRID: Rotation = [[1, 0, 0], [0, 0, 1], [0, -1, 0]];
+    let rotation = mul_matrix(&mul_matrix(&TO_VOX, &transform.rotation), &TO_GRID);
+
+    // The placement is affine, so it's enough to match the position of a single voxel
+    let size = [extent[0] as i32, extent[2] as i32, extent[1] as i32];
+    let local = to_vox_axes([0; 3], extent[2]);
+    let placed = world_position(
+        [local[0] as i32, local[1] as i32, local[2] as i32],
+        size,
+        &rotation,
+        [0; 3],
+    );
+    let cell = transform.cell([0; 3]);
+    let expected = [cell[0], -cell[2], cell[1]];
+    let mut translation = [0; 3];
+    for i in 0..3 {
+        translation[i] = expected[i] - placed[i];
+    }
+    (rotation, translation)
+}
+
+/// Writes the SIZE and XYZI chunks of every grid and returns the palette of the file.
+/// The document palettes are written as they are if they fit into the .vox palette
+/// together, otherwise the used colors are quantized.
+fn write_models(children: &mut Vec<u8>, grids: &[&VoxelManager]) -> io::Result<Vec<[u8; 4]>> {
+    for grid in grids.iter() {
+        let extent = grid.extent();
+        if extent.iter().any(|e| *e > 256) {
+            return Err(invalid_data(format!(
+                "The {}x{}x{} grid is larger than the 256 cells .vox supports",
+                extent[0], extent[1], extent[2]
+            )));
+        }
+    }
+    let voxels: Vec<Vec<([usize; 3], u16)>> =
+        grids.iter().map(|grid| grid.indexed_voxels()).collect();
+    let palette_len: usize = grids.iter().map(|grid| grid.palette().len()).sum();
+    let (palette, vox_indices): (Vec<[u8; 4]>, Vec<Vec<u8>>) = if palette_len <= MAX_COLORS {
+        let palette = grids
+            .iter()
+            .flat_map(|grid| grid.palette().iter().map(|color| color_to_rgba(*color)))
+            .collect();
+        // Counted in usize, the last palette ends at index 255
+        let mut first = 1;
+        let mut vox_indices = Vec::new();
+        for (grid, voxels) in grids.iter().zip(voxels.iter()) {
+            vox_indices.push(
+                voxels
+                    .iter()
+                    .map(|v| (v.1 as usize + first) as u8)
+                    .collect(),
+            );
+            first += grid.palette().len();
+        }
+        (palette, vox_indices)
+    } else {
+        let colors: Vec<Vec<[u8; 4]>> = grids
+            .iter()
+            .zip(voxels.iter())
+            .map(|(grid, voxels)| {
+                voxels
+                    .iter()
+                    .map(|v| color_to_rgba(grid.palette()[v.1 as usize]))
+                    .collect()
+            })
+            .collect();
+        let (palette, indices) = quantize(&colors.concat());
+        let vox_indices = colors
+            .iter()
+            .map(|colors| colors.iter().map(|color| indices[color]).collect())
+            .collect();
+        (palette, vox_indices)
+    };
+
+    for ((grid, voxels), vox_indices) in grids.iter().zip(voxels.iter()).zip(vox_indices.iter()) {
+        let extent = grid.extent();
+        let mut size = Vec::new();
+        for e in [extent[0], extent[2], extent[1]].iter() {
+            size.extend_from_slice(&(*e as i32).to_le_bytes());
+        }
+
+        let mut xyzi = (voxels.len() as i32).to_le_bytes().to_vec();
+        for ((pos, _), idx) in voxels.iter().zip(vox_indices.iter()) {
+            let mut voxel = to_vox_axes(*pos, extent[2]);
+            voxel[3] = *idx;
+            xyzi.extend_from_slice(&voxel);
+        }
+
+        write_chunk(children, b"SIZE", &size, &[]);
+        write_chunk(children, b"XYZI", &xyzi, &[]);
+    }
+    Ok(palette)
+}
+
+/// Wraps the chunks into the MAIN chunk after the palette
+fn write_file(mut children: Vec<u8>, palette: &[[u8; 4]]) -> Vec<u8> {
+    let mut rgba = Vec::new();
+    for idx in 0..256 {
+        rgba.extend_from_slice(&palette.get(idx).cloned().unwrap_or([0; 4]));
+    }
+    write_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    write_chunk(&mut bytes, b"MAIN", &[], &children);
+    bytes
+}
+
+/// Encodes the voxel grid as a single model .vox file. The document palette is written as is
+/// if it fits into the .vox palette, otherwise the used colors are quantized.
+pub fn export(voxel_manager: &VoxelManager) -> io::Result<Vec<u8>> {
+    let mut children = Vec::new();
+    let palette = write_models(&mut children, &[voxel_manager])?;
+    Ok(write_file(children, &palette))
+}
+
+/// Encodes every object as a separate model. The scene graph has a group with a named
+/// transform node for every object, which places the model like the object's transform.
+pub fn export_objects(objects: &[SceneObject]) -> io::Result<Vec<u8>> {
+    let grids: Vec<&VoxelManager> = objects.iter().map(|o| &o.voxel_manager).collect();
+    let mut children = Vec::new();
+    let palette = write_models(&mut children, &grids)?;
+
+    write_transform_node(&mut children, 0, 1, &[], &[]);
+    let object_nodes: Vec<i32> = (0..objects.len() as i32).map(|idx| 2 + 2 * idx).collect();
+    let mut group = Vec::new();
+    write_i32s(&mut group, &[1]);
+    write_dict(&mut group, &[]);
+    write_i32s(&mut group, &[object_nodes.len() as i32]);
+    write_i32s(&mut group, &object_nodes);
+    write_chunk(&mut children, b"nGRP", &group, &[]);
+
+    for (idx, (object, node)) in objects.iter().zip(object_nodes.iter()).enumerate() {
+        let (rotation, translation) =
+            vox_transform(&object.transform, object.voxel_manager.extent());
+        write_transform_node(
+            &mut children,
+            *node,
+            node + 1,
+            &[("_name", object.name.clone())],
+            &[
+                ("_r", encode_rotation(&rotation).to_string()),
+                (
+                    "_t",
+                    format!("{} {} {}", translation[0], translation[1], translation[2]),
+                ),
+            ],
+        );
+        let mut shape = Vec::new();
+        write_i32s(&mut shape, &[node + 1]);
+        write_dict(&mut shape, &[]);
+        write_i32s(&mut shape, &[1, idx as i32]);
+        write_dict(&mut shape, &[]);
+        write_chunk(&mut children, b"nSHP", &shape, &[]);
+    }
+    Ok(write_file(children, &palette))
+}
+
+pub fn export_file<W: Write>(writer: &mut W, voxel_manager: &VoxelManager) -> io::Result<()> {
+    writer.write_all(&export(voxel_manager)?)?;
+    writer.flush()
+}
+
+pub fn export_objects_file<W: Write>(writer: &mut W, objects: &[SceneObject]) -> io::Result<()> {
+    writer.write_all(&export_objects(objects)?)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes.extend_from_slice(children);
+        bytes
+    }
+
+    fn i32s(values: &[i32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = i32s(&[value.len() as i32]);
+        bytes.extend_from_slice(value.as_bytes());
+        bytes
+    }
+
+    fn dict(pairs: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = i32s(&[pairs.len() as i32]);
+        for (key, value) in pairs.iter() {
+            bytes.extend(string(key));
+            bytes.extend(string(value));
+        }
+        bytes
+    }
+
+    fn model(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut bytes = chunk(b"SIZE", &i32s(&size), &[]);
+        let mut xyzi = i32s(&[voxels.len() as i32]);
+        for v in voxels.iter() {
+            xyzi.extend_from_slice(v);
+        }
+        bytes.extend(chunk(b"XYZI", &xyzi, &[]));
+        bytes
+    }
+
+    fn file(children: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(i32s(&[150]));
+        bytes.extend(chunk(b"MAIN", &[], children));
+        bytes
+    }
+
+    fn transform(id: i32, child: i32, frame: &[(&str, &str)]) -> Vec<u8> {
+        let mut content = i32s(&[id]);
+        content.extend(dict(&[]));
+        content.extend(i32s(&[child, -1, 0, 1]));
+        content.extend(dict(frame));
+        chunk(b"nTRN", &content, &[])
+    }
+
+    fn shape(id: i32, model_id: i32) -> Vec<u8> {
+        let mut content = i32s(&[id]);
+        content.extend(dict(&[]));
+        content.extend(i32s(&[1, model_id]));
+        content.extend(dict(&[]));
+        chunk(b"nSHP", &content, &[])
+    }
+
+    fn group(id: i32, children: &[i32]) -> Vec<u8> {
+        let mut content = i32s(&[id]);
+        content.extend(dict(&[]));
+        content.extend(i32s(&[children.len() as i32]));
+        content.extend(i32s(children));
+        chunk(b"nGRP", &content, &[])
+    }
+
+    fn rgba(colors: &[[u8; 4]]) -> Vec<u8> {
+        let mut content = Vec::new();
+        for idx in 0..256 {
+            content.extend_from_slice(&colors.get(idx).cloned().unwrap_or([0; 4]));
+        }
+        chunk(b"RGBA", &content, &[])
+    }
+
+    #[test]
+    fn default_palette_matches_magicavoxel() {
+        let palette = default_palette();
+        assert_eq!(palette[0], [0, 0, 0, 0]);
+        assert_eq!(palette[1], [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(palette[2], [0xff, 0xff, 0xcc, 0xff]);
+        assert_eq!(palette[215], [0x00, 0x00, 0x33, 0xff]);
+        assert_eq!(palette[216], [0xee, 0x00, 0x00, 0xff]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    #[test]
+    fn single_model_with_palette() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let mut children = model([2, 3, 4], &[[0, 0, 0, 1], [1, 2, 3, 2]]);
+        children.extend(rgba(&[red, blue]));
+        let voxel_manager = import(&file(&children), [8; 3]).unwrap();
+
+        // (x, y, z) in .vox becomes (x, z, depth - 1 - y) in the grid
+        assert_eq!(
+            voxel_manager.voxels(),
+            vec![
+                ([0, 0, 2], color_from_rgba(red)),
+                ([1, 3, 0], color_from_rgba(blue)),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_palette_uses_default() {
+        let voxel_manager = import(&file(&model([1, 1, 1], &[[0, 0, 0, 1]])), [4; 3]).unwrap();
+        assert_eq!(voxel_manager.get(0, 0, 0), Some([1.0; 4]));
+    }
+
+    #[test]
+    fn multi_model_scene() {
+        let mut children = model([1, 1, 1], &[[0, 0, 0, 1]]);
+        children.extend(model([1, 1, 1], &[[0, 0, 0, 2]]));
+        children.extend(transform(0, 1, &[]));
+        children.extend(group(1, &[2, 4]));
+        children.extend(transform(2, 3, &[("_t", "0 0 0")]));
+        children.extend(shape(3, 0));
+        children.extend(transform(4, 5, &[("_t", "3 0 2")]));
+        children.extend(shape(5, 1));
+        let voxel_manager = import(&file(&children), [8; 3]).unwrap();
+
+        let palette = default_palette();
+        assert_eq!(
+            voxel_manager.voxels(),
+            vec![
+                ([0, 0, 0], color_from_rgba(palette[1])),
+                ([3, 2, 0], color_from_rgba(palette[2])),
+            ]
+        );
+    }
+
+    #[test]
+    fn rotated_model() {
+        // _r = 1: rows pick columns 1, 0, 2, which swaps the x and y axes
+        let mut children = model([3, 1, 1], &[[0, 0, 0, 1], [2, 0, 0, 2]]);
+        children.extend(transform(0, 1, &[("_r", "1")]));
+        children.extend(shape(1, 0));
+        let voxel_manager = import(&file(&children), [4; 3]).unwrap();
+
+        let positions: Vec<[usize; 3]> = voxel_manager.voxels().iter().map(|v| v.0).collect();
+        assert_eq!(positions, vec![[0, 0, 0], [0, 0, 2]]);
+    }
+
+    #[test]
+    fn model_too_large() {
+        let err = import(&file(&model([40, 1, 1], &[[39, 0, 0, 1]])), [32; 3])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("does not fit"));
+    }
+
+    fn fill(voxel_manager: &mut VoxelManager, pos: [usize; 3], rgba: [u8; 4]) {
+        voxel_manager.set(pos[0], pos[1], pos[2], color_from_rgba(rgba));
+    }
+
+    #[test]
+    fn export_then_import() {
+        let mut voxel_manager = VoxelManager::new([4; 3]);
+        fill(&mut voxel_manager, [0, 0, 0], [255, 0, 0, 255]);
+        fill(&mut voxel_manager, [3, 1, 0], [0, 128, 0, 255]);
+        fill(&mut voxel_manager, [1, 3, 2], [255, 0, 0, 255]);
+        fill(&mut voxel_manager, [3, 3, 3], [10, 20, 30, 40]);
+
+        let bytes = export(&voxel_manager).unwrap();
+        assert_eq!(import(&bytes, [4; 3]).unwrap(), voxel_manager);
+    }
+
+    #[test]
+    fn import_then_export() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let mut children = model([3, 3, 3], &[[0, 0, 0, 1], [1, 2, 0, 2], [2, 2, 2, 1]]);
+        children.extend(rgba(&[red, blue]));
+        let voxel_manager = import(&file(&children), [3; 3]).unwrap();
+
+        let exported = Scene::parse(&export(&voxel_manager).unwrap()).unwrap();
+        assert_eq!(exported.models.len(), 1);
+        assert_eq!(exported.models[0].size, [3, 3, 3]);
+        let mut voxels: Vec<([u8; 3], [u8; 4])> = exported.models[0]
+            .voxels
+            .iter()
+            .map(|v| ([v[0], v[1], v[2]], exported.palette[v[3] as usize]))
+            .collect();
+        voxels.sort();
+        assert_eq!(
+            voxels,
+            vec![([0, 0, 0], red), ([1, 2, 0], blue), ([2, 2, 2], red)]
+        );
+    }
+
+    #[test]
+    fn palette_mapping() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let mut children = model([2, 2, 2], &[[0, 0, 0, 7], [1, 0, 0, 3], [1, 1, 1, 7]]);
+        let mut colors = [[0, 0, 0, 255]; 7];
+        colors[2] = blue;
+        colors[6] = red;
+        children.extend(rgba(&colors));
+        let mut voxel_manager = import(&file(&children), [2; 3]).unwrap();
+        // Only the used colors are added, ordered by their index in the file
+        assert_eq!(
+            voxel_manager.palette(),
+            &[color_from_rgba(blue), color_from_rgba(red)]
+        );
+        assert_eq!(voxel_manager.get_index(0, 0, 1), Some(1));
+
+        // The document palette is exported as is, with unused entries
+        voxel_manager.add_palette_color([0.0, 1.0, 0.0, 1.0]);
+        let scene = Scene::parse(&export(&voxel_manager).unwrap()).unwrap();
+        assert_eq!(scene.palette[1], blue);
+        assert_eq!(scene.palette[2], red);
+        assert_eq!(scene.palette[3], [0, 255, 0, 255]);
+        let mut used: Vec<u8> = scene.models[0].voxels.iter().map(|v| v[3]).collect();
+        used.sort_unstable();
+        assert_eq!(used, vec![1, 2, 2]);
+    }
+
+    #[test]
+    fn full_palette() {
+        let mut voxel_manager = VoxelManager::new([2; 3]);
+        let palette: Vec<[u8; 4]> = (0..MAX_COLORS).map(|i| [i as u8, 0, 0, 255]).collect();
+        voxel_manager.set_palette(palette.iter().map(|c| color_from_rgba(*c)).collect());
+        voxel_manager.set_index(0, 0, 0, 0);
+        voxel_manager.set_index(1, 1, 1, MAX_COLORS as u16 - 1);
+
+        let scene = Scene::parse(&export(&voxel_manager).unwrap()).unwrap();
+        assert_eq!(&scene.palette[1..256], &palette[..]);
+        let mut used: Vec<u8> = scene.models[0].voxels.iter().map(|v| v[3]).collect();
+        used.sort_unstable();
+        assert_eq!(used, vec![1, 255]);
+    }
+
+    #[test]
+    fn non_cubic_grid() {
+        let mut voxel_manager = VoxelManager::new([5, 2, 3]);
+        fill(&mut voxel_manager, [0, 0, 0], [255, 0, 0, 255]);
+        fill(&mut voxel_manager, [4, 1, 2], [0, 0, 255, 255]);
+
+        let bytes = export(&voxel_manager).unwrap();
+        let scene = Scene::parse(&bytes).unwrap();
+        // The Y-up height of the grid is the Z size in the file
+        assert_eq!(scene.models[0].size, [5, 3, 2]);
+        assert_eq!(import(&bytes, [5, 2, 3]).unwrap(), voxel_manager);
+        assert!(import(&bytes, [5, 3, 2]).is_err());
+    }
+
+    #[test]
+    fn quantize_to_nearest() {
+        let mut voxel_manager = VoxelManager::new([20; 3]);
+        // 300 distinct grays, the darkest ones are more frequent so they make the palette
+        for i in 0..300 {
+            let gray = (i % 256) as u8;
+            let alpha = if i < 256 { 255 } else { 254 };
+            fill(
+                &mut voxel_manager,
+                [i % 20, i / 20, 0],
+                [gray, gray, gray, alpha],
+            );
+        }
+        for i in 0..255 {
+            fill(
+                &mut voxel_manager,
+                [i % 20, i / 20, 1],
+                [i as u8, i as u8, i as u8, 255],
+            );
+        }
+
+        let scene = Scene::parse(&export(&voxel_manager).unwrap()).unwrap();
+        let used: std::collections::HashSet<u8> =
+            scene.models[0].voxels.iter().map(|v| v[3]).collect();
+        assert!(used.len() <= MAX_COLORS);
+        assert!(!used.contains(&0));
+
+        let imported = import(&export(&voxel_manager).unwrap(), [20; 3]).unwrap();
+        assert_eq!(imported.voxels().len(), voxel_manager.voxels().len());
+        // The lightest gray didn't fit into the palette, its nearest match is one step darker
+        assert_eq!(
+            imported.get(255 % 20, 255 / 20, 0),
+            Some(color_from_rgba([254, 254, 254, 255]))
+        );
+        assert_eq!(imported.get(4, 0, 1), Some(color_from_rgba([4, 4, 4, 255])));
+    }
+
+    #[test]
+    fn rotation_encoding() {
+        let mut rotations = vec![IDENTITY];
+        let mut next = 0;
+        while next < rotations.len() {
+            for axis in 0..3 {
+                let rotation = mul_matrix(&crate::scene::quarter_turn(axis), &rotations[next]);
+                if !rotations.contains(&rotation) {
+                    rotations.push(rotation);
+                }
+            }
+            next += 1;
+        }
+        assert_eq!(rotations.len(), 24);
+        for rotation in rotations.iter() {
+            let encoded = encode_rotation(rotation).to_string();
+            assert_eq!(parse_rotation(&encoded).unwrap(), *rotation);
+        }
+    }
+
+    #[test]
+    fn separate_objects() {
+        let mut first = VoxelManager::new([3, 2, 4]);
+        fill(&mut first, [0, 0, 0], [255, 0, 0, 255]);
+        fill(&mut first, [2, 1, 0], [0, 0, 255, 255]);
+        fill(&mut first, [1, 0, 3], [255, 0, 0, 255]);
+        let mut second = VoxelManager::new([2, 5, 3]);
+        fill(&mut second, [0, 4, 0], [0, 255, 0, 255]);
+        fill(&mut second, [1, 0, 2], [255, 0, 0, 255]);
+        let mut scene = crate::scene::Scene::new(first);
+        scene.add_object(second.clone());
+        scene.add_object(second);
+        for (idx, axis) in [(0, 1), (1, 0), (2, 2)].iter() {
+            let mut transform = scene.objects()[*idx].transform;
+            transform.rotate(*axis, scene.objects()[*idx].voxel_manager.extent());
+            transform.translation[1] += *idx as i32;
+            scene.set_transform(*idx, transform);
+        }
+
+        let bytes = export_objects(scene.objects()).unwrap();
+        let parsed = Scene::parse(&bytes).unwrap();
+        assert_eq!(parsed.models.len(), 3);
+        assert_eq!(parsed.nodes.len(), 8);
+        let merged = scene.merged();
+        let imported = import(&bytes, merged.extent()).unwrap();
+        assert_eq!(imported.voxels(), merged.voxels());
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut bytes = file(&model([2, 2, 2], &[[0, 0, 0, 1]]));
+        bytes.truncate(bytes.len() - 2);
+        assert!(import(&bytes, [8; 3]).is_err());
+        assert!(import(b"RIFF", [8; 3]).is_err());
+    }
+}
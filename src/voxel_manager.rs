@@ -1,11 +1,115 @@
-use crate::geometry::{BoundingBox, Ray};
-use crate::vertex::{instance, VoxelInstance, VoxelVertex};
+use crate::geometry::{BoundingBox, Ray, EPSYLON};
+use crate::material::Material;
+use crate::scene::{mul_matrix, mul_vector, quarter_turn, Rotation, Transform, IDENTITY};
+use crate::vertex::{instance, mesh_vertex, MeshVertex, VoxelInstance};
 use cgmath::Vector3;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::iter;
+use std::mem;
 
-#[derive(Copy, Clone, Default)]
+/// Outward directions of the faces in the order `BoundingBox::voxel_vertices` emits them
+const FACE_DIRECTIONS: [[i32; 3]; 6] = [
+    [0, 0, -1],
+    [0, -1, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [-1, 0, 0],
+    [0, 0, 1],
+];
+
+/// Collects triangles and merges the vertices which are identical in every attribute
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<MeshVertex>,
+    indices: Vec<u32>,
+    /// Triangles of the transparent faces, they are drawn after the opaque ones
+    transparent_indices: Vec<u32>,
+    lookup: HashMap<[u32; 14], u32>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, vertex: MeshVertex) -> u32 {
+        let mut key = [0; 14];
+        let material = vertex.material.to_array();
+        let attributes = vertex
+            .pos
+            .iter()
+            .chain(vertex.normal.iter())
+            .chain(vertex.color.iter())
+            .chain(material.iter());
+        for (k, value) in key.iter_mut().zip(attributes) {
+            // Avoids -0.0 and 0.0 ending up as different vertices
+            *k = (value + 0.0).to_bits();
+        }
+        let vertices = &mut self.vertices;
+        *self.lookup.entry(key).or_insert_with(|| {
+            vertices.push(vertex);
+            (vertices.len() - 1) as u32
+        })
+    }
+
+    /// Adds a quad with counter-clockwise corners as two triangles
+    fn quad(&mut self, corners: [MeshVertex; 4]) {
+        let idx: Vec<u32> = corners.iter().map(|c| self.vertex(*c)).collect();
+        let indices = if corners[0].material.is_transparent(&corners[0].color) {
+            &mut self.transparent_indices
+        } else {
+            &mut self.indices
+        };
+        indices.extend_from_slice(&[idx[0], idx[1], idx[2], idx[2], idx[3], idx[0]]);
+    }
+
+    fn finish(mut self) -> (Vec<MeshVertex>, Vec<u32>) {
+        self.indices.append(&mut self.transparent_indices);
+        (self.vertices, self.indices)
+    }
+}
+
+/// Which side of the canvas keeps its place on an axis when the canvas is resized
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Anchor {
+    Start,
+    Center,
+    End,
+}
+
+impl Anchor {
+    pub fn next(self) -> Self {
+        match self {
+            Anchor::Start => Anchor::Center,
+            Anchor::Center => Anchor::End,
+            Anchor::End => Anchor::Start,
+        }
+    }
+
+    /// How much the voxels move on an axis when it's resized from `old` to `new` cells
+    pub fn offset(self, old: usize, new: usize) -> i32 {
+        let growth = new as i32 - old as i32;
+        match self {
+            Anchor::Start => 0,
+            Anchor::Center => growth / 2,
+            Anchor::End => growth,
+        }
+    }
+}
+
+/// Result of casting a ray into the voxel grid
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RayHit {
+    /// The first filled voxel along the ray
+    pub voxel: [usize; 3],
+    /// Normal of the face the ray entered the voxel through
+    pub normal: [i32; 3],
+    /// The cell in front of the hit face, if it's inside the grid
+    pub empty: Option<[usize; 3]>,
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
 struct CubeDescriptor {
-    color: Option<[f32; 4]>,
-    neighbours: usize,
+    /// Index of the color in the palette
+    color: Option<u16>,
+    /// Number of filled neighbours, only maintained for filled cells
+    neighbours: u8,
 }
 
 impl CubeDescriptor {
@@ -25,20 +129,1032 @@ impl CubeDescriptor {
     }
 }
 
+/// Edge length of the cubic chunks the grid is divided into
+const CHUNK_SIZE: usize = 16;
+const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// A block of cells which is only allocated while it has a filled voxel
+#[derive(Clone, Debug, PartialEq)]
+struct Chunk {
+    cells: Vec<CubeDescriptor>,
+    filled: usize,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk {
+            cells: vec![Default::default(); CHUNK_VOLUME],
+            filled: 0,
+        }
+    }
+}
+
+/// A chunk of a layer, the neighbours are only counted in the merged grid
+#[derive(Clone, Debug)]
+struct LayerChunk {
+    cells: Vec<Option<u16>>,
+    filled: usize,
+}
+
+/// A named part of the document with its own voxels
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    /// Locked layers are left alone by the editing tools
+    pub locked: bool,
+    chunks: HashMap<[usize; 3], LayerChunk>,
+}
+
+impl Layer {
+    pub fn new(name: String) -> Self {
+        Layer {
+            name,
+            visible: true,
+            locked: false,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Returns the palette index of the layer's voxel in the cell
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<u16> {
+        self.chunks
+            .get(&chunk_key(x, y, z))
+            .and_then(|chunk| chunk.cells[cell_index(x, y, z)])
+    }
+
+    /// Fills or empties the cell and returns what it contained before
+    fn set(&mut self, x: usize, y: usize, z: usize, value: Option<u16>) -> Option<u16> {
+        let key = chunk_key(x, y, z);
+        if value.is_none() && !self.chunks.contains_key(&key) {
+            return None;
+        }
+        let chunk = self.chunks.entry(key).or_insert_with(|| LayerChunk {
+            cells: vec![None; CHUNK_VOLUME],
+            filled: 0,
+        });
+        let previous = mem::replace(&mut chunk.cells[cell_index(x, y, z)], value);
+        match (previous, value) {
+            (None, Some(_)) => chunk.filled += 1,
+            (Some(_), None) => chunk.filled -= 1,
+            _ => {}
+        }
+        if chunk.filled == 0 {
+            self.chunks.remove(&key);
+        }
+        previous
+    }
+
+    /// Returns the position and palette index of every voxel of the layer
+    pub fn voxels(&self) -> Vec<([usize; 3], u16)> {
+        let mut voxels: Vec<_> = self
+            .chunks
+            .iter()
+            .flat_map(|(key, chunk)| {
+                chunk
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(idx, cell)| cell.map(|c| (cell_position(key, idx), c)))
+            })
+            .collect();
+        voxels.sort_by_key(|(pos, _)| *pos);
+        voxels
+    }
+
+    /// Approximate number of bytes the voxels of the layer take
+    pub fn memory_size(&self) -> usize {
+        self.chunks.len() * CHUNK_VOLUME * mem::size_of::<Option<u16>>()
+    }
+}
+
+fn chunk_key(x: usize, y: usize, z: usize) -> [usize; 3] {
+    [x / CHUNK_SIZE, y / CHUNK_SIZE, z / CHUNK_SIZE]
+}
+
+fn cell_index(x: usize, y: usize, z: usize) -> usize {
+    ((x % CHUNK_SIZE) * CHUNK_SIZE + y % CHUNK_SIZE) * CHUNK_SIZE + z % CHUNK_SIZE
+}
+
+/// Position of a cell of the chunk in grid coordinates
+fn cell_position(key: &[usize; 3], idx: usize) -> [usize; 3] {
+    [
+        key[0] * CHUNK_SIZE + idx / (CHUNK_SIZE * CHUNK_SIZE),
+        key[1] * CHUNK_SIZE + idx / CHUNK_SIZE % CHUNK_SIZE,
+        key[2] * CHUNK_SIZE + idx % CHUNK_SIZE,
+    ]
+}
+
+/// The parts of the grid whose visible voxels changed since the renderer last asked
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirtyRegion {
+    /// Every voxel may have changed, like after a palette edit
+    All,
+    /// The keys of the changed chunks, ordered by position
+    Chunks(Vec<[usize; 3]>),
+}
+
+/// A rigid change of the voxels in a box of the grid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VoxelTransform {
+    /// Counter-clockwise quarter turns around the axis, looking from its positive end.
+    /// The box turns around its center, which is rounded to a cell for odd sizes.
+    Rotate { axis: usize, turns: u8 },
+    /// Mirrors the voxels on the axis
+    Flip(usize),
+    /// Moves the voxels by the offset. With `wrap` the voxels stay in the box, the ones
+    /// leaving it on one side come back on the other.
+    Translate { offset: [i32; 3], wrap: bool },
+}
+
+impl VoxelTransform {
+    /// The matrix of a rotation or a flip, translations keep the orientation
+    fn matrix(&self) -> Rotation {
+        match *self {
+            VoxelTransform::Rotate { axis, turns } => {
+                (0..turns).fold(IDENTITY, |m, _| mul_matrix(&quarter_turn(axis), &m))
+            }
+            VoxelTransform::Flip(axis) => {
+                let mut matrix = IDENTITY;
+                matrix[axis][axis] = -1;
+                matrix
+            }
+            VoxelTransform::Translate { .. } => IDENTITY,
+        }
+    }
+
+    /// The corner and extent of the box the voxels of a box end up in, the corner may be
+    /// outside of the grid
+    fn target(&self, corner: [usize; 3], extent: [usize; 3]) -> ([i32; 3], [usize; 3]) {
+        let mut target = [0; 3];
+        let mut target_extent = extent;
+        let rotated = mul_vector(
+            &self.matrix(),
+            [extent[0] as i32, extent[1] as i32, extent[2] as i32],
+        );
+        for i in 0..3 {
+            target[i] = corner[i] as i32;
+            match *self {
+                VoxelTransform::Translate {
+                    offset,
+                    wrap: false,
+                } => target[i] += offset[i],
+                VoxelTransform::Translate { wrap: true, .. } => {}
+                _ => {
+                    let size = rotated[i].abs();
+                    target_extent[i] = size as usize;
+                    target[i] += (extent[i] as i32 - size).div_euclid(2);
+                }
+            }
+        }
+        (target, target_extent)
+    }
+
+    /// Where the cell `pos` of a box with the given extent ends up, relative to the corner
+    /// of the target box
+    fn cell(&self, pos: [usize; 3], extent: [usize; 3]) -> [i32; 3] {
+        let pos = [pos[0] as i32, pos[1] as i32, pos[2] as i32];
+        match *self {
+            VoxelTransform::Translate { offset, wrap: true } => {
+                let mut cell = [0; 3];
+                for i in 0..3 {
+                    cell[i] = (pos[i] + offset[i]).rem_euclid(extent[i] as i32);
+                }
+                cell
+            }
+            VoxelTransform::Translate { wrap: false, .. } => pos,
+            _ => {
+                let transform = Transform {
+                    rotation: self.matrix(),
+                    translation: [0; 3],
+                };
+                let (start, _) = transform.bounds(extent);
+                let cell = transform.cell(pos);
+                [cell[0] - start[0], cell[1] - start[1], cell[2] - start[2]]
+            }
+        }
+    }
+}
+
+/// What happens to the voxels a transform moves out of the grid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GridBounds {
+    /// The voxels are deleted
+    Clip,
+    /// The grid grows to fit them, it never shrinks
+    Grow,
+}
+
+/// Morphological filters over the 26 cells around every cell
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VoxelFilter {
+    /// Fills the empty cells next to the voxels with the color of the nearest voxel
+    Dilate,
+    /// Empties the voxels next to an empty cell
+    Erode,
+    /// Erodes and dilates, which removes the thin parts
+    Open,
+    /// Dilates and erodes, which fills the small gaps
+    Close,
+    /// Fills or empties every cell like the majority of the cells around it
+    Smooth,
+}
+
+/// Boolean operations between the voxels of a target and of a source
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BooleanOp {
+    /// The cells filled in either of them
+    Union,
+    /// The cells of the target which are empty in the source
+    Subtract,
+    /// The cells filled in both of them
+    Intersect,
+    /// The cells filled in only one of them
+    Xor,
+}
+
+/// Whose color the cells filled in both the target and the source keep
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverlapColor {
+    Target,
+    Source,
+}
+
+impl BooleanOp {
+    /// Combines the voxels of the target with the voxels of the source, the result is ordered
+    /// by position
+    pub fn combine(
+        self,
+        target: &[([usize; 3], u16)],
+        source: &[([usize; 3], u16)],
+        overlap: OverlapColor,
+    ) -> Vec<([usize; 3], u16)> {
+        let target: HashMap<_, _> = target.iter().cloned().collect();
+        let source: HashMap<_, _> = source.iter().cloned().collect();
+        let cells: HashSet<_> = target.keys().chain(source.keys()).cloned().collect();
+        let mut combined: Vec<_> = cells
+            .into_iter()
+            .filter_map(|pos| {
+                let (in_target, in_source) = (target.get(&pos), source.get(&pos));
+                // Every cell is filled in the target or the source
+                let filled = match self {
+                    BooleanOp::Union => true,
+                    BooleanOp::Subtract => in_target.is_some() && in_source.is_none(),
+                    BooleanOp::Intersect => in_target.is_some() && in_source.is_some(),
+                    BooleanOp::Xor => in_target.is_none() || in_source.is_none(),
+                };
+                let idx = match overlap {
+                    OverlapColor::Target => in_target.or(in_source),
+                    OverlapColor::Source => in_source.or(in_target),
+                };
+                idx.filter(|_| filled).map(|idx| (pos, *idx))
+            })
+            .collect();
+        combined.sort();
+        combined
+    }
+}
+
+/// The voxels of a layer by their position. The filters work on an unbounded grid, where
+/// the cells outside of the canvas are empty.
+type VoxelMap = HashMap<[i32; 3], u16>;
+
+/// The region of a filter, given by its corner and extent
+type Region = ([usize; 3], [usize; 3]);
+
+/// Offsets of the 26 cells around a cell, the nearest first
+fn neighbourhood_offsets() -> Vec<[i32; 3]> {
+    let mut offsets = Vec::new();
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                if [x, y, z] != [0; 3] {
+                    offsets.push([x, y, z]);
+                }
+            }
+        }
+    }
+    offsets.sort_by_key(|[x, y, z]| x * x + y * y + z * z);
+    offsets
+}
+
+fn neighbourhood<'a>(
+    pos: [i32; 3],
+    offsets: &'a [[i32; 3]],
+) -> impl Iterator<Item = [i32; 3]> + 'a {
+    offsets
+        .iter()
+        .map(move |o| [pos[0] + o[0], pos[1] + o[1], pos[2] + o[2]])
+}
+
+/// Whether the filter may change the cell, every cell may change without a region
+fn in_region(pos: [i32; 3], region: Option<Region>) -> bool {
+    match region {
+        Some((corner, extent)) => {
+            (0..3).all(|i| pos[i] >= corner[i] as i32 && pos[i] < (corner[i] + extent[i]) as i32)
+        }
+        None => true,
+    }
+}
+
+fn dilate(voxels: &VoxelMap, region: Option<Region>) -> VoxelMap {
+    let offsets = neighbourhood_offsets();
+    let mut dilated = voxels.clone();
+    for pos in voxels.keys() {
+        for cell in neighbourhood(*pos, &offsets) {
+            if !dilated.contains_key(&cell) && in_region(cell, region) {
+                let nearest = neighbourhood(cell, &offsets)
+                    .find_map(|neighbour| voxels.get(&neighbour))
+                    .unwrap();
+                dilated.insert(cell, *nearest);
+            }
+        }
+    }
+    dilated
+}
+
+fn erode(voxels: &VoxelMap, region: Option<Region>) -> VoxelMap {
+    let offsets = neighbourhood_offsets();
+    voxels
+        .iter()
+        .filter(|(pos, _)| {
+            !in_region(**pos, region)
+                || neighbourhood(**pos, &offsets).all(|cell| voxels.contains_key(&cell))
+        })
+        .map(|(pos, idx)| (*pos, *idx))
+        .collect()
+}
+
+fn smooth(voxels: &VoxelMap, region: Option<Region>) -> VoxelMap {
+    let offsets = neighbourhood_offsets();
+    let candidates: HashSet<[i32; 3]> = voxels
+        .keys()
+        .flat_map(|pos| iter::once(*pos).chain(neighbourhood(*pos, &offsets)))
+        .filter(|pos| in_region(*pos, region))
+        .collect();
+    let mut smoothed = voxels.clone();
+    for pos in candidates {
+        // The majority of the 27 cells, the cell itself included
+        let colors: Vec<u16> = iter::once(pos)
+            .chain(neighbourhood(pos, &offsets))
+            .filter_map(|cell| voxels.get(&cell).cloned())
+            .collect();
+        let filled = colors.len() > 13;
+        match voxels.get(&pos) {
+            Some(_) if !filled => {
+                smoothed.remove(&pos);
+            }
+            None if filled => {
+                // The most common color around the cell, the lowest index of a tie
+                let mut counts: HashMap<u16, usize> = HashMap::new();
+                for idx in colors {
+                    *counts.entry(idx).or_insert(0) += 1;
+                }
+                let (idx, _) = counts
+                    .into_iter()
+                    .max_by_key(|&(idx, count)| (count, u16::MAX - idx))
+                    .unwrap();
+                smoothed.insert(pos, idx);
+            }
+            _ => {}
+        }
+    }
+    smoothed
+}
+
+fn color_key(color: &[f32; 4]) -> [u32; 4] {
+    [
+        color[0].to_bits(),
+        color[1].to_bits(),
+        color[2].to_bits(),
+        color[3].to_bits(),
+    ]
+}
+
+/// Sparse voxel grid. The cells are stored in chunks, and only the chunks containing
+/// filled voxels are allocated. The voxels refer to the entries of the palette by index,
+/// every entry has a color and a material.
+/// The voxels belong to layers, and the edits change the active layer. The visible layers
+/// are merged into the grid which is drawn, picked and exported, where the later layers
+/// cover the earlier ones.
+#[derive(Clone, Debug)]
 pub struct VoxelManager {
-    boxes: Vec<Vec<Vec<CubeDescriptor>>>,
-    extent: usize,
+    /// The merged voxels of the visible layers
+    chunks: HashMap<[usize; 3], Chunk>,
+    layers: Vec<Layer>,
+    active_layer: usize,
+    extent: [usize; 3],
+    palette: Vec<[f32; 4]>,
+    /// The material of every palette entry
+    materials: Vec<Material>,
+    /// The first palette index of every color with the default material
+    palette_lookup: HashMap<[u32; 4], u16>,
+    /// The merged chunks whose visible voxels changed since `take_dirty_region`
+    dirty_chunks: HashSet<[usize; 3]>,
+    all_dirty: bool,
+}
+
+/// Two grids are equal if they have the same layers with the same colors and materials
+/// in the same cells, whatever their palettes are
+impl PartialEq for VoxelManager {
+    fn eq(&self, other: &Self) -> bool {
+        let entries = |voxel_manager: &VoxelManager| -> Vec<_> {
+            voxel_manager
+                .layers
+                .iter()
+                .map(|layer| {
+                    let voxels: Vec<_> = layer
+                        .voxels()
+                        .into_iter()
+                        .map(|(pos, idx)| {
+                            let idx = idx as usize;
+                            (
+                                pos,
+                                voxel_manager.palette[idx],
+                                voxel_manager.materials[idx],
+                            )
+                        })
+                        .collect();
+                    (layer.name.clone(), layer.visible, layer.locked, voxels)
+                })
+                .collect()
+        };
+        self.extent == other.extent && entries(self) == entries(other)
+    }
 }
 
 impl VoxelManager {
-    pub fn new(extent: usize) -> Self {
+    pub fn new(extent: [usize; 3]) -> Self {
         VoxelManager {
-            boxes: vec![vec![vec![Default::default(); extent]; extent]; extent],
+            chunks: HashMap::new(),
+            layers: vec![Layer::new("Layer 1".to_string())],
+            active_layer: 0,
             extent,
+            palette: Vec::new(),
+            materials: Vec::new(),
+            palette_lookup: HashMap::new(),
+            dirty_chunks: HashSet::new(),
+            all_dirty: true,
+        }
+    }
+
+    pub fn palette(&self) -> &[[f32; 4]] {
+        &self.palette
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Replaces the palette, the voxels keep their palette indices. The entries keep
+    /// their material, and the new entries get the default one.
+    pub fn set_palette(&mut self, palette: Vec<[f32; 4]>) {
+        assert!(palette.len() <= u16::MAX as usize);
+        self.materials.resize(palette.len(), Material::default());
+        self.palette = palette;
+        self.rebuild_palette_lookup();
+        self.all_dirty = true;
+    }
+
+    /// Replaces the material of every palette entry
+    pub fn set_materials(&mut self, materials: Vec<Material>) {
+        assert_eq!(materials.len(), self.palette.len());
+        self.materials = materials;
+        self.rebuild_palette_lookup();
+        self.all_dirty = true;
+    }
+
+    /// Changes the material of a palette entry and of every voxel using it
+    pub fn set_material(&mut self, idx: u16, material: Material) {
+        self.materials[idx as usize] = material;
+        self.rebuild_palette_lookup();
+        self.all_dirty = true;
+    }
+
+    /// Changes a color of the palette, which recolors every voxel using it
+    pub fn set_palette_color(&mut self, idx: u16, color: [f32; 4]) {
+        self.palette[idx as usize] = color;
+        self.rebuild_palette_lookup();
+        self.all_dirty = true;
+    }
+
+    /// Adds a new entry to the palette, even if the color is already in it
+    pub fn add_palette_color(&mut self, color: [f32; 4]) -> u16 {
+        assert!(
+            self.palette.len() < u16::MAX as usize,
+            "The palette is full"
+        );
+        self.palette.push(color);
+        self.materials.push(Material::default());
+        let idx = (self.palette.len() - 1) as u16;
+        self.palette_lookup.entry(color_key(&color)).or_insert(idx);
+        idx
+    }
+
+    /// Removes the last entry of the palette, which no voxel may use
+    pub fn pop_palette_color(&mut self) {
+        self.palette.pop();
+        self.materials.pop();
+        self.rebuild_palette_lookup();
+    }
+
+    /// Returns the palette index of the color with the default material, the color is added
+    /// to the palette if needed
+    pub fn color_index(&mut self, color: [f32; 4]) -> u16 {
+        match self.palette_lookup.get(&color_key(&color)) {
+            Some(idx) => *idx,
+            None => self.add_palette_color(color),
+        }
+    }
+
+    /// The palette index `color_index` would return for the color, without adding it
+    pub fn peek_color_index(&self, color: [f32; 4]) -> u16 {
+        match self.palette_lookup.get(&color_key(&color)) {
+            Some(idx) => *idx,
+            None => self.palette.len() as u16,
+        }
+    }
+
+    fn rebuild_palette_lookup(&mut self) {
+        self.palette_lookup.clear();
+        let plain = Material::default();
+        for (idx, color) in self.palette.iter().enumerate() {
+            if self.materials[idx] == plain {
+                self.palette_lookup
+                    .entry(color_key(color))
+                    .or_insert(idx as u16);
+            }
+        }
+    }
+
+    /// Size of the grid along the x, y and z axes
+    pub fn extent(&self) -> [usize; 3] {
+        self.extent
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Index of the layer the edits go to
+    pub fn active_layer(&self) -> usize {
+        self.active_layer
+    }
+
+    pub fn set_active_layer(&mut self, idx: usize) {
+        assert!(idx < self.layers.len());
+        self.active_layer = idx;
+    }
+
+    /// Adds an empty layer on top of the others and makes it the active one
+    pub fn add_layer(&mut self) -> usize {
+        let name = (1..)
+            .map(|n| format!("Layer {}", n))
+            .find(|name| self.layers.iter().all(|layer| layer.name != *name))
+            .unwrap();
+        self.layers.push(Layer::new(name));
+        self.active_layer = self.layers.len() - 1;
+        self.active_layer
+    }
+
+    /// Removes the layer with its voxels, the last remaining layer can't be removed
+    pub fn remove_layer(&mut self, idx: usize) -> Layer {
+        assert!(self.layers.len() > 1, "The document needs a layer");
+        let layer = self.layers.remove(idx);
+        if self.active_layer > idx || self.active_layer == self.layers.len() {
+            self.active_layer -= 1;
+        }
+        if layer.visible {
+            self.merge_layers();
         }
+        layer
+    }
+
+    /// Puts the layer back into the stack and makes it the active one
+    pub fn insert_layer(&mut self, idx: usize, layer: Layer) {
+        self.layers.insert(idx, layer);
+        self.active_layer = idx;
+        self.merge_layers();
     }
 
-    pub fn add_box(&mut self, bbox: BoundingBox) {
+    /// Moves a layer to another place of the stack, the active layer stays active
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+        let active = self.active_layer;
+        self.active_layer = if active == from {
+            to
+        } else if from < active && active <= to {
+            active - 1
+        } else if to <= active && active < from {
+            active + 1
+        } else {
+            active
+        };
+        self.merge_layers();
+    }
+
+    pub fn set_layer_visible(&mut self, idx: usize, visible: bool) {
+        if self.layers[idx].visible != visible {
+            self.layers[idx].visible = visible;
+            self.merge_layers();
+        }
+    }
+
+    pub fn set_layer_locked(&mut self, idx: usize, locked: bool) {
+        self.layers[idx].locked = locked;
+    }
+
+    pub fn rename_layer(&mut self, idx: usize, name: String) {
+        self.layers[idx].name = name;
+    }
+
+    /// Rebuilds the merged grid from the visible layers
+    fn merge_layers(&mut self) {
+        self.chunks.clear();
+        self.all_dirty = true;
+        let voxels: Vec<_> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.visible)
+            .flat_map(|layer| layer.voxels())
+            .collect();
+        for ([x, y, z], idx) in voxels {
+            self.fill_merged(x, y, z, idx);
+        }
+    }
+
+    /// Updates a cell of the merged grid from the topmost visible layer filling it
+    fn merge_cell(&mut self, x: usize, y: usize, z: usize) {
+        let top = self
+            .layers
+            .iter()
+            .rev()
+            .filter(|layer| layer.visible)
+            .find_map(|layer| layer.get(x, y, z));
+        match top {
+            Some(idx) => self.fill_merged(x, y, z, idx),
+            None => self.clear_merged(x, y, z),
+        }
+    }
+
+    fn cell(&self, x: usize, y: usize, z: usize) -> Option<&CubeDescriptor> {
+        self.chunks
+            .get(&chunk_key(x, y, z))
+            .map(|chunk| &chunk.cells[cell_index(x, y, z)])
+    }
+
+    fn cell_mut(&mut self, x: usize, y: usize, z: usize) -> Option<&mut CubeDescriptor> {
+        self.chunks
+            .get_mut(&chunk_key(x, y, z))
+            .map(|chunk| &mut chunk.cells[cell_index(x, y, z)])
+    }
+
+    /// The allocated chunks ordered by their position, so the generated data is deterministic
+    fn sorted_chunks(&self) -> Vec<(&[usize; 3], &Chunk)> {
+        let mut chunks: Vec<_> = self.chunks.iter().collect();
+        chunks.sort_by_key(|(key, _)| **key);
+        chunks
+    }
+
+    /// Returns the filled cells with their descriptors chunk by chunk
+    fn filled_cells(&self) -> impl Iterator<Item = ([usize; 3], &CubeDescriptor)> {
+        self.sorted_chunks().into_iter().flat_map(|(key, chunk)| {
+            chunk
+                .cells
+                .iter()
+                .enumerate()
+                .filter(|(_, cell)| cell.color.is_some())
+                .map(move |(idx, cell)| (cell_position(key, idx), cell))
+        })
+    }
+
+    /// Returns the color of the visible voxel in the cell
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<[f32; 4]> {
+        self.get_index(x, y, z)
+            .map(|idx| self.palette[idx as usize])
+    }
+
+    /// Returns the palette index of the visible voxel's color
+    pub fn get_index(&self, x: usize, y: usize, z: usize) -> Option<u16> {
+        self.cell(x, y, z).and_then(|cell| cell.color)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, color: [f32; 4]) {
+        let idx = self.color_index(color);
+        self.set_index(x, y, z, idx);
+    }
+
+    /// Fills the cell of the active layer with the palette color of the given index
+    pub fn set_index(&mut self, x: usize, y: usize, z: usize, idx: u16) {
+        self.set_layer_cell(self.active_layer, x, y, z, Some(idx));
+    }
+
+    /// Empties the cell of the active layer
+    pub fn clear(&mut self, x: usize, y: usize, z: usize) {
+        self.set_layer_cell(self.active_layer, x, y, z, None);
+    }
+
+    /// Fills or empties the cells of the active layer in order, so the last edit of a cell wins
+    pub fn set_cells(&mut self, cells: &[([usize; 3], Option<u16>)]) {
+        for &([x, y, z], value) in cells {
+            self.set_layer_cell(self.active_layer, x, y, z, value);
+        }
+    }
+
+    /// Fills the cell of a layer with the palette entry or empties it
+    pub fn set_layer_cell(
+        &mut self,
+        layer: usize,
+        x: usize,
+        y: usize,
+        z: usize,
+        value: Option<u16>,
+    ) {
+        if let Some(idx) = value {
+            assert!(x < self.extent[0] && y < self.extent[1] && z < self.extent[2]);
+            debug_assert!((idx as usize) < self.palette.len());
+        }
+        if self.layers[layer].set(x, y, z, value) != value && self.layers[layer].visible {
+            self.merge_cell(x, y, z);
+        }
+    }
+
+    fn fill_merged(&mut self, x: usize, y: usize, z: usize, idx: u16) {
+        self.mark_dirty(x, y, z);
+        let chunk = self
+            .chunks
+            .entry(chunk_key(x, y, z))
+            .or_insert_with(Chunk::new);
+        if chunk.cells[cell_index(x, y, z)]
+            .color
+            .replace(idx)
+            .is_some()
+        {
+            return;
+        }
+        chunk.filled += 1;
+
+        let mut neighbours = 0;
+        for [nx, ny, nz] in self.get_neighbour_indices(x, y, z) {
+            if let Some(cell) = self.cell_mut(nx, ny, nz).filter(|c| c.color.is_some()) {
+                cell.incr();
+                neighbours += 1;
+            }
+        }
+        self.cell_mut(x, y, z).unwrap().neighbours = neighbours;
+    }
+
+    fn clear_merged(&mut self, x: usize, y: usize, z: usize) {
+        let key = chunk_key(x, y, z);
+        let chunk = match self.chunks.get_mut(&key) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let cell = &mut chunk.cells[cell_index(x, y, z)];
+        if cell.color.take().is_none() {
+            return;
+        }
+        cell.neighbours = 0;
+        chunk.filled -= 1;
+        if chunk.filled == 0 {
+            self.chunks.remove(&key);
+        }
+        self.mark_dirty(x, y, z);
+
+        for [nx, ny, nz] in self.get_neighbour_indices(x, y, z) {
+            if let Some(cell) = self.cell_mut(nx, ny, nz).filter(|c| c.color.is_some()) {
+                cell.decr();
+            }
+        }
+    }
+
+    /// Marks the chunk of the cell as changed, with the chunks of its neighbours whose
+    /// visibility depends on it
+    fn mark_dirty(&mut self, x: usize, y: usize, z: usize) {
+        self.dirty_chunks.insert(chunk_key(x, y, z));
+        for [nx, ny, nz] in self.get_neighbour_indices(x, y, z) {
+            self.dirty_chunks.insert(chunk_key(nx, ny, nz));
+        }
+    }
+
+    /// Returns the parts of the grid which changed since the last call
+    pub fn take_dirty_region(&mut self) -> DirtyRegion {
+        let chunks = mem::take(&mut self.dirty_chunks);
+        if mem::replace(&mut self.all_dirty, false) {
+            return DirtyRegion::All;
+        }
+        let mut chunks: Vec<_> = chunks.into_iter().collect();
+        chunks.sort();
+        DirtyRegion::Chunks(chunks)
+    }
+
+    /// Returns the position and color of every visible voxel
+    pub fn voxels(&self) -> Vec<([usize; 3], [f32; 4])> {
+        self.indexed_voxels()
+            .into_iter()
+            .map(|(pos, idx)| (pos, self.palette[idx as usize]))
+            .collect()
+    }
+
+    /// Returns the position and palette index of every visible voxel
+    pub fn indexed_voxels(&self) -> Vec<([usize; 3], u16)> {
+        let mut voxels: Vec<_> = self
+            .filled_cells()
+            .map(|(pos, cell)| (pos, cell.color.unwrap()))
+            .collect();
+        voxels.sort_by_key(|(pos, _)| *pos);
+        voxels
+    }
+
+    /// The range of cells covered by the allocated chunks on every axis, clipped to the grid
+    fn occupied_bounds(&self) -> Option<([usize; 3], [usize; 3])> {
+        let mut keys = self.chunks.keys();
+        let first = keys.next()?;
+        let (mut min, mut max) = (*first, *first);
+        for key in keys {
+            for i in 0..3 {
+                min[i] = min[i].min(key[i]);
+                max[i] = max[i].max(key[i]);
+            }
+        }
+        let mut start = [0; 3];
+        let mut end = [0; 3];
+        for i in 0..3 {
+            start[i] = min[i] * CHUNK_SIZE;
+            end[i] = ((max[i] + 1) * CHUNK_SIZE).min(self.extent[i]);
+        }
+        Some((start, end))
+    }
+
+    /// Where a voxel ends up after the voxels are moved by `offset`, if it's inside of `extent`
+    fn shifted(pos: [usize; 3], offset: [i32; 3], extent: [usize; 3]) -> Option<[usize; 3]> {
+        let mut shifted = [0; 3];
+        for i in 0..3 {
+            let p = pos[i] as i32 + offset[i];
+            if p < 0 || p >= extent[i] as i32 {
+                return None;
+            }
+            shifted[i] = p as usize;
+        }
+        Some(shifted)
+    }
+
+    /// Returns the voxels of every layer which would be deleted by `resize` with the index
+    /// of their layer and their palette index
+    pub fn cropped_voxels(
+        &self,
+        extent: [usize; 3],
+        offset: [i32; 3],
+    ) -> Vec<(usize, [usize; 3], u16)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .flat_map(|(layer, voxels)| {
+                voxels
+                    .voxels()
+                    .into_iter()
+                    .map(move |(pos, idx)| (layer, pos, idx))
+            })
+            .filter(|(_, pos, _)| Self::shifted(*pos, offset, extent).is_none())
+            .collect()
+    }
+
+    /// Changes the size of the grid and moves every voxel by `offset`.
+    /// The voxels which end up outside of the new grid are deleted.
+    pub fn resize(&mut self, extent: [usize; 3], offset: [i32; 3]) {
+        let mut resized = VoxelManager::new(extent);
+        resized.palette = self.palette.clone();
+        resized.materials = self.materials.clone();
+        resized.palette_lookup = self.palette_lookup.clone();
+        resized.layers = self
+            .layers
+            .iter()
+            .map(|layer| Layer {
+                chunks: HashMap::new(),
+                name: layer.name.clone(),
+                ..*layer
+            })
+            .collect();
+        resized.active_layer = self.active_layer;
+        for (layer, voxels) in self.layers.iter().enumerate() {
+            for (pos, idx) in voxels.voxels() {
+                if let Some([x, y, z]) = Self::shifted(pos, offset, extent) {
+                    resized.set_layer_cell(layer, x, y, z, Some(idx));
+                }
+            }
+        }
+        *self = resized;
+    }
+
+    /// The cell if it's inside of the grid
+    fn grid_cell(&self, cell: [i32; 3]) -> Option<[usize; 3]> {
+        Self::shifted([0; 3], cell, self.extent)
+    }
+
+    /// The extent of the grid and the offset of its voxels once the grid grows to fit the box
+    fn grown(&self, corner: [i32; 3], extent: [usize; 3]) -> ([usize; 3], [i32; 3]) {
+        let mut grid = [0; 3];
+        let mut offset = [0; 3];
+        for i in 0..3 {
+            let start = corner[i].min(0);
+            let end = (corner[i] + extent[i] as i32).max(self.extent[i] as i32);
+            grid[i] = (end - start) as usize;
+            offset[i] = -start;
+        }
+        (grid, offset)
+    }
+
+    /// The corner and extent of the box the voxels of `region` end up in after the transform,
+    /// cropped to the grid. The region is the whole grid if it's None. Returns None if the
+    /// box is entirely outside of the grid.
+    pub fn transformed_box(
+        &self,
+        transform: VoxelTransform,
+        region: Option<([usize; 3], [usize; 3])>,
+        bounds: GridBounds,
+    ) -> Option<([usize; 3], [usize; 3])> {
+        let (corner, extent) = region.unwrap_or(([0; 3], self.extent));
+        let (target, target_extent) = transform.target(corner, extent);
+        let (grid, offset) = match bounds {
+            GridBounds::Clip => (self.extent, [0; 3]),
+            GridBounds::Grow => self.grown(target, target_extent),
+        };
+        let mut cropped = ([0; 3], [0; 3]);
+        for i in 0..3 {
+            let start = (target[i] + offset[i]).max(0);
+            let end = (target[i] + offset[i] + target_extent[i] as i32).min(grid[i] as i32);
+            if start >= end {
+                return None;
+            }
+            cropped.0[i] = start as usize;
+            cropped.1[i] = (end - start) as usize;
+        }
+        Some(cropped)
+    }
+
+    /// Rotates, flips or moves the voxels of the active layer inside the region, given by
+    /// its corner and extent, or the voxels of every unlocked layer if it's None. The voxels
+    /// moved out of the grid are deleted or the grid grows to fit them.
+    pub fn transform(
+        &mut self,
+        transform: VoxelTransform,
+        region: Option<([usize; 3], [usize; 3])>,
+        bounds: GridBounds,
+    ) {
+        let layers: Vec<usize> = match region {
+            Some(_) => vec![self.active_layer],
+            None => (0..self.layers.len())
+                .filter(|idx| !self.layers[*idx].locked)
+                .collect(),
+        };
+        let (mut corner, extent) = region.unwrap_or(([0; 3], self.extent));
+        let (mut target, target_extent) = transform.target(corner, extent);
+        if bounds == GridBounds::Grow {
+            let (grid, offset) = self.grown(target, target_extent);
+            if grid != self.extent {
+                self.resize(grid, offset);
+                for i in 0..3 {
+                    corner[i] = (corner[i] as i32 + offset[i]) as usize;
+                    target[i] += offset[i];
+                }
+            }
+        }
+        for layer in layers {
+            let voxels: Vec<_> = self.layers[layer]
+                .voxels()
+                .into_iter()
+                .filter(|(pos, _)| {
+                    (0..3).all(|i| pos[i] >= corner[i] && pos[i] < corner[i] + extent[i])
+                })
+                .collect();
+            for &([x, y, z], _) in &voxels {
+                self.set_layer_cell(layer, x, y, z, None);
+            }
+            for (pos, idx) in voxels {
+                let relative = [pos[0] - corner[0], pos[1] - corner[1], pos[2] - corner[2]];
+                let cell = transform.cell(relative, extent);
+                let moved = [
+                    target[0] + cell[0],
+                    target[1] + cell[1],
+                    target[2] + cell[2],
+                ];
+                if let Some([x, y, z]) = self.grid_cell(moved) {
+                    self.set_layer_cell(layer, x, y, z, Some(idx));
+                }
+            }
+        }
+    }
+
+    /// Replaces the extent of the grid and every layer, like when a transform is undone
+    pub fn restore_layers(&mut self, extent: [usize; 3], layers: Vec<Layer>) {
+        self.extent = extent;
+        self.layers = layers;
+        self.merge_layers();
+    }
+
+    /// Fills the box with the palette entry `idx`
+    pub fn add_box(&mut self, bbox: BoundingBox, idx: u16) {
         let origin: Vector3<usize> = Vector3::new(
             bbox.corner.x as usize,
             bbox.corner.y as usize,
@@ -47,15 +1163,7 @@ impl VoxelManager {
         for x in origin.x..origin.x + bbox.extent.x as usize {
             for y in origin.y..origin.y + bbox.extent.y as usize {
                 for z in origin.z..origin.z + bbox.extent.z as usize {
-                    if self.boxes[x][y][z]
-                        .color
-                        .replace(bbox.color.into())
-                        .is_none()
-                    {
-                        for [nx, ny, nz] in self.get_neighbour_indices(x, y, z) {
-                            self.boxes[nx][ny][nz].incr();
-                        }
-                    }
+                    self.set_index(x, y, z, idx);
                 }
             }
         }
@@ -70,17 +1178,14 @@ impl VoxelManager {
         for x in origin.x..origin.x + bbox.extent.x as usize {
             for y in origin.y..origin.y + bbox.extent.y as usize {
                 for z in origin.z..origin.z + bbox.extent.z as usize {
-                    if self.boxes[x][y][z].color.take().is_some() {
-                        for [nx, ny, nz] in self.get_neighbour_indices(x, y, z) {
-                            self.boxes[nx][ny][nz].decr();
-                        }
-                    }
+                    self.clear(x, y, z);
                 }
             }
         }
     }
 
-    pub fn refill(&mut self, bbox: BoundingBox) {
+    /// Changes the filled voxels of the active layer in the box to the palette entry `idx`
+    pub fn refill(&mut self, bbox: BoundingBox, idx: u16) {
         let origin: Vector3<usize> = Vector3::new(
             bbox.corner.x as usize,
             bbox.corner.y as usize,
@@ -89,23 +1194,186 @@ impl VoxelManager {
         for x in origin.x..origin.x + bbox.extent.x as usize {
             for y in origin.y..origin.y + bbox.extent.y as usize {
                 for z in origin.z..origin.z + bbox.extent.z as usize {
-                    if self.boxes[x][y][z].color.is_some() {
-                        self.boxes[x][y][z].color = Some(bbox.color.into());
+                    if self.layers[self.active_layer].get(x, y, z).is_some() {
+                        self.set_index(x, y, z, idx);
                     }
                 }
             }
         }
     }
 
+    /// Edits turning the voxels of the active layer into the given ones
+    pub fn layer_edits(&self, voxels: &[([usize; 3], u16)]) -> Vec<([usize; 3], Option<u16>)> {
+        let layer = &self.layers[self.active_layer];
+        let kept: HashSet<_> = voxels.iter().map(|(pos, _)| *pos).collect();
+        let mut edits: Vec<_> = layer
+            .voxels()
+            .into_iter()
+            .filter(|(pos, _)| !kept.contains(pos))
+            .map(|(pos, _)| (pos, None))
+            .chain(
+                voxels
+                    .iter()
+                    .filter(|([x, y, z], idx)| layer.get(*x, *y, *z) != Some(*idx))
+                    .map(|(pos, idx)| (*pos, Some(*idx))),
+            )
+            .collect();
+        edits.sort();
+        edits
+    }
+
+    /// Edits applying the filter to the voxels of the active layer in the region, given by its
+    /// corner and extent, or to the whole layer. The cells around the region are only read.
+    pub fn filter(
+        &self,
+        filter: VoxelFilter,
+        region: Option<Region>,
+    ) -> Vec<([usize; 3], Option<u16>)> {
+        let voxels: VoxelMap = self.layers[self.active_layer]
+            .voxels()
+            .into_iter()
+            .map(|([x, y, z], idx)| ([x as i32, y as i32, z as i32], idx))
+            .collect();
+        let filtered = match filter {
+            VoxelFilter::Dilate => dilate(&voxels, region),
+            VoxelFilter::Erode => erode(&voxels, region),
+            VoxelFilter::Open => dilate(&erode(&voxels, region), region),
+            VoxelFilter::Close => erode(&dilate(&voxels, region), region),
+            VoxelFilter::Smooth => smooth(&voxels, region),
+        };
+        let removed = voxels
+            .keys()
+            .filter(|pos| !filtered.contains_key(*pos))
+            .map(|pos| (*pos, None));
+        let changed = filtered
+            .iter()
+            .filter(|(pos, idx)| voxels.get(*pos) != Some(*idx))
+            .map(|(pos, idx)| (*pos, Some(*idx)));
+        // The voxels grown out of the canvas are dropped
+        let mut edits: Vec<_> = removed
+            .chain(changed)
+            .filter(|(pos, _)| (0..3).all(|i| pos[i] >= 0 && (pos[i] as usize) < self.extent[i]))
+            .map(|(pos, idx)| ([pos[0] as usize, pos[1] as usize, pos[2] as usize], idx))
+            .collect();
+        edits.sort();
+        edits
+    }
+
+    /// Edits emptying the voxels of the active layer which are at least `thickness` cells
+    /// below the surface, so only a shell of that thickness is left. A shell without thickness
+    /// changes nothing.
+    pub fn hollow(&self, thickness: usize) -> Vec<([usize; 3], Option<u16>)> {
+        if thickness == 0 {
+            return Vec::new();
+        }
+        // The visible voxels are the surface, the depth grows inward from them
+        let sources = self
+            .filled_cells()
+            .filter(|(_, cell)| cell.visible())
+            .map(|(pos, _)| pos);
+        let depths = self.spread(sources, thickness, |pos| {
+            let [x, y, z] = pos;
+            self.get_index(x, y, z).is_some()
+        });
+        self.layers[self.active_layer]
+            .voxels()
+            .into_iter()
+            .filter(|(pos, _)| !depths.contains_key(pos))
+            .map(|(pos, _)| (pos, None))
+            .collect()
+    }
+
+    /// Edits filling the empty cells inside of the model up to `thickness` cells from its
+    /// voxels, with the color of the voxel they grow from. The cells connected to the border
+    /// of the grid are outside.
+    pub fn thicken(&self, thickness: usize) -> Vec<([usize; 3], Option<u16>)> {
+        let (start, end) = match self.occupied_bounds() {
+            Some(bounds) => bounds,
+            None => return Vec::new(),
+        };
+        // Only the cells up to `thickness` from the voxels can be filled, so the outside is
+        // only flooded in a margin around them. The empty cells on the boundary of the margin
+        // are either on the border of the grid or beyond every voxel, so they are outside.
+        let mut lower = [0; 3];
+        let mut upper = [0; 3];
+        for i in 0..3 {
+            lower[i] = start[i].saturating_sub(thickness + 1);
+            upper[i] = (end[i] + thickness + 1).min(self.extent[i]);
+        }
+        let in_region = |pos: [usize; 3]| (0..3).all(|i| pos[i] >= lower[i] && pos[i] < upper[i]);
+        let border_cells = (lower[0]..upper[0])
+            .flat_map(|x| (lower[1]..upper[1]).map(move |y| (x, y)))
+            .flat_map(|(x, y)| (lower[2]..upper[2]).map(move |z| [x, y, z]))
+            .filter(|pos| {
+                (0..3).any(|i| pos[i] == lower[i] || pos[i] + 1 == upper[i])
+                    && self.get_index(pos[0], pos[1], pos[2]).is_none()
+            });
+        let outside = self.spread(border_cells, usize::MAX, |pos| {
+            let [x, y, z] = pos;
+            in_region(pos) && self.get_index(x, y, z).is_none()
+        });
+        let sources: Vec<_> = self
+            .filled_cells()
+            .map(|(pos, cell)| (pos, cell.color.unwrap()))
+            .collect();
+        let mut colors: HashMap<[usize; 3], u16> = sources.iter().cloned().collect();
+        let mut edits = Vec::new();
+        let mut queue: VecDeque<_> = sources.into_iter().map(|(pos, _)| (pos, 0)).collect();
+        while let Some((pos, depth)) = queue.pop_front() {
+            if depth == thickness {
+                continue;
+            }
+            let color = colors[&pos];
+            for next in self.get_neighbour_indices(pos[0], pos[1], pos[2]) {
+                if !colors.contains_key(&next) && !outside.contains_key(&next) {
+                    colors.insert(next, color);
+                    edits.push((next, Some(color)));
+                    queue.push_back((next, depth + 1));
+                }
+            }
+        }
+        edits
+    }
+
+    /// Breadth first search from the sources through the face neighbours for which `inside`
+    /// holds, returns the cells closer than `limit` steps to a source with their distance
+    fn spread(
+        &self,
+        sources: impl Iterator<Item = [usize; 3]>,
+        limit: usize,
+        inside: impl Fn([usize; 3]) -> bool,
+    ) -> HashMap<[usize; 3], usize> {
+        let mut distances = HashMap::new();
+        let mut queue = VecDeque::new();
+        for pos in sources {
+            if limit > 0 && distances.insert(pos, 0).is_none() {
+                queue.push_back(pos);
+            }
+        }
+        while let Some(pos) = queue.pop_front() {
+            let distance = distances[&pos] + 1;
+            if distance >= limit {
+                continue;
+            }
+            for next in self.get_neighbour_indices(pos[0], pos[1], pos[2]) {
+                if !distances.contains_key(&next) && inside(next) {
+                    distances.insert(next, distance);
+                    queue.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+
     fn get_neighbour_indices(&self, pos_x: usize, pos_y: usize, pos_z: usize) -> Vec<[usize; 3]> {
         let mut neighbours = Vec::new();
         let min_x = pos_x.max(1) - 1;
         let min_y = pos_y.max(1) - 1;
         let min_z = pos_z.max(1) - 1;
 
-        let max_x = (pos_x + 1).min(self.extent - 1);
-        let max_y = (pos_y + 1).min(self.extent - 1);
-        let max_z = (pos_z + 1).min(self.extent - 1);
+        let max_x = (pos_x + 1).min(self.extent[0] - 1);
+        let max_y = (pos_y + 1).min(self.extent[1] - 1);
+        let max_z = (pos_z + 1).min(self.extent[2] - 1);
         for x in min_x..=max_x {
             if x != pos_x {
                 neighbours.push([x, pos_y, pos_z]);
@@ -125,129 +1393,1299 @@ impl VoxelManager {
         neighbours
     }
 
-    fn get_neighbour_boxes(
-        &mut self,
-        pos_x: usize,
-        pos_y: usize,
-        pos_z: usize,
-    ) -> Vec<BoundingBox> {
-        let mut origins = Vec::new();
-        for [nx, ny, nz] in self.get_neighbour_indices(pos_x, pos_y, pos_z) {
-            if self.boxes[nx][ny][nz].color.is_none() {
-                origins.push(BoundingBox::new(
-                    cgmath::Vector3::new(nx as f32, ny as f32, nz as f32),
-                    cgmath::Vector3::new(1.0, 1.0, 1.0),
-                    [1.0; 4],
-                ));
+    /// Walks the grid cells along the ray with the Amanatides-Woo algorithm and returns
+    /// the first filled voxel, the face the ray entered it through and the empty cell
+    /// in front of that face.
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        let origin = ray.origin;
+        let dir = ray.direction();
+        let extent = [
+            self.extent[0] as i32,
+            self.extent[1] as i32,
+            self.extent[2] as i32,
+        ];
+        let (lower, upper) = self.occupied_bounds()?;
+        let lower = [lower[0] as i32, lower[1] as i32, lower[2] as i32];
+        let upper = [upper[0] as i32, upper[1] as i32, upper[2] as i32];
+
+        // Clip the ray segment to the allocated chunks, the rest of the grid is empty
+        let mut t_enter = 0.0f32;
+        let mut t_exit = 1.0f32;
+        let mut enter_axis = None;
+        for i in 0..3 {
+            if dir[i].abs() < EPSYLON {
+                if origin[i] < lower[i] as f32 || origin[i] > upper[i] as f32 {
+                    return None;
+                }
+            } else {
+                let t1 = (lower[i] as f32 - origin[i]) / dir[i];
+                let t2 = (upper[i] as f32 - origin[i]) / dir[i];
+                let (near, far) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+                if near > t_enter {
+                    t_enter = near;
+                    enter_axis = Some(i);
+                }
+                t_exit = t_exit.min(far);
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        let start = origin + dir * t_enter;
+        let mut cell = [0; 3];
+        let mut step = [0; 3];
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for i in 0..3 {
+            cell[i] = (start[i].floor() as i32).max(lower[i]).min(upper[i] - 1);
+            if dir[i] > 0.0 {
+                step[i] = 1;
+                t_delta[i] = 1.0 / dir[i];
+                t_max[i] = t_enter + ((cell[i] + 1) as f32 - start[i]) / dir[i];
+            } else if dir[i] < 0.0 {
+                step[i] = -1;
+                t_delta[i] = -1.0 / dir[i];
+                t_max[i] = t_enter + (cell[i] as f32 - start[i]) / dir[i];
             }
         }
-        origins
+
+        // The normal stays zero if the ray starts inside the clipped region
+        let mut normal = [0; 3];
+        if let Some(axis) = enter_axis {
+            normal[axis] = -step[axis];
+        }
+        loop {
+            if self.is_filled(cell[0], cell[1], cell[2]) {
+                let in_front = [
+                    cell[0] + normal[0],
+                    cell[1] + normal[1],
+                    cell[2] + normal[2],
+                ];
+                let empty = if normal != [0; 3]
+                    && (0..3).all(|i| in_front[i] >= 0 && in_front[i] < extent[i])
+                {
+                    Some([
+                        in_front[0] as usize,
+                        in_front[1] as usize,
+                        in_front[2] as usize,
+                    ])
+                } else {
+                    None
+                };
+                return Some(RayHit {
+                    voxel: [cell[0] as usize, cell[1] as usize, cell[2] as usize],
+                    normal,
+                    empty,
+                });
+            }
+
+            let mut axis = 0;
+            for i in 1..3 {
+                if t_max[i] < t_max[axis] {
+                    axis = i;
+                }
+            }
+            if t_max[axis] > t_exit {
+                return None;
+            }
+            cell[axis] += step[axis];
+            if cell[axis] < lower[axis] || cell[axis] >= upper[axis] {
+                return None;
+            }
+            t_max[axis] += t_delta[axis];
+            normal = [0; 3];
+            normal[axis] = -step[axis];
+        }
     }
 
-    pub fn get_intersection_boxes(
-        &mut self,
-        ray: &Ray,
-    ) -> (Option<BoundingBox>, Option<BoundingBox>) {
-        let mut erase_box = None;
-        let mut draw_box = None;
-        let mut closest_distance = 100000.0;
-        let mut distance = 1.0;
-        let mut bbox;
-        for x in 0..self.extent {
-            for y in 0..self.extent {
-                for z in 0..self.extent {
-                    if let Some(color) = self.boxes[x][y][z].color {
-                        if !self.boxes[x][y][z].visible() {
-                            continue;
+    /// Returns the voxel under the cursor and the empty cell next to the pointed face
+    pub fn get_intersection_boxes(&self, ray: &Ray) -> (Option<BoundingBox>, Option<BoundingBox>) {
+        let hit = match self.raycast(ray) {
+            Some(hit) => hit,
+            None => return (None, None),
+        };
+        let [x, y, z] = hit.voxel;
+        let color = self.get(x, y, z).unwrap();
+        let erase_box = BoundingBox::new(
+            cgmath::Vector3::new(x as f32, y as f32, z as f32),
+            cgmath::Vector3::new(1.0, 1.0, 1.0),
+            color,
+        );
+        let draw_box = hit.empty.map(|[x, y, z]| {
+            BoundingBox::new(
+                cgmath::Vector3::new(x as f32, y as f32, z as f32),
+                cgmath::Vector3::new(1.0, 1.0, 1.0),
+                [1.0; 4],
+            )
+        });
+        (Some(erase_box), draw_box)
+    }
+
+    fn is_filled(&self, x: i32, y: i32, z: i32) -> bool {
+        let [ex, ey, ez] = self.extent;
+        if x < 0 || y < 0 || z < 0 || x >= ex as i32 || y >= ey as i32 || z >= ez as i32 {
+            return false;
+        }
+        self.get(x as usize, y as usize, z as usize).is_some()
+    }
+
+    /// Builds the surface of the model. Only the faces next to empty cells are kept and
+    /// the faces lying in the same plane share their vertices.
+    pub fn vertices(&self) -> (Vec<MeshVertex>, Vec<u32>) {
+        let mut mesh = MeshBuilder::default();
+        for ([x, y, z], cell) in self.filled_cells() {
+            if !cell.visible() {
+                continue;
+            }
+            let idx = cell.color.unwrap() as usize;
+            let (color, material) = (self.palette[idx], self.materials[idx]);
+            let vertex = |pos, normal| MeshVertex {
+                material,
+                ..mesh_vertex(pos, normal, color)
+            };
+            let bbox = BoundingBox::new(
+                cgmath::Vector3::new(x as f32, y as f32, z as f32),
+                cgmath::Vector3::new(1.0, 1.0, 1.0),
+                color,
+            );
+            let vertices = bbox.voxel_vertices();
+            for (face, dir) in FACE_DIRECTIONS.iter().enumerate() {
+                if self.is_filled(x as i32 + dir[0], y as i32 + dir[1], z as i32 + dir[2]) {
+                    continue;
+                }
+                let corners = &vertices[4 * face..4 * face + 4];
+                mesh.quad([
+                    vertex(corners[0].pos, corners[0].normal),
+                    vertex(corners[1].pos, corners[1].normal),
+                    vertex(corners[2].pos, corners[2].normal),
+                    vertex(corners[3].pos, corners[3].normal),
+                ]);
+            }
+        }
+        mesh.finish()
+    }
+
+    /// Builds the surface of the model like `vertices`, but the coplanar faces with the
+    /// same color are merged into maximal rectangles.
+    pub fn greedy_vertices(&self) -> (Vec<MeshVertex>, Vec<u32>) {
+        let mut mesh = MeshBuilder::default();
+        let largest = *self.extent.iter().max().unwrap();
+        let mut mask: Vec<Option<u16>> = vec![None; largest * largest];
+        for dir in FACE_DIRECTIONS.iter() {
+            let axis = dir.iter().position(|d| *d != 0).unwrap();
+            let sign = dir[axis];
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            // The mask has a row of u_end faces for each v coordinate
+            let (u_end, v_end) = (self.extent[u_axis] as i32, self.extent[v_axis] as i32);
+            let mut normal = [0.0; 3];
+            normal[axis] = sign as f32;
+            for (layer, chunks) in self.occupied_layers(axis, u_axis, v_axis) {
+                let first = (layer * CHUNK_SIZE) as i32;
+                let last = (first + CHUNK_SIZE as i32).min(self.extent[axis] as i32);
+                for slice in first..last {
+                    // Collect the faces of the slice which are next to an empty cell
+                    let mut faces = Vec::new();
+                    for [chunk_u, chunk_v] in chunks.iter() {
+                        let (u_start, v_start) =
+                            ((chunk_u * CHUNK_SIZE) as i32, (chunk_v * CHUNK_SIZE) as i32);
+                        for v in v_start..(v_start + CHUNK_SIZE as i32).min(v_end) {
+                            for u in u_start..(u_start + CHUNK_SIZE as i32).min(u_end) {
+                                let mut pos = [0; 3];
+                                pos[axis] = slice;
+                                pos[u_axis] = u;
+                                pos[v_axis] = v;
+                                let entry = self
+                                    .get_index(pos[0] as usize, pos[1] as usize, pos[2] as usize)
+                                    .filter(|_| {
+                                        !self.is_filled(
+                                            pos[0] + dir[0],
+                                            pos[1] + dir[1],
+                                            pos[2] + dir[2],
+                                        )
+                                    });
+                                if entry.is_some() {
+                                    mask[(v * u_end + u) as usize] = entry;
+                                    faces.push((v, u));
+                                }
+                            }
+                        }
+                    }
+                    // Visit the faces row by row, the merged faces are removed from the mask
+                    faces.sort();
+
+                    for (v, u) in faces {
+                        let entry = match mask[(v * u_end + u) as usize] {
+                            Some(entry) => entry,
+                            None => continue,
+                        };
+                        let mut width = 1;
+                        while u + width < u_end
+                            && mask[(v * u_end + u + width) as usize] == Some(entry)
+                        {
+                            width += 1;
                         }
-                        bbox = BoundingBox::new(
-                            cgmath::Vector3::new(x as f32, y as f32, z as f32),
-                            cgmath::Vector3::new(1.0, 1.0, 1.0),
-                            color,
-                        );
-
-                        if ray.box_intersection(&bbox, &mut distance) {
-                            if distance.abs() < closest_distance {
-                                closest_distance = distance.abs();
-                                erase_box = Some(bbox);
-                                if cfg!(feature = "debug_ray") {
-                                    self.boxes[x][y][z].color = Some([0.0, 0.0, 1.0, 1.0]);
+                        let mut height = 1;
+                        'grow: while v + height < v_end {
+                            for du in 0..width {
+                                if mask[((v + height) * u_end + u + du) as usize] != Some(entry) {
+                                    break 'grow;
                                 }
                             }
-                        } else if cfg!(feature = "debug_ray") {
-                            self.boxes[x][y][z].color = Some([0.0, 1.0, 0.0, 0.1]);
+                            height += 1;
+                        }
+                        for dv in 0..height {
+                            for du in 0..width {
+                                mask[((v + dv) * u_end + u + du) as usize] = None;
+                            }
+                        }
+
+                        let plane = if sign > 0 { slice + 1 } else { slice } as f32;
+                        let color = self.palette[entry as usize];
+                        let material = self.materials[entry as usize];
+                        let corner = |cu: i32, cv: i32| {
+                            let mut pos = [0.0; 3];
+                            pos[axis] = plane;
+                            pos[u_axis] = cu as f32;
+                            pos[v_axis] = cv as f32;
+                            MeshVertex {
+                                material,
+                                ..mesh_vertex(pos, normal, color)
+                            }
+                        };
+                        let corners = [
+                            corner(u, v),
+                            corner(u + width, v),
+                            corner(u + width, v + height),
+                            corner(u, v + height),
+                        ];
+                        // The u and v axes follow each other cyclically, so the corners
+                        // are counter-clockwise when looking from the positive side
+                        if sign > 0 {
+                            mesh.quad(corners);
+                        } else {
+                            mesh.quad([corners[0], corners[3], corners[2], corners[1]]);
                         }
                     }
                 }
             }
         }
-        if let Some(bbox) = erase_box {
-            draw_box = erase_box;
-            let boxes = self.get_neighbour_boxes(
-                bbox.corner.x as usize,
-                bbox.corner.y as usize,
-                bbox.corner.z as usize,
+        mesh.finish()
+    }
+
+    /// Groups the allocated chunks into layers along `axis`, and returns the position
+    /// of the chunks on the `u_axis` and `v_axis` for each layer
+    fn occupied_layers(
+        &self,
+        axis: usize,
+        u_axis: usize,
+        v_axis: usize,
+    ) -> Vec<(usize, Vec<[usize; 2]>)> {
+        let mut layers: HashMap<usize, Vec<[usize; 2]>> = HashMap::new();
+        for key in self.chunks.keys() {
+            layers
+                .entry(key[axis])
+                .or_default()
+                .push([key[u_axis], key[v_axis]]);
+        }
+        let mut layers: Vec<_> = layers.into_iter().collect();
+        layers.sort_by_key(|(layer, _)| *layer);
+        layers
+    }
+
+    fn instance(&self, [x, y, z]: [usize; 3], idx: u16) -> VoxelInstance {
+        let idx = idx as usize;
+        instance(
+            [x as f32, y as f32, z as f32],
+            self.palette[idx],
+            &self.materials[idx],
+        )
+    }
+
+    /// Returns the instance of every visible voxel with its position
+    pub fn instances(&self) -> Vec<([usize; 3], VoxelInstance)> {
+        self.filled_cells()
+            .filter(|(_, cell)| cell.visible())
+            .map(|(pos, cell)| (pos, self.instance(pos, cell.color.unwrap())))
+            .collect()
+    }
+
+    /// Returns every cell of the chunk inside the grid with the instance of its voxel, or
+    /// None if the cell is empty or its voxel is hidden
+    pub fn chunk_instances(&self, key: [usize; 3]) -> Vec<([usize; 3], Option<VoxelInstance>)> {
+        let mut cells = Vec::new();
+        let start = [
+            key[0] * CHUNK_SIZE,
+            key[1] * CHUNK_SIZE,
+            key[2] * CHUNK_SIZE,
+        ];
+        let end = [
+            (start[0] + CHUNK_SIZE).min(self.extent[0]),
+            (start[1] + CHUNK_SIZE).min(self.extent[1]),
+            (start[2] + CHUNK_SIZE).min(self.extent[2]),
+        ];
+        for x in start[0]..end[0] {
+            for y in start[1]..end[1] {
+                for z in start[2]..end[2] {
+                    let instance = self
+                        .cell(x, y, z)
+                        .filter(|cell| cell.color.is_some() && cell.visible())
+                        .map(|cell| self.instance([x, y, z], cell.color.unwrap()));
+                    cells.push(([x, y, z], instance));
+                }
+            }
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    fn solid_block(extent: usize, size: f32) -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new([extent; 3]);
+        let idx = voxel_manager.color_index([1.0, 0.0, 0.0, 1.0]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(size, size, size),
+                [1.0, 0.0, 0.0, 1.0],
+            ),
+            idx,
+        );
+        voxel_manager
+    }
+
+    fn position_key(vertex: &MeshVertex) -> [u32; 3] {
+        [
+            vertex.pos[0].to_bits(),
+            vertex.pos[1].to_bits(),
+            vertex.pos[2].to_bits(),
+        ]
+    }
+
+    #[test]
+    fn hidden_faces_are_culled() {
+        let (vertices, indices) = solid_block(4, 3.0).vertices();
+        // 9 quads on each side of the block
+        assert_eq!(indices.len(), 6 * 9 * 6);
+        // 16 vertices per side, shared within the side
+        assert_eq!(vertices.len(), 6 * 16);
+    }
+
+    fn surface_area(vertices: &[MeshVertex], indices: &[u32]) -> f32 {
+        indices
+            .chunks(3)
+            .map(|t| {
+                let p = |i: u32| Vector3::from(vertices[i as usize].pos);
+                (p(t[1]) - p(t[0])).cross(p(t[2]) - p(t[0])).magnitude() / 2.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn greedy_wall() {
+        let mut voxel_manager = VoxelManager::new([32; 3]);
+        let idx = voxel_manager.color_index([1.0, 0.0, 0.0, 1.0]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(32.0, 32.0, 1.0),
+                [1.0, 0.0, 0.0, 1.0],
+            ),
+            idx,
+        );
+        let (naive_vertices, naive_indices) = voxel_manager.vertices();
+        let (vertices, indices) = voxel_manager.greedy_vertices();
+
+        // One quad for each side of the wall
+        assert_eq!(indices.len(), 6 * 6);
+        assert!(naive_indices.len() > 1024 * 6);
+        assert_eq!(
+            surface_area(&vertices, &indices),
+            surface_area(&naive_vertices, &naive_indices)
+        );
+    }
+
+    #[test]
+    fn greedy_keeps_colors_apart() {
+        let mut voxel_manager = solid_block(6, 5.0);
+        let idx = voxel_manager.color_index([0.0, 0.0, 1.0, 1.0]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(1.0, 1.0, 4.0),
+                Vector3::new(2.0, 3.0, 2.0),
+                [0.0, 0.0, 1.0, 1.0],
+            ),
+            idx,
+        );
+        voxel_manager.clear(0, 4, 0);
+        voxel_manager.clear(2, 4, 2);
+        let (naive_vertices, naive_indices) = voxel_manager.vertices();
+        let (vertices, indices) = voxel_manager.greedy_vertices();
+
+        assert!(indices.len() < naive_indices.len());
+        assert!(
+            (surface_area(&vertices, &indices) - surface_area(&naive_vertices, &naive_indices))
+                .abs()
+                < 0.001
+        );
+        for color in [[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]].iter() {
+            let filter = |vertices: &[MeshVertex], indices: &[u32]| -> Vec<u32> {
+                indices
+                    .chunks(3)
+                    .filter(|t| vertices[t[0] as usize].color == *color)
+                    .flat_map(|t| t.to_vec())
+                    .collect()
+            };
+            assert_eq!(
+                surface_area(&vertices, &filter(&vertices, &indices)),
+                surface_area(&naive_vertices, &filter(&naive_vertices, &naive_indices))
             );
-            for bbox in boxes {
-                if ray.box_intersection(&bbox, &mut distance) {
-                    if distance.abs() < closest_distance {
-                        closest_distance = distance.abs();
-                        draw_box = Some(bbox);
-                    }
+        }
+    }
+
+    #[test]
+    fn greedy_winding_matches_normals() {
+        let (vertices, indices) = solid_block(4, 3.0).greedy_vertices();
+        assert_eq!(indices.len(), 6 * 6);
+        for t in indices.chunks(3) {
+            let p = |i: u32| Vector3::from(vertices[t[i as usize] as usize].pos);
+            let face_normal = (p(1) - p(0)).cross(p(2) - p(0));
+            let normal = Vector3::from(vertices[t[0] as usize].normal);
+            assert!(face_normal.dot(normal) > 0.0);
+        }
+    }
+
+    fn ray(origin: [f32; 3], end: [f32; 3]) -> Ray {
+        Ray::new(Vector3::from(origin), Vector3::from(end))
+    }
+
+    #[test]
+    fn raycast_hits_face_pointed_at() {
+        let mut voxel_manager = VoxelManager::new([8; 3]);
+        voxel_manager.set(4, 4, 4, [1.0; 4]);
+
+        let hit = voxel_manager
+            .raycast(&ray([4.5, 20.0, 4.5], [4.5, -20.0, 4.5]))
+            .unwrap();
+        assert_eq!(hit.voxel, [4, 4, 4]);
+        assert_eq!(hit.normal, [0, 1, 0]);
+        assert_eq!(hit.empty, Some([4, 5, 4]));
+
+        let hit = voxel_manager
+            .raycast(&ray([-10.0, 4.5, 4.2], [20.0, 4.5, 4.8]))
+            .unwrap();
+        assert_eq!(hit.normal, [-1, 0, 0]);
+        assert_eq!(hit.empty, Some([3, 4, 4]));
+
+        assert_eq!(
+            voxel_manager.raycast(&ray([-10.0, 4.5, 4.5], [20.0, 8.5, 4.5])),
+            None
+        );
+        assert_eq!(
+            voxel_manager.raycast(&ray([-10.0, 20.0, 4.5], [-5.0, 20.0, 4.5])),
+            None
+        );
+    }
+
+    #[test]
+    fn raycast_grazing_angle() {
+        let mut voxel_manager = VoxelManager::new([8; 3]);
+        for x in 5..8 {
+            voxel_manager.set(x, 0, 0, [1.0; 4]);
+        }
+        voxel_manager.set(3, 2, 0, [1.0; 4]);
+
+        // An almost flat ray which reaches the top of the row in the middle of a voxel
+        let hit = voxel_manager
+            .raycast(&ray([0.5, 1.2, 0.5], [8.0, 0.9, 0.5]))
+            .unwrap();
+        assert_eq!(hit.voxel, [5, 0, 0]);
+        assert_eq!(hit.normal, [0, 1, 0]);
+        assert_eq!(hit.empty, Some([5, 1, 0]));
+    }
+
+    #[test]
+    fn raycast_grid_boundary() {
+        let mut voxel_manager = VoxelManager::new([4; 3]);
+        voxel_manager.set(0, 2, 2, [1.0; 4]);
+        // The face on the border of the grid has no cell in front of it
+        let hit = voxel_manager
+            .raycast(&ray([-5.0, 2.5, 2.5], [5.0, 2.5, 2.5]))
+            .unwrap();
+        assert_eq!(hit.voxel, [0, 2, 2]);
+        assert_eq!(hit.normal, [-1, 0, 0]);
+        assert_eq!(hit.empty, None);
+    }
+
+    #[test]
+    fn closed_surface() {
+        let mut voxel_manager = solid_block(4, 3.0);
+        voxel_manager.set(3, 0, 0, [0.0, 1.0, 0.0, 1.0]);
+        voxel_manager.clear(1, 2, 1);
+        let (vertices, indices) = voxel_manager.vertices();
+
+        // Every edge has to be shared by exactly two triangles, in opposite directions
+        let mut edges = HashMap::new();
+        for triangle in indices.chunks(3) {
+            for i in 0..3 {
+                let start = position_key(&vertices[triangle[i] as usize]);
+                let end = position_key(&vertices[triangle[(i + 1) % 3] as usize]);
+                *edges.entry((start, end)).or_insert(0) += 1;
+            }
+        }
+        for ((start, end), count) in edges.iter() {
+            assert_eq!(*count, 1);
+            assert_eq!(edges.get(&(*end, *start)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn neighbours_across_chunk_borders() {
+        let mut voxel_manager = VoxelManager::new([64; 3]);
+        let idx = voxel_manager.color_index([1.0; 4]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(15.0, 15.0, 15.0),
+                Vector3::new(3.0, 3.0, 3.0),
+                [1.0; 4],
+            ),
+            idx,
+        );
+        assert_eq!(voxel_manager.chunks.len(), 8);
+        // Only the voxel in the middle is hidden
+        assert_eq!(voxel_manager.instances().len(), 26);
+
+        voxel_manager.clear(15, 16, 16);
+        assert_eq!(voxel_manager.instances().len(), 26);
+        voxel_manager.set(15, 16, 16, [1.0; 4]);
+        assert_eq!(voxel_manager.instances().len(), 26);
+
+        voxel_manager.erase_box(BoundingBox::new(
+            Vector3::new(15.0, 15.0, 15.0),
+            Vector3::new(3.0, 3.0, 3.0),
+            [1.0; 4],
+        ));
+        assert_eq!(voxel_manager, VoxelManager::new([64; 3]));
+    }
+
+    #[test]
+    fn large_sparse_grid() {
+        let mut voxel_manager = VoxelManager::new([512; 3]);
+        voxel_manager.set(0, 0, 0, [1.0; 4]);
+        voxel_manager.set(500, 300, 7, [0.5; 4]);
+        assert_eq!(voxel_manager.chunks.len(), 2);
+        assert_eq!(
+            voxel_manager.voxels(),
+            vec![([0, 0, 0], [1.0; 4]), ([500, 300, 7], [0.5; 4])]
+        );
+        assert_eq!(voxel_manager.instances().len(), 2);
+        assert_eq!(voxel_manager.greedy_vertices().1.len(), 2 * 6 * 6);
+
+        let hit = voxel_manager
+            .raycast(&ray([500.5, 600.0, 7.5], [500.5, -10.0, 7.5]))
+            .unwrap();
+        assert_eq!(hit.voxel, [500, 300, 7]);
+        assert_eq!(hit.empty, Some([500, 301, 7]));
+    }
+
+    #[test]
+    fn non_cubic_grid() {
+        let mut voxel_manager = VoxelManager::new([40, 2, 4]);
+        let idx = voxel_manager.color_index([1.0; 4]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(40.0, 1.0, 4.0),
+                [1.0; 4],
+            ),
+            idx,
+        );
+        assert_eq!(voxel_manager.greedy_vertices().1.len(), 6 * 6);
+
+        voxel_manager.set(39, 1, 3, [1.0; 4]);
+        let hit = voxel_manager
+            .raycast(&ray([39.5, 10.0, 3.5], [39.5, -10.0, 3.5]))
+            .unwrap();
+        assert_eq!(hit.voxel, [39, 1, 3]);
+        // The top of the grid is right above the voxel
+        assert_eq!(hit.empty, None);
+        let hit = voxel_manager
+            .raycast(&ray([38.5, 10.0, 3.5], [38.5, -10.0, 3.5]))
+            .unwrap();
+        assert_eq!(hit.empty, Some([38, 1, 3]));
+    }
+
+    #[test]
+    fn palette_indices() {
+        let mut voxel_manager = VoxelManager::new([4, 4, 4]);
+        voxel_manager.set_palette(vec![[1.0; 4], [0.5; 4]]);
+        voxel_manager.set(0, 0, 0, [0.5; 4]);
+        voxel_manager.set(1, 0, 0, [0.25; 4]);
+        voxel_manager.set(2, 0, 0, [0.5; 4]);
+        assert_eq!(voxel_manager.palette().len(), 3);
+        assert_eq!(voxel_manager.get_index(0, 0, 0), Some(1));
+        assert_eq!(voxel_manager.get_index(1, 0, 0), Some(2));
+
+        // Editing the palette recolors every voxel using the entry
+        voxel_manager.set_palette_color(1, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(voxel_manager.get(0, 0, 0), Some([0.0, 1.0, 0.0, 1.0]));
+        assert_eq!(voxel_manager.get(2, 0, 0), Some([0.0, 1.0, 0.0, 1.0]));
+        assert_eq!(voxel_manager.get(1, 0, 0), Some([0.25; 4]));
+        assert_eq!(voxel_manager.peek_color_index([0.25; 4]), 2);
+        assert_eq!(voxel_manager.peek_color_index([0.5; 4]), 3);
+        assert_eq!(voxel_manager.palette().len(), 3);
+        assert_eq!(voxel_manager.color_index([0.5; 4]), 3);
+    }
+
+    #[test]
+    fn transparent_faces_last() {
+        let mut voxel_manager = VoxelManager::new([4, 4, 4]);
+        voxel_manager.set_palette(vec![[1.0; 4], [0.5, 0.5, 0.5, 1.0]]);
+        voxel_manager.set_material(
+            0,
+            Material {
+                transparency: 0.5,
+                ..Material::default()
+            },
+        );
+        voxel_manager.set_index(0, 0, 0, 0);
+        voxel_manager.set_index(2, 0, 0, 1);
+        // The glass entry isn't reused for plain colors
+        assert_eq!(voxel_manager.color_index([1.0; 4]), 2);
+
+        let (vertices, indices) = voxel_manager.greedy_vertices();
+        let transparent: Vec<bool> = indices
+            .chunks(3)
+            .map(|id| vertices[id[0] as usize].material.transparency > 0.0)
+            .collect();
+        assert_eq!(transparent.len(), 2 * 2 * 6);
+        assert!(transparent[..12].iter().all(|t| !t));
+        assert!(transparent[12..].iter().all(|t| *t));
+    }
+
+    #[test]
+    fn resize_with_anchors() {
+        let mut voxel_manager = VoxelManager::new([4, 4, 4]);
+        voxel_manager.set(0, 0, 0, [1.0; 4]);
+        voxel_manager.set(3, 3, 3, [0.5; 4]);
+
+        let extent = [8, 2, 6];
+        let offset = [
+            Anchor::End.offset(4, 8),
+            Anchor::Start.offset(4, 2),
+            Anchor::Center.offset(4, 6),
+        ];
+        assert_eq!(offset, [4, 0, 1]);
+        assert_eq!(
+            voxel_manager.cropped_voxels(extent, offset),
+            vec![(0, [3, 3, 3], 1)]
+        );
+
+        voxel_manager.resize(extent, offset);
+        assert_eq!(voxel_manager.extent(), extent);
+        assert_eq!(voxel_manager.voxels(), vec![([4, 0, 1], [1.0; 4])]);
+    }
+
+    #[test]
+    fn dirty_chunks() {
+        let mut voxel_manager = VoxelManager::new([40, 20, 20]);
+        assert_eq!(voxel_manager.take_dirty_region(), DirtyRegion::All);
+        assert_eq!(
+            voxel_manager.take_dirty_region(),
+            DirtyRegion::Chunks(vec![])
+        );
+
+        voxel_manager.set(3, 3, 3, [1.0; 4]);
+        assert_eq!(
+            voxel_manager.take_dirty_region(),
+            DirtyRegion::Chunks(vec![[0, 0, 0]])
+        );
+        // The neighbour across the chunk border may become hidden
+        voxel_manager.set(16, 3, 3, [1.0; 4]);
+        voxel_manager.clear(3, 3, 3);
+        assert_eq!(
+            voxel_manager.take_dirty_region(),
+            DirtyRegion::Chunks(vec![[0, 0, 0], [1, 0, 0]])
+        );
+        // Clearing an empty cell changes nothing
+        voxel_manager.clear(35, 3, 3);
+        assert_eq!(
+            voxel_manager.take_dirty_region(),
+            DirtyRegion::Chunks(vec![])
+        );
+
+        let instances = voxel_manager.chunk_instances([2, 1, 0]);
+        // The chunk is clipped to the grid
+        assert_eq!(instances.len(), 8 * 4 * 16);
+        assert!(instances.iter().all(|(_, instance)| instance.is_none()));
+        let instances = voxel_manager.chunk_instances([1, 0, 0]);
+        assert_eq!(
+            instances
+                .iter()
+                .filter_map(|(pos, instance)| instance.map(|i| (*pos, i.offset())))
+                .collect::<Vec<_>>(),
+            vec![([16, 3, 3], [16.0, 3.0, 3.0])]
+        );
+
+        voxel_manager.set_palette_color(0, [0.5; 4]);
+        assert_eq!(voxel_manager.take_dirty_region(), DirtyRegion::All);
+    }
+
+    #[test]
+    fn resize_rebuilds_neighbours() {
+        let mut voxel_manager = solid_block(3, 3.0);
+        assert_eq!(voxel_manager.instances().len(), 26);
+        voxel_manager.resize([3, 3, 2], [0, 0, -1]);
+        // The hidden voxel in the middle is on the surface now
+        assert_eq!(voxel_manager.instances().len(), 18);
+        assert_eq!(voxel_manager, {
+            let mut expected = VoxelManager::new([3, 3, 2]);
+            let idx = expected.color_index([1.0, 0.0, 0.0, 1.0]);
+            expected.add_box(
+                BoundingBox::new(
+                    Vector3::new(0.0, 0.0, 0.0),
+                    Vector3::new(3.0, 3.0, 2.0),
+                    [1.0, 0.0, 0.0, 1.0],
+                ),
+                idx,
+            );
+            expected
+        });
+    }
+
+    #[test]
+    fn layers_merge_visible_voxels() {
+        let mut voxel_manager = VoxelManager::new([4, 4, 4]);
+        voxel_manager.set_palette(vec![[1.0; 4], [0.5, 0.5, 0.5, 1.0]]);
+        voxel_manager.set_index(0, 0, 0, 0);
+        voxel_manager.set_index(1, 0, 0, 0);
+        assert_eq!(voxel_manager.add_layer(), 1);
+        assert_eq!(voxel_manager.layers()[1].name, "Layer 2");
+        voxel_manager.set_index(1, 0, 0, 1);
+        voxel_manager.set_index(2, 0, 0, 1);
+        // The later layer covers the earlier one
+        assert_eq!(
+            voxel_manager.indexed_voxels(),
+            vec![([0, 0, 0], 0), ([1, 0, 0], 1), ([2, 0, 0], 1)]
+        );
+        assert_eq!(voxel_manager.layers()[0].voxels().len(), 2);
+
+        voxel_manager.set_layer_visible(1, false);
+        assert_eq!(
+            voxel_manager.indexed_voxels(),
+            vec![([0, 0, 0], 0), ([1, 0, 0], 0)]
+        );
+        assert_eq!(voxel_manager.instances().len(), 2);
+        let hit = voxel_manager.raycast(&ray([10.0, 0.5, 0.5], [-10.0, 0.5, 0.5]));
+        assert_eq!(hit.map(|h| h.voxel), Some([1, 0, 0]));
+
+        // Edits of a hidden layer show up once it's visible again
+        voxel_manager.clear(2, 0, 0);
+        assert_eq!(voxel_manager.get_index(2, 0, 0), None);
+        voxel_manager.set_layer_visible(1, true);
+        assert_eq!(voxel_manager.get_index(1, 0, 0), Some(1));
+        assert_eq!(voxel_manager.get_index(2, 0, 0), None);
+
+        // The active layer moves with the layer
+        voxel_manager.move_layer(1, 0);
+        assert_eq!(voxel_manager.active_layer(), 0);
+        assert_eq!(voxel_manager.get_index(1, 0, 0), Some(0));
+        voxel_manager.clear(1, 0, 0);
+        assert_eq!(voxel_manager.get_index(1, 0, 0), Some(0));
+
+        let removed = voxel_manager.remove_layer(1);
+        assert_eq!(removed.name, "Layer 1");
+        assert_eq!(voxel_manager.voxels(), vec![]);
+        voxel_manager.insert_layer(1, removed);
+        assert_eq!(voxel_manager.active_layer(), 1);
+        assert_eq!(voxel_manager.indexed_voxels().len(), 2);
+    }
+
+    #[test]
+    fn resize_keeps_layers() {
+        let mut voxel_manager = VoxelManager::new([4, 4, 4]);
+        voxel_manager.set(3, 0, 0, [1.0; 4]);
+        voxel_manager.add_layer();
+        voxel_manager.set_layer_visible(1, false);
+        voxel_manager.set_layer_locked(1, true);
+        voxel_manager.set(0, 0, 0, [1.0; 4]);
+
+        assert_eq!(
+            voxel_manager.cropped_voxels([2, 4, 4], [-1, 0, 0]),
+            vec![(0, [3, 0, 0], 0), (1, [0, 0, 0], 0)]
+        );
+        voxel_manager.resize([2, 4, 4], [-2, 0, 0]);
+        assert_eq!(voxel_manager.active_layer(), 1);
+        assert!(voxel_manager.layers()[1].locked);
+        assert!(!voxel_manager.layers()[1].visible);
+        assert_eq!(voxel_manager.layers()[0].voxels(), vec![([1, 0, 0], 0)]);
+        assert_eq!(voxel_manager.layers()[1].voxels(), vec![]);
+    }
+
+    /// A model without any symmetry, so every rotation and flip changes it
+    fn chiral_model() -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new([4; 3]);
+        voxel_manager.set_palette(vec![[1.0; 4], [0.5, 0.5, 0.5, 1.0]]);
+        for &(pos, idx) in [
+            ([1, 1, 1], 0),
+            ([2, 1, 1], 0),
+            ([3, 1, 1], 1),
+            ([1, 2, 1], 0),
+            ([1, 1, 2], 1),
+        ]
+        .iter()
+        {
+            voxel_manager.set_index(pos[0], pos[1], pos[2], idx);
+        }
+        voxel_manager
+    }
+
+    fn transformed(voxel_manager: &VoxelManager, transform: VoxelTransform) -> VoxelManager {
+        let mut transformed = voxel_manager.clone();
+        transformed.transform(transform, None, GridBounds::Clip);
+        transformed
+    }
+
+    fn rotate(axis: usize, turns: u8) -> VoxelTransform {
+        VoxelTransform::Rotate { axis, turns }
+    }
+
+    /// Checks the neighbour count of every filled cell against the filled cells around it
+    fn assert_neighbours(voxel_manager: &VoxelManager) {
+        for ([x, y, z], cell) in voxel_manager.filled_cells() {
+            let filled = voxel_manager
+                .get_neighbour_indices(x, y, z)
+                .iter()
+                .filter(|&&[nx, ny, nz]| voxel_manager.get_index(nx, ny, nz).is_some())
+                .count();
+            assert_eq!(cell.neighbours as usize, filled, "at {:?}", [x, y, z]);
+        }
+    }
+
+    #[test]
+    fn quarter_turns_cycle() {
+        let model = chiral_model();
+        for axis in 0..3 {
+            let mut turned = model.clone();
+            for turns in 1..=4 {
+                turned = transformed(&turned, rotate(axis, 1));
+                assert_neighbours(&turned);
+                assert_eq!(
+                    turned == model,
+                    turns == 4,
+                    "{} turns around {}",
+                    turns,
+                    axis
+                );
+                assert_eq!(turned, transformed(&model, rotate(axis, turns)));
+            }
+            assert_eq!(transformed(&model, rotate(axis, 0)), model);
+        }
+    }
+
+    #[test]
+    fn rotation_group() {
+        let model = chiral_model();
+        // Every orientation reachable by quarter turns
+        let mut orientations = vec![model.clone()];
+        let mut next = 0;
+        while next < orientations.len() {
+            for axis in 0..3 {
+                let turned = transformed(&orientations[next], rotate(axis, 1));
+                if !orientations.contains(&turned) {
+                    orientations.push(turned);
                 }
             }
+            next += 1;
         }
-        (erase_box, draw_box)
-    }
-
-    pub fn vertices(&self) -> (Vec<VoxelVertex>, Vec<u32>) {
-        let mut vertex_data = Vec::new();
-        let mut index_data = Vec::new();
-        let mut step;
-        let mut idx;
-        let mut bbox;
-        for x in 0..self.extent {
-            for y in 0..self.extent {
-                for z in 0..self.extent {
-                    if let Some(color) = self.boxes[x][y][z].color {
-                        idx = vertex_data.len() as u32;
-                        for i in 0..6 {
-                            step = 4 * i;
-                            index_data.extend_from_slice(&[
-                                idx + step,
-                                idx + 1 + step,
-                                idx + 2 + step,
-                                idx + 2 + step,
-                                idx + 3 + step,
-                                idx + step,
-                            ]);
-                        }
-                        bbox = BoundingBox::new(
-                            cgmath::Vector3::new(x as f32, y as f32, z as f32),
-                            cgmath::Vector3::new(1.0, 1.0, 1.0),
-                            color,
-                        );
-                        vertex_data.append(&mut bbox.voxel_vertices());
-                    }
+        assert_eq!(orientations.len(), 24);
+
+        // Every rotation has an inverse in the group
+        for orientation in orientations.iter() {
+            for axis in 0..3 {
+                for turns in 1..4 {
+                    let turned = transformed(orientation, rotate(axis, turns));
+                    assert!(orientations.contains(&turned));
+                    assert_eq!(&transformed(&turned, rotate(axis, 4 - turns)), orientation);
                 }
             }
         }
-        (vertex_data, index_data)
+
+        // Half turns around two axes are a half turn around the third one
+        for axis in 0..3 {
+            let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+            let turned = transformed(&transformed(&model, rotate(a, 2)), rotate(b, 2));
+            assert_eq!(turned, transformed(&model, rotate(axis, 2)));
+        }
     }
 
-    pub fn instance_data(&self) -> Vec<VoxelInstance> {
-        let mut instance_data = Vec::new();
-        for x in 0..self.extent {
-            for y in 0..self.extent {
-                for z in 0..self.extent {
-                    if let Some(color) = self.boxes[x][y][z].color {
-                        if self.boxes[x][y][z].visible() {
-                            instance_data.push(instance([x as f32, y as f32, z as f32], color));
-                        }
-                    }
+    #[test]
+    fn flips_mirror_the_rotations() {
+        let model = chiral_model();
+        for axis in 0..3 {
+            let flipped = transformed(&model, VoxelTransform::Flip(axis));
+            assert_neighbours(&flipped);
+            assert_ne!(flipped, model);
+            assert_eq!(transformed(&flipped, VoxelTransform::Flip(axis)), model);
+            // A mirrored chiral model can't be turned back
+            for turn_axis in 0..3 {
+                for turns in 0..4 {
+                    assert_ne!(transformed(&flipped, rotate(turn_axis, turns)), model);
                 }
             }
+            // Flipping on the two other axes is a half turn
+            let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+            let twice = transformed(
+                &transformed(&model, VoxelTransform::Flip(a)),
+                VoxelTransform::Flip(b),
+            );
+            assert_eq!(twice, transformed(&model, rotate(axis, 2)));
+        }
+    }
+
+    #[test]
+    fn translate_wraps_or_clips() {
+        let model = chiral_model();
+        let wrapped = transformed(
+            &model,
+            VoxelTransform::Translate {
+                offset: [2, 0, -1],
+                wrap: true,
+            },
+        );
+        assert_eq!(
+            wrapped.indexed_voxels(),
+            vec![
+                ([0, 1, 0], 0),
+                ([1, 1, 0], 1),
+                ([3, 1, 0], 0),
+                ([3, 1, 1], 1),
+                ([3, 2, 0], 0)
+            ]
+        );
+        assert_neighbours(&wrapped);
+        let back = transformed(
+            &wrapped,
+            VoxelTransform::Translate {
+                offset: [-2, 0, 1],
+                wrap: true,
+            },
+        );
+        assert_eq!(back, model);
+
+        let moved = VoxelTransform::Translate {
+            offset: [1, 0, 0],
+            wrap: false,
+        };
+        let clipped = transformed(&model, moved);
+        assert_eq!(clipped.indexed_voxels().len(), 4);
+        assert_eq!(clipped.get_index(3, 1, 1), Some(0));
+        assert_eq!(
+            model.transformed_box(moved, None, GridBounds::Clip),
+            Some(([1, 0, 0], [3, 4, 4]))
+        );
+
+        let mut grown = model.clone();
+        assert_eq!(
+            grown.transformed_box(moved, None, GridBounds::Grow),
+            Some(([1, 0, 0], [4, 4, 4]))
+        );
+        grown.transform(moved, None, GridBounds::Grow);
+        assert_eq!(grown.extent(), [5, 4, 4]);
+        assert_eq!(grown.indexed_voxels().len(), 5);
+        assert_eq!(grown.get_index(4, 1, 1), Some(1));
+    }
+
+    #[test]
+    fn transform_region() {
+        let mut voxel_manager = VoxelManager::new([6, 4, 4]);
+        voxel_manager.set_palette(vec![[1.0; 4]]);
+        for x in 0..3 {
+            voxel_manager.set_index(x, 0, 0, 0);
         }
-        instance_data
+        voxel_manager.set_index(5, 0, 0, 0);
+        let region = Some(([0, 0, 0], [3, 1, 1]));
+        let turn = rotate(2, 1);
+
+        // The bar turns around its middle cell, half of it ends up below the grid
+        assert_eq!(
+            voxel_manager.transformed_box(turn, region, GridBounds::Clip),
+            Some(([1, 0, 0], [1, 2, 1]))
+        );
+        let mut clipped = voxel_manager.clone();
+        clipped.transform(turn, region, GridBounds::Clip);
+        assert_eq!(
+            clipped.indexed_voxels(),
+            vec![([1, 0, 0], 0), ([1, 1, 0], 0), ([5, 0, 0], 0)]
+        );
+
+        let mut grown = voxel_manager.clone();
+        assert_eq!(
+            grown.transformed_box(turn, region, GridBounds::Grow),
+            Some(([1, 0, 0], [1, 3, 1]))
+        );
+        grown.transform(turn, region, GridBounds::Grow);
+        assert_eq!(grown.extent(), [6, 5, 4]);
+        assert_eq!(
+            grown.indexed_voxels(),
+            vec![
+                ([1, 0, 0], 0),
+                ([1, 1, 0], 0),
+                ([1, 2, 0], 0),
+                ([5, 1, 0], 0)
+            ]
+        );
+        assert_neighbours(&grown);
+
+        // Only the active layer is transformed
+        voxel_manager.add_layer();
+        voxel_manager.transform(VoxelTransform::Flip(0), region, GridBounds::Clip);
+        assert_eq!(voxel_manager.indexed_voxels().len(), 4);
+        assert_eq!(voxel_manager.get_index(0, 0, 0), Some(0));
+    }
+
+    #[test]
+    fn transform_skips_locked_layers() {
+        let mut voxel_manager = chiral_model();
+        voxel_manager.add_layer();
+        voxel_manager.set_index(0, 0, 0, 1);
+        voxel_manager.set_layer_locked(0, true);
+        voxel_manager.transform(VoxelTransform::Flip(0), None, GridBounds::Clip);
+        assert_eq!(
+            voxel_manager.layers()[0].voxels(),
+            chiral_model().layers()[0].voxels()
+        );
+        assert_eq!(voxel_manager.layers()[1].voxels(), vec![([3, 0, 0], 1)]);
+        assert_neighbours(&voxel_manager);
+    }
+
+    /// A solid cube of 5 cells with a cell of space around it
+    fn floating_cube() -> VoxelManager {
+        let mut voxel_manager = VoxelManager::new([7; 3]);
+        let idx = voxel_manager.color_index([1.0; 4]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(1.0, 1.0, 1.0),
+                Vector3::new(5.0, 5.0, 5.0),
+                [1.0; 4],
+            ),
+            idx,
+        );
+        voxel_manager
+    }
+
+    #[test]
+    fn hollow_keeps_shell() {
+        let mut voxel_manager = floating_cube();
+        // The inner cube of 3 cells is more than a cell below the surface
+        let edits = voxel_manager.hollow(1);
+        assert_eq!(edits.len(), 27);
+        assert!(edits
+            .iter()
+            .all(|(pos, idx)| { idx.is_none() && pos.iter().all(|p| (2..5).contains(p)) }));
+        assert_eq!(voxel_manager.hollow(2), vec![([3, 3, 3], None)]);
+        assert!(voxel_manager.hollow(3).is_empty());
+        assert!(voxel_manager.hollow(0).is_empty());
+
+        voxel_manager.set_cells(&edits);
+        assert_eq!(voxel_manager.indexed_voxels().len(), 98);
+        assert_neighbours(&voxel_manager);
+        // The walls of the cavity are surface too
+        assert!(voxel_manager.hollow(1).is_empty());
+
+        // Only the voxels of the active layer are removed
+        let mut layered = floating_cube();
+        layered.add_layer();
+        layered.set_index(3, 3, 3, 0);
+        assert_eq!(layered.hollow(1), vec![([3, 3, 3], None)]);
+    }
+
+    #[test]
+    fn thicken_grows_inward() {
+        let solid = floating_cube();
+        let mut voxel_manager = solid.clone();
+        voxel_manager.set_cells(&voxel_manager.hollow(1));
+        let edits = voxel_manager.thicken(1);
+        assert_eq!(edits.len(), 26);
+        assert!(!edits.iter().any(|(pos, _)| *pos == [3, 3, 3]));
+        assert!(edits.iter().all(|(_, idx)| *idx == Some(0)));
+
+        // Hollowing and thickening back gives the solid cube
+        voxel_manager.set_cells(&voxel_manager.thicken(2));
+        assert_eq!(voxel_manager.indexed_voxels(), solid.indexed_voxels());
+        assert_neighbours(&voxel_manager);
+        assert!(voxel_manager.thicken(1).is_empty());
+
+        // An opening connects the cavity to the outside
+        let mut open = solid.clone();
+        open.set_cells(&open.hollow(1));
+        open.clear(3, 3, 1);
+        assert!(open.thicken(3).is_empty());
+
+        // Far from the border of the grid only a margin around the model is flooded
+        let mut large = VoxelManager::new([100; 3]);
+        let cells: Vec<_> = voxel_manager
+            .indexed_voxels()
+            .into_iter()
+            .map(|([x, y, z], idx)| ([x + 40, y + 40, z + 40], Some(idx)))
+            .collect();
+        large.set_palette(voxel_manager.palette().to_vec());
+        large.set_cells(&cells);
+        large.set_cells(&large.hollow(1));
+        assert_eq!(large.thicken(1).len(), 26);
+        large.clear(43, 43, 41);
+        assert!(large.thicken(3).is_empty());
+    }
+
+    #[test]
+    fn erode_then_dilate_solid_box() {
+        let mut voxel_manager = floating_cube();
+        let eroded = voxel_manager.filter(VoxelFilter::Erode, None);
+        assert_eq!(eroded.len(), 125 - 27);
+        assert!(voxel_manager.filter(VoxelFilter::Open, None).is_empty());
+
+        voxel_manager.set_cells(&eroded);
+        let dilated = voxel_manager.filter(VoxelFilter::Dilate, None);
+        assert_eq!(dilated.len(), 125 - 27);
+        voxel_manager.set_cells(&dilated);
+        assert_eq!(
+            voxel_manager.indexed_voxels(),
+            floating_cube().indexed_voxels()
+        );
+        assert_neighbours(&voxel_manager);
+
+        // The cells outside of the canvas are empty
+        let block = solid_block(4, 4.0);
+        assert_eq!(block.filter(VoxelFilter::Erode, None).len(), 64 - 8);
+        assert!(block.filter(VoxelFilter::Open, None).is_empty());
+        assert!(block.filter(VoxelFilter::Close, None).is_empty());
+    }
+
+    #[test]
+    fn dilate_with_nearest_color() {
+        let mut voxel_manager = VoxelManager::new([5, 3, 3]);
+        voxel_manager.set_palette(vec![[1.0; 4], [0.5; 4]]);
+        voxel_manager.set_index(1, 1, 1, 0);
+        voxel_manager.set_index(3, 1, 1, 1);
+        let dilated = voxel_manager.filter(VoxelFilter::Dilate, None);
+        assert_eq!(dilated.len(), 45 - 2);
+        assert!(dilated.contains(&([0, 0, 0], Some(0))));
+        assert!(dilated.contains(&([4, 2, 2], Some(1))));
+        // A face neighbour is nearer than a diagonal one
+        assert!(dilated.contains(&([2, 0, 1], Some(0))));
+        voxel_manager.set_index(2, 0, 0, 1);
+        let dilated = voxel_manager.filter(VoxelFilter::Dilate, None);
+        assert!(dilated.contains(&([2, 0, 1], Some(1))));
+
+        // Only the cells of the region are filled
+        let region = Some(([0, 0, 0], [1, 3, 3]));
+        let dilated = voxel_manager.filter(VoxelFilter::Dilate, region);
+        assert_eq!(dilated.len(), 9);
+        assert!(dilated.iter().all(|(pos, _)| pos[0] == 0));
+    }
+
+    #[test]
+    fn close_fills_gaps() {
+        let mut voxel_manager = floating_cube();
+        voxel_manager.clear(3, 3, 3);
+        voxel_manager.clear(3, 3, 5);
+        let closed = voxel_manager.filter(VoxelFilter::Close, None);
+        assert_eq!(closed, vec![([3, 3, 3], Some(0)), ([3, 3, 5], Some(0))]);
+        // Opening removes the voxels sticking out
+        voxel_manager.set_cells(&closed);
+        voxel_manager.set_index(3, 3, 6, 0);
+        let opened = voxel_manager.filter(VoxelFilter::Open, None);
+        assert_eq!(opened, vec![([3, 3, 6], None)]);
+    }
+
+    #[test]
+    fn smooth_majority() {
+        let mut voxel_manager = VoxelManager::new([8; 3]);
+        let idx = voxel_manager.color_index([1.0; 4]);
+        voxel_manager.add_box(
+            BoundingBox::new(
+                Vector3::new(1.0, 1.0, 1.0),
+                Vector3::new(5.0, 5.0, 5.0),
+                [1.0; 4],
+            ),
+            idx,
+        );
+        // A bump and a pit on the top face
+        voxel_manager.set_index(2, 2, 6, idx);
+        voxel_manager.clear(4, 4, 5);
+        let top = Some(([2, 2, 5], [3, 3, 2]));
+        assert_eq!(
+            voxel_manager.filter(VoxelFilter::Smooth, top),
+            vec![([2, 2, 6], None), ([4, 4, 5], Some(idx))]
+        );
+        // The edges of the box are rounded off
+        let smoothed = voxel_manager.filter(VoxelFilter::Smooth, None);
+        assert!(smoothed.contains(&([1, 1, 3], None)));
+        assert!(!smoothed.iter().any(|(pos, _)| *pos == [1, 3, 3]));
+    }
+
+    #[test]
+    fn boolean_identities() {
+        let a = vec![([0, 0, 0], 0), ([1, 0, 0], 0), ([1, 1, 0], 0)];
+        let b = vec![([1, 0, 0], 1), ([2, 0, 0], 1)];
+        let combine = |op: BooleanOp, target: &[_], source: &[_]| {
+            op.combine(target, source, OverlapColor::Target)
+        };
+        assert!(combine(BooleanOp::Subtract, &a, &a).is_empty());
+        assert!(combine(BooleanOp::Xor, &a, &a).is_empty());
+        assert_eq!(combine(BooleanOp::Union, &a, &a), a);
+        assert_eq!(combine(BooleanOp::Intersect, &a, &a), a);
+        assert_eq!(combine(BooleanOp::Union, &a, &[]), a);
+        assert_eq!(combine(BooleanOp::Subtract, &a, &[]), a);
+        assert!(combine(BooleanOp::Intersect, &a, &[]).is_empty());
+        assert!(combine(BooleanOp::Subtract, &[], &a).is_empty());
+
+        assert_eq!(
+            combine(BooleanOp::Subtract, &a, &b),
+            vec![([0, 0, 0], 0), ([1, 1, 0], 0)]
+        );
+        assert_eq!(combine(BooleanOp::Intersect, &a, &b), vec![([1, 0, 0], 0)]);
+        assert_eq!(
+            combine(BooleanOp::Xor, &a, &b),
+            vec![([0, 0, 0], 0), ([1, 1, 0], 0), ([2, 0, 0], 1)]
+        );
+        // The parts outside and inside of the source make up the target
+        let parts = combine(
+            BooleanOp::Union,
+            &combine(BooleanOp::Subtract, &a, &b),
+            &combine(BooleanOp::Intersect, &a, &b),
+        );
+        assert_eq!(parts, a);
+
+        // The overlapping cell gets the color of the target or of the source
+        let union = combine(BooleanOp::Union, &a, &b);
+        assert_eq!(union.len(), 4);
+        assert!(union.contains(&([1, 0, 0], 0)));
+        let union = BooleanOp::Union.combine(&a, &b, OverlapColor::Source);
+        assert!(union.contains(&([1, 0, 0], 1)));
+        assert_eq!(combine(BooleanOp::Union, &b, &a), union);
+    }
+
+    #[test]
+    fn combine_layers() {
+        let mut voxel_manager = chiral_model();
+        voxel_manager.add_layer();
+        voxel_manager.set_index(1, 1, 1, 1);
+        voxel_manager.set_index(3, 3, 3, 1);
+        let target = voxel_manager.layers()[1].voxels();
+        let source = voxel_manager.layers()[0].voxels();
+        let combined = BooleanOp::Subtract.combine(&target, &source, OverlapColor::Target);
+        assert_eq!(combined, vec![([3, 3, 3], 1)]);
+
+        let edits = voxel_manager.layer_edits(&combined);
+        assert_eq!(edits, vec![([1, 1, 1], None)]);
+        voxel_manager.set_cells(&edits);
+        assert_eq!(voxel_manager.layers()[1].voxels(), combined);
+        assert!(voxel_manager.layer_edits(&combined).is_empty());
+        assert_neighbours(&voxel_manager);
     }
 }